@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE sensors;
+ALTER TABLE devices DROP COLUMN device_type;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE devices ADD COLUMN device_type INTEGER NOT NULL DEFAULT 16;
+
+CREATE TABLE sensors (
+    id SERIAL PRIMARY KEY NOT NULL,
+    device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    device_position INTEGER NOT NULL,
+    occupied BOOLEAN NOT NULL DEFAULT FALSE,
+    changed_at TIMESTAMP
+);
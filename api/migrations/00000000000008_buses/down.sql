@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE devices DROP CONSTRAINT devices_bus_adr_key;
+DELETE FROM devices WHERE bus <> (SELECT MIN(bus) FROM devices);
+ALTER TABLE devices ADD CONSTRAINT devices_adr_key UNIQUE (adr);
+ALTER TABLE devices DROP COLUMN bus;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- existing devices were discovered on the single configured bus, usually /dev/i2c-1
+ALTER TABLE devices ADD COLUMN bus INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE devices DROP CONSTRAINT devices_adr_key;
+ALTER TABLE devices ADD CONSTRAINT devices_bus_adr_key UNIQUE (bus, adr);
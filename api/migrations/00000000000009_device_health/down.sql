@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE devices
+    DROP COLUMN online,
+    DROP COLUMN last_seen,
+    DROP COLUMN failure_count,
+    DROP COLUMN last_error;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE devices
+    ADD COLUMN online BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN last_seen TIMESTAMP,
+    ADD COLUMN failure_count INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN last_error TEXT;
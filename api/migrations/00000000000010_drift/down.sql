@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE devices
+    DROP COLUMN drift_count,
+    DROP COLUMN last_drift;
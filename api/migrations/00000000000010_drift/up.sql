@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE devices
+    ADD COLUMN drift_count INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN last_drift TIMESTAMP;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE devices
+    DROP COLUMN protocol_version,
+    DROP COLUMN features,
+    DROP COLUMN firmware_version;
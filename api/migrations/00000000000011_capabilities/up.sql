@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE devices
+    ADD COLUMN protocol_version INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN features INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN firmware_version TEXT;
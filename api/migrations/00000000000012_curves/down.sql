@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE points
+    DROP COLUMN curve,
+    DROP COLUMN curve_gamma,
+    DROP COLUMN curve_table;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE points
+    ADD COLUMN curve TEXT NOT NULL DEFAULT 'linear',
+    ADD COLUMN curve_gamma REAL NOT NULL DEFAULT 2.2,
+    ADD COLUMN curve_table INTEGER[];
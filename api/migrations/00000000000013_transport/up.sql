@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE devices ADD COLUMN transport TEXT NOT NULL DEFAULT 'i2c';
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM devices WHERE transport IN ('artnet', 'sacn');
+ALTER TABLE devices
+    DROP COLUMN universe,
+    DROP COLUMN dmx_channel;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE devices
+    ADD COLUMN universe INTEGER,
+    ADD COLUMN dmx_channel INTEGER;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE devices
+    DROP COLUMN max_watts;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE devices
+    ADD COLUMN max_watts REAL;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE energy (
+    id SERIAL PRIMARY KEY NOT NULL,
+    point_id INTEGER NOT NULL REFERENCES points(id) ON DELETE CASCADE,
+    started_at TIMESTAMP NOT NULL,
+    sampled_at TIMESTAMP NOT NULL,
+    wh DOUBLE PRECISION NOT NULL
+);
+CREATE INDEX energy_sampled_at ON energy (sampled_at);
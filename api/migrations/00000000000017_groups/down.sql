@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE point_group_items;
+DROP TABLE point_groups;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE point_groups (
+    id SERIAL PRIMARY KEY NOT NULL,
+    group_name TEXT NOT NULL,
+    kind TEXT NOT NULL DEFAULT 'group' CHECK (kind IN ('room', 'group')),
+    icon TEXT
+);
+
+CREATE TABLE point_group_items (
+    id SERIAL PRIMARY KEY NOT NULL,
+    group_id INTEGER NOT NULL REFERENCES point_groups(id) ON DELETE CASCADE,
+    point_id INTEGER NOT NULL REFERENCES points(id) ON DELETE CASCADE,
+    UNIQUE (group_id, point_id)
+);
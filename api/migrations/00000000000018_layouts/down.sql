@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE points
+    DROP COLUMN layout_id;
+DROP TABLE layouts;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE layouts (
+    id SERIAL PRIMARY KEY NOT NULL,
+    layout_name TEXT NOT NULL,
+    -- floors are listed by it, lowest first
+    floor INTEGER NOT NULL DEFAULT 0,
+    width REAL NOT NULL CHECK (width > 0),
+    height REAL NOT NULL CHECK (height > 0),
+    background TEXT
+);
+
+ALTER TABLE points
+    ADD COLUMN layout_id INTEGER REFERENCES layouts(id) ON DELETE SET NULL;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM energy WHERE point_id IS NULL;
+ALTER TABLE energy
+    ALTER COLUMN point_id SET NOT NULL,
+    DROP CONSTRAINT energy_point_id_fkey,
+    ADD CONSTRAINT energy_point_id_fkey FOREIGN KEY (point_id) REFERENCES points(id) ON DELETE CASCADE;
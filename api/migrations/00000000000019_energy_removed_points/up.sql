@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- energy of removed points is kept without a point
+ALTER TABLE energy
+    ALTER COLUMN point_id DROP NOT NULL,
+    DROP CONSTRAINT energy_point_id_fkey,
+    ADD CONSTRAINT energy_point_id_fkey FOREIGN KEY (point_id) REFERENCES points(id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE scan_entries;
+DROP TABLE scan_reports;
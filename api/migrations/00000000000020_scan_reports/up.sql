@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- last discovery of every bus, kept so unknown chips and fixed devices are not probed again after a restart
+CREATE TABLE scan_reports (
+    bus INTEGER PRIMARY KEY NOT NULL,
+    scanned_at TIMESTAMP NOT NULL,
+    addresses INTEGER NOT NULL
+);
+
+CREATE TABLE scan_entries (
+    id SERIAL PRIMARY KEY NOT NULL,
+    bus INTEGER NOT NULL REFERENCES scan_reports(bus) ON DELETE CASCADE,
+    adr INTEGER NOT NULL,
+    identifier INTEGER NOT NULL,
+    known BOOLEAN NOT NULL,
+    endpoint_count INTEGER NOT NULL DEFAULT 0,
+    probed BOOLEAN NOT NULL,
+    UNIQUE (bus, adr)
+);
@@ -1,10 +1,18 @@
 pub mod helpers;
 mod auth;
 mod devices;
+mod energy;
+mod groups;
+mod layouts;
 mod points;
 mod presets;
+mod sensors;
 mod setup;
 
+use crate::calls::{
+    ErrMessage,
+    FieldError,
+};
 use crate::middleware::auth::TokenFactory;
 use actix_web::{
     web,
@@ -16,7 +24,6 @@ use actix_web::{
         StatusCode,
     },
 };
-use serde::Serialize;
 use derive_more::Display;
 use serde_json;
 
@@ -24,6 +31,14 @@ use serde_json;
 
 pub fn expose_api() -> Scope {
     web::scope("/api")
+        .app_data(web::JsonConfig::default().error_handler(|err, _| {
+            log::debug!("Rejected request body: {}", err);
+            ApiError::invalid("body", "format").into()
+        }))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| {
+            log::debug!("Rejected request query: {}", err);
+            ApiError::invalid("query", "format").into()
+        }))
         .route("", web::get().to(|| async { "OK" }))
         .service(
             web::scope("/step")
@@ -50,6 +65,49 @@ pub fn expose_api() -> Scope {
                 .route(web::get().to(self::devices::get))
                 .route(web::post().to(self::devices::post))
             )
+            .service(
+                web::resource("/stats")
+                .route(web::get().to(self::devices::stats))
+            )
+            .service(
+                web::resource("/scan")
+                .route(web::get().to(self::devices::scan))
+                .route(web::delete().to(self::devices::scan_delete))
+            )
+            .service(
+                web::resource("/power")
+                .route(web::get().to(self::devices::power::get))
+            )
+            .service(
+                web::resource("/dmx")
+                .route(web::post().to(self::devices::dmx::post))
+            )
+            .service(
+                web::resource("/{device}/dmx")
+                .route(web::put().to(self::devices::dmx::put))
+                .route(web::delete().to(self::devices::dmx::delete))
+            )
+            .service(
+                web::resource("/{device}/power")
+                .route(web::put().to(self::devices::power::put))
+            )
+            .service(
+                web::resource("/{device}/address")
+                .route(web::put().to(self::devices::address::put))
+            )
+            .service(
+                web::resource("/{device}/settings")
+                .route(web::get().to(self::devices::settings::get))
+                .route(web::put().to(self::devices::settings::put))
+            )
+        )
+        .service(
+            web::scope("/sensors")
+            .wrap(TokenFactory::new())
+            .service(
+                web::resource("")
+                .route(web::get().to(self::sensors::get))
+            )
         )
         .service(
             web::scope("/points")
@@ -58,11 +116,16 @@ pub fn expose_api() -> Scope {
                 web::resource("")
                 .route(web::get().to(self::points::get))
                 .route(web::put().to(self::points::put))
+                .route(web::patch().to(self::points::patch))
             )
             .service(
                 web::resource("/identify")
                 .route(web::post().to(self::points::identify::post))
             )
+            .service(
+                web::resource("/spatial")
+                .route(web::put().to(self::points::spatial::put))
+            )
             .service(
                 web::resource("/single/{point}")
                 .route(web::get().to(self::points::single::get))
@@ -89,11 +152,61 @@ pub fn expose_api() -> Scope {
                 .route(web::put().to(self::presets::points::put))
             )
         )
+        .service(
+            web::scope("/layouts")
+            .wrap(TokenFactory::new())
+            .service(
+                web::resource("")
+                .route(web::get().to(self::layouts::get))
+                .route(web::post().to(self::layouts::post))
+            )
+            .service(
+                web::resource("/{layout}")
+                .route(web::put().to(self::layouts::put))
+                .route(web::delete().to(self::layouts::del))
+            )
+        )
+        .service(
+            web::scope("/groups")
+            .wrap(TokenFactory::new())
+            .service(
+                web::resource("")
+                .route(web::get().to(self::groups::get))
+                .route(web::post().to(self::groups::post))
+            )
+            .service(
+                web::resource("/{group}")
+                .route(web::put().to(self::groups::put))
+                .route(web::delete().to(self::groups::del))
+            )
+            .service(
+                web::resource("/{group}/command")
+                .route(web::put().to(self::groups::command))
+            )
+        )
+        .service(
+            web::scope("/energy")
+            .wrap(TokenFactory::new())
+            .service(
+                web::resource("/points")
+                .route(web::get().to(self::energy::points))
+            )
+            .service(
+                web::resource("/devices")
+                .route(web::get().to(self::energy::devices))
+            )
+            .service(
+                web::resource("/tags")
+                .route(web::get().to(self::energy::tags))
+            )
+        )
 }
 
 #[derive(Debug, Display)]
 pub enum ApiError {
-    BadRequest,
+    /// bad request with every rule the input failed
+    #[display(fmt = "BadRequest")]
+    Invalid(Vec<FieldError>),
     Unauthorized,
     Forbidden,
     NotFound,
@@ -104,20 +217,37 @@ pub enum ApiError {
     Unavailable,
 }
 
+impl ApiError {
+    /// Bad request failing one rule without limits
+    pub fn invalid(field: &str, rule: &'static str) -> Self {
+        ApiError::Invalid(vec![FieldError {
+            field: field.to_string(),
+            rule,
+            min: None,
+            max: None,
+            allowed: None,
+        }])
+    }
+}
+
 impl ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse {
-        #[derive(Debug, Serialize)]
-        struct ErrMessage {
-            pub message: String,
-        }
+        let (code, errors) = match self {
+            ApiError::Invalid(v) => (Some("validation"), v.clone()),
+            _ => (None, vec![]),
+        };
         HttpResponse::build(self.status_code())
         .insert_header(ContentType::json())
-        .body(serde_json::to_string(&ErrMessage { message: self.to_string() }).unwrap())
+        .body(serde_json::to_string(&ErrMessage {
+            code,
+            message: Some(self.to_string()),
+            errors,
+        }).unwrap())
     }
 
     fn status_code(&self) -> StatusCode {
         match self {
-            ApiError::BadRequest => StatusCode::BAD_REQUEST,
+            ApiError::Invalid(_) => StatusCode::BAD_REQUEST,
             ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
             ApiError::Forbidden => StatusCode::FORBIDDEN,
             ApiError::NotFound => StatusCode::NOT_FOUND,
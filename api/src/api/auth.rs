@@ -1,4 +1,5 @@
 use crate::api::ApiError;
+use crate::api::helpers::validation::Validator;
 use crate::calls::*;
 use crate::types::{
     Tokens,
@@ -37,6 +38,11 @@ pub async fn post(
     auth_req: web::Json<AuthReq>,
     rq: HttpRequest
 ) -> Result<web::Json<AuthRes>, ApiError> {
+    Validator::new()
+    .length("user_name", auth_req.user_name.chars().count(), 1, None)
+    .length("password", auth_req.password.chars().count(), 1, None)
+    .finish()?;
+
     // wowie, thats a lot of work just for one header...
     let ua = rq.headers().get("user-agent")
     .ok_or(ApiError::InternalErr)
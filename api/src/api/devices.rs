@@ -1,9 +1,21 @@
+pub mod address;
+pub mod dmx;
+pub mod power;
+pub mod settings;
+
 use crate::api::ApiError;
-use crate::api::helpers::db::points::{
-    fill_diff,
-    reduce_diff,
+use crate::api::helpers::db;
+use crate::api::helpers::frames::{
+    FrameCache,
+    FrameStats,
 };
 use crate::api::helpers::i2c::LightDevices;
+use crate::api::helpers::i2c::scan::ScanReport;
+use crate::api::helpers::props::{
+    DMX_BUS,
+    I2C_LIGHT_CONTROLLER,
+    I2C_PRESENCE_DETECTOR,
+};
 use crate::types::{
     DbPool,
     SharedStorage,
@@ -12,8 +24,12 @@ use crate::models::{
     Devices,
     NewDevices,
     Points,
+    Sensors,
+};
+use actix_web::{
+    web,
+    HttpResponse,
 };
-use actix_web::web;
 use diesel::{
     prelude::*,
     insert_into,
@@ -47,20 +63,81 @@ pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<Devices>>, Api
     Ok(web::Json(device_request))
 }
 
-// updates from i2c and refreshes the 
-pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>) -> Result<web::Json<Vec<Devices>>, ApiError> {
-    let i2cid = *shared_data.i2c_device.clone();
-    let mut controller = LightDevices::new(i2cid)
+// dispatcher write savings since start
+pub async fn stats(frames: web::Data<FrameCache>) -> web::Json<FrameStats> {
+    web::Json(frames.stats())
+}
+
+// last discovery of every configured bus, including chips that are not ours
+pub async fn scan(shared_data: web::Data<SharedStorage>) -> web::Json<Vec<ScanReport>> {
+    let reports = shared_data.buses.iter()
+    .map(|bus_config| bus_config.scan_report.lock().unwrap().clone())
+    .collect();
+    web::Json(reports)
+}
+
+// forgets scan reports, letting next discovery probe unknown chips and fixed devices again
+pub async fn scan_delete(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>) -> Result<HttpResponse, ApiError> {
+    web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::scan::clear(&mut con)
+    })
+    .await
     .map_err(|err| {
-        log::error!("Failed to get i2c driver: {}", err);
-        ApiError::TooManyRequests
-    })?;
-    
-    let detected_devices = controller.controllers()
+        log::error!("Web block for scan report deleting failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+    for bus_config in shared_data.buses.iter() {
+        *bus_config.scan_report.lock().unwrap() = ScanReport {
+            bus: bus_config.id,
+            ..ScanReport::default()
+        };
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+// updates from every configured i2c bus and refreshes the 
+pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>) -> Result<web::Json<Vec<Devices>>, ApiError> {
+    let mut detected_devices: Vec<NewDevices> = vec![];
+    for bus_config in shared_data.buses.iter() {
+        let mut controller = LightDevices::new(bus_config)
+        .map_err(|err| {
+            log::error!("Failed to get i2c driver for bus {}: {}", bus_config.id, err);
+            ApiError::TooManyRequests
+        })?;
+
+        let bus_devices = controller.controllers()
+        .map_err(|err| {
+            log::error!("Failed to fetch i2c controllers on bus {}: {}", bus_config.id, err);
+            ApiError::InternalErr
+        })?;
+        detected_devices.extend(bus_devices);
+    }
+    // scan report block, kept over restarts
+    let pool_scan = pool.clone();
+    let reports = shared_data.buses.iter()
+    .map(|bus_config| bus_config.scan_report.lock().unwrap().clone())
+    .collect::<Vec<ScanReport>>();
+    web::block(move || {
+        let mut con = pool_scan.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool_scan: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::scan::store(&mut con, &reports)
+    })
+    .await
     .map_err(|err| {
-        log::error!("Failed to fetch i2c controllers: {}", err);
+        log::error!("Web block for scan report storing failed with: {}", err);
         ApiError::InternalErr
-    })?;
+    })??;
+
+    // devices on buses missing from config are left untouched
+    let scanned_buses = shared_data.buses.iter().map(|v| v.id).collect::<Vec<i32>>();
 
     use crate::schema::devices::dsl::*;
     let pool_read_devices = pool.clone();
@@ -70,7 +147,7 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
             log::error!("Could not fetch connection from pool_read_devices: {}", err);
             ApiError::InternalErr
         })?;
-        devices.load::<Devices>(&mut con)
+        devices.filter(bus.eq_any(scanned_buses)).load::<Devices>(&mut con)
         .map_err(|err| {
             log::error!("Requesting Data failed: {}", err);
             ApiError::InternalErr
@@ -92,11 +169,16 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
     let mut address_insert: Vec<usize> = vec![];
     let mut no_insert: Vec<usize> = vec!();
     for (db_index, device) in db_devices.iter().enumerate() {
-        match detected_devices.iter().position(|r| r.adr == device.adr) {
+        match detected_devices.iter().position(|r| r.adr == device.adr && r.bus == device.bus) {
             Some(detected_index) => {
                 no_insert.push(detected_index);
                 let matched_device = &detected_devices[detected_index];
-                if matched_device.endpoint_count != device.endpoint_count {
+                if matched_device.endpoint_count != device.endpoint_count
+                    || matched_device.device_type != device.device_type
+                    || matched_device.protocol_version != device.protocol_version
+                    || matched_device.features != device.features
+                    || matched_device.firmware_version != device.firmware_version
+                    || matched_device.transport != device.transport {
                     address_update.push((db_index, detected_index));
                     continue;
                 }
@@ -118,6 +200,7 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
     // db device insert block
     let pool_insert = pool.clone();
     let detected_devs = detected_devices.clone();
+    let detected_devs_health = detected_devices.clone();
     web::block(move || {
         let mut con = pool_insert.get()
         .map_err(|err| {
@@ -153,7 +236,12 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
         let updated_devices = address_update.iter()
         .map(|sec| {
             let mut devc_clone = db_devs[sec.0].clone();
-            devc_clone.endpoint_count = detected_devices[sec.0].endpoint_count.clone();
+            devc_clone.endpoint_count = detected_devices[sec.1].endpoint_count;
+            devc_clone.device_type = detected_devices[sec.1].device_type;
+            devc_clone.protocol_version = detected_devices[sec.1].protocol_version;
+            devc_clone.features = detected_devices[sec.1].features;
+            devc_clone.firmware_version = detected_devices[sec.1].firmware_version.clone();
+            devc_clone.transport = detected_devices[sec.1].transport.clone();
             devc_clone
         })
         .collect::<Vec<Devices>>();
@@ -161,6 +249,11 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
             diesel::update(devices).filter(crate::schema::devices::dsl::id.eq(devc_update.id))
             .set((
                 crate::schema::devices::dsl::endpoint_count.eq(devc_update.endpoint_count),
+                crate::schema::devices::dsl::device_type.eq(devc_update.device_type),
+                crate::schema::devices::dsl::protocol_version.eq(devc_update.protocol_version),
+                crate::schema::devices::dsl::features.eq(devc_update.features),
+                crate::schema::devices::dsl::firmware_version.eq(devc_update.firmware_version),
+                crate::schema::devices::dsl::transport.eq(devc_update.transport),
             ))
             .execute(&mut con)
             .map_err(|err| {
@@ -178,6 +271,7 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
     
     // db device delete block
     let pool_delete = pool.clone();
+    let db_devs_delete = db_devices.clone();
     if address_delete.len() > 0 {
         web::block(move || {
             let mut con = pool_delete.get()
@@ -187,8 +281,8 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
             })?;
             let mut delete_ = delete(devices).into_boxed();
             let mut first = true;
-            for db_id in address_delete {
-                let i32_db_id = db_id as i32;
+            for db_index in address_delete {
+                let i32_db_id = db_devs_delete[db_index].id;
                 if first {
                     first = false;
                     delete_ = delete_.filter(id.eq(i32_db_id));
@@ -209,6 +303,36 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
         })??;
     }
 
+    // detected devices answered the scan
+    let pool_health = pool.clone();
+    let detected_health = detected_devs_health;
+    web::block(move || {
+        let mut con = pool_health.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool_health: {}", err);
+            ApiError::InternalErr
+        })?;
+        let seen_ids = devices.load::<Devices>(&mut con)
+        .map_err(|err| {
+            log::error!("Fetching devices failed: {}", err);
+            ApiError::InternalErr
+        })?
+        .iter()
+        .filter(|devc| detected_health.iter().any(|r| r.adr == devc.adr && r.bus == devc.bus))
+        .map(|devc| devc.id)
+        .collect::<Vec<i32>>();
+        db::devices::mark_seen(&mut con, seen_ids)
+        .map_err(|err| {
+            log::error!("Failed to update device health: {}", err);
+            ApiError::InternalErr
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for device health failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
     // picking up all updates
     let pool_rebase = pool.clone();
     let rebase_db_devices = web::block(move || {
@@ -229,7 +353,7 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
         ApiError::InternalErr
     })??;
 
-    // updating points for devices
+    // updating points and sensors for devices
     let rebase_db_devs = rebase_db_devices.clone();
     let pool_rebase_point = pool.clone();
     web::block(move || {
@@ -239,6 +363,37 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
             ApiError::InternalErr
         })?;
         for devc in rebase_db_devs {
+            if devc.device_type == I2C_PRESENCE_DETECTOR as i32 {
+                use crate::schema::sensors::dsl::*;
+                // points of a device that used to be a light controller are no longer addressable
+                diesel::delete(crate::schema::points::dsl::points.filter(crate::schema::points::dsl::device_id.eq(devc.id)))
+                .execute(&mut con)
+                .map_err(|err| {
+                    log::error!("Failed to clear presence detector points: {}", err);
+                    ApiError::InternalErr
+                })?;
+                let db_sensors = sensors.filter(device_id.eq(devc.id))
+                .load::<Sensors>(&mut con)
+                .map_err(|err| {
+                    log::error!("Fetching sensors failed: {}", err);
+                    ApiError::InternalErr
+                })?;
+                let sensor_count = devc.endpoint_count as usize;
+                let diff = db_sensors.len().abs_diff(sensor_count);
+                if db_sensors.len() < sensor_count {
+                    let max_value = db_sensors.iter().map(|sensor| sensor.device_position).max().unwrap_or(-1);
+                    db::sensors::fill_diff(&mut con, diff, devc.id, max_value)?;
+                } else if db_sensors.len() > sensor_count {
+                    db::sensors::reduce_diff(&mut con, diff, db_sensors)?;
+                }
+                continue;
+            }
+            diesel::delete(crate::schema::sensors::dsl::sensors.filter(crate::schema::sensors::dsl::device_id.eq(devc.id)))
+            .execute(&mut con)
+            .map_err(|err| {
+                log::error!("Failed to clear light controller sensors: {}", err);
+                ApiError::InternalErr
+            })?;
             use crate::schema::points::dsl::*;
             let db_points = points.filter(device_id.eq(devc.id))
             .load::<Points>(&mut con)
@@ -258,12 +413,12 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
                     Some(v) => v,
                     None => -1
                 };
-                fill_diff(&mut con, diff, devc.id, max_value)?;
+                db::points::fill_diff(&mut con, diff, devc.id, max_value)?;
                 continue;
             }
 
             if db_points.len() > point_count {
-                reduce_diff(&mut con, diff, db_points)?;
+                db::points::reduce_diff(&mut con, diff, db_points)?;
             }
         }
         Ok(true)
@@ -275,3 +430,45 @@ pub async fn post(pool: web::Data<DbPool>, shared_data: web::Data<SharedStorage>
     })??;
     Ok(web::Json(rebase_db_devices))
 }
+
+// light controller by id, used by per device endpoints
+async fn fetch_device(pool: web::Data<DbPool>, device_id: i32) -> Result<Devices, ApiError> {
+    let device = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::devices::dsl::*;
+        devices.find(device_id)
+        .first::<Devices>(&mut con)
+        .optional()
+        .map_err(|err| {
+            log::error!("Fetching device [{}] failed: {}", device_id, err);
+            ApiError::InternalErr
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for device fetching failed with: {}", err);
+        ApiError::InternalErr
+    })??
+    .ok_or(ApiError::NotFound)?;
+    // only light controllers on a bus have outputs to configure
+    if device.device_type != I2C_LIGHT_CONTROLLER as i32 || device.bus == DMX_BUS {
+        return Err(ApiError::Conflict);
+    }
+    Ok(device)
+}
+
+fn open_controller(shared_data: &SharedStorage, device: &Devices) -> Result<LightDevices, ApiError> {
+    let bus_config = shared_data.buses.get(device.bus).ok_or_else(|| {
+        log::error!("Device [{}] is on bus {} that is not configured", device.id, device.bus);
+        ApiError::Unavailable
+    })?;
+    LightDevices::new(bus_config)
+    .map_err(|err| {
+        log::error!("Failed to get i2c driver for bus {}: {}", bus_config.id, err);
+        ApiError::Unavailable
+    })
+}
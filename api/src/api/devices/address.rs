@@ -0,0 +1,117 @@
+use super::{
+    fetch_device,
+    open_controller,
+};
+use crate::api::ApiError;
+use crate::api::helpers::batcher::Batcher;
+use crate::api::helpers::frames::FrameCache;
+use crate::api::helpers::i2c::bus::BusError;
+use crate::api::helpers::i2c::capabilities::Capabilities;
+use crate::api::helpers::props::{
+    I2C_FEATURE_ADDRESS_CHANGE,
+    I2C_RANGE_MAX,
+    I2C_RANGE_MIN,
+};
+use crate::api::helpers::validation::Validator;
+use crate::types::{
+    DbPool,
+    SharedStorage,
+};
+use crate::models::{
+    DeviceAddress,
+    Devices,
+};
+use actix_web::web;
+use diesel::prelude::*;
+
+// moves controller to another address on its bus, points follow the device row
+pub async fn put(
+    path: web::Path<i32>,
+    data: web::Json<DeviceAddress>,
+    pool: web::Data<DbPool>,
+    shared_data: web::Data<SharedStorage>,
+    batcher: web::Data<Batcher>,
+    frames: web::Data<FrameCache>,
+) -> Result<web::Json<Devices>, ApiError> {
+    let target = data.adr;
+    Validator::new().range("adr", target, I2C_RANGE_MIN as i32, I2C_RANGE_MAX as i32 - 1).finish()?;
+    let device = fetch_device(pool.clone(), path.into_inner()).await?;
+    if device.adr == target {
+        return Ok(web::Json(device));
+    }
+    if !Capabilities::from(&device).supports(I2C_FEATURE_ADDRESS_CHANGE) {
+        return Err(ApiError::Conflict);
+    }
+
+    let pool_taken = pool.clone();
+    let device_bus = device.bus;
+    let taken = web::block(move || {
+        let mut con = pool_taken.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool_taken: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::devices::dsl::*;
+        devices.filter(bus.eq(device_bus).and(adr.eq(target)))
+        .count()
+        .get_result::<i64>(&mut con)
+        .map_err(|err| {
+            log::error!("Checking address {} on bus {} failed: {}", target, device_bus, err);
+            ApiError::InternalErr
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for address check failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+    if taken > 0 {
+        return Err(ApiError::Conflict);
+    }
+
+    let shared = shared_data.clone();
+    let moved_device = device.clone();
+    web::block(move || {
+        let mut controller = open_controller(&shared, &moved_device)?;
+        // refused as well when something not discovered yet answers there
+        controller.set_address(moved_device.adr as u16, target as u16)
+        .map_err(|err| match err {
+            BusError::InvalidInput => ApiError::Conflict,
+            err => {
+                log::error!("Failed to move device [{}] from {} to {}: {}", moved_device.id, moved_device.adr, target, err);
+                ApiError::Unavailable
+            },
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for address change failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    let result = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::devices::dsl::*;
+        diesel::update(devices.find(device.id))
+        .set(adr.eq(target))
+        .get_result::<Devices>(&mut con)
+        .map_err(|err| {
+            log::error!("Device [{}] moved to {} on bus {} but updating it failed: {}", device.id, target, device.bus, err);
+            ApiError::InternalErr
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for device address update failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    // controller may come up dark on its new address, levels are sent again
+    frames.forget(result.id);
+    batcher.request();
+    Ok(web::Json(result))
+}
@@ -0,0 +1,269 @@
+use crate::api::ApiError;
+use crate::api::helpers::batcher::Batcher;
+use crate::api::helpers::db;
+use crate::api::helpers::dmx::{
+    DmxOutput,
+    DmxPatch,
+};
+use crate::api::helpers::frames::FrameCache;
+use crate::api::helpers::props::{
+    ARTNET_UNIVERSE_MAX,
+    DMX_BUS,
+    DMX_CHANNELS,
+    I2C_LIGHT_CONTROLLER,
+    SACN_UNIVERSE_MAX,
+    SACN_UNIVERSE_MIN,
+};
+use crate::api::helpers::validation::Validator;
+use crate::types::{
+    DbCon,
+    DbPool,
+};
+use crate::models::{
+    Devices,
+    DmxRequest,
+    NewDmxDevices,
+    Points,
+};
+use actix_web::{
+    web,
+    HttpResponse,
+};
+use diesel::prelude::*;
+
+static DMX_TRANSPORTS: &[&str] = &["artnet", "sacn"];
+
+// adds art-net or sacn fixture with one point per 16 bit channel pair
+pub async fn post(
+    data: web::Json<DmxRequest>,
+    pool: web::Data<DbPool>,
+    batcher: web::Data<Batcher>,
+) -> Result<web::Json<Devices>, ApiError> {
+    let patch = valid_patch(&data)?;
+
+    let device = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::transaction(&mut con, |con| {
+            ensure_free(con, &patch, data.endpoint_count, None)?;
+            use crate::schema::devices::dsl::*;
+            let next_adr = devices.select(diesel::dsl::max(adr))
+            .filter(bus.eq(DMX_BUS))
+            .first::<Option<i32>>(con)
+            .map_err(|err| {
+                log::error!("Fetching dmx addresses failed: {}", err);
+                ApiError::InternalErr
+            })?
+            .map_or(0, |v| v + 1);
+            let device = diesel::insert_into(devices)
+            .values(&NewDmxDevices {
+                adr: next_adr,
+                endpoint_count: data.endpoint_count,
+                device_type: I2C_LIGHT_CONTROLLER as i32,
+                bus: DMX_BUS,
+                transport: patch.protocol.transport().to_string(),
+                universe: Some(data.universe),
+                dmx_channel: Some(data.channel),
+            })
+            .get_result::<Devices>(con)
+            .map_err(|err| {
+                log::error!("Failed to insert dmx device: {}", err);
+                ApiError::InternalErr
+            })?;
+            db::points::fill_diff(con, device.endpoint_count as usize, device.id, -1)?;
+            Ok(device)
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for dmx device insert failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    batcher.request();
+    Ok(web::Json(device))
+}
+
+// repatches fixture, points are added or removed to match endpoint count
+pub async fn put(
+    path: web::Path<i32>,
+    data: web::Json<DmxRequest>,
+    pool: web::Data<DbPool>,
+    batcher: web::Data<Batcher>,
+    frames: web::Data<FrameCache>,
+    dmx: web::Data<DmxOutput>,
+) -> Result<web::Json<Devices>, ApiError> {
+    let device_id = path.into_inner();
+    let patch = valid_patch(&data)?;
+
+    let (previous, device) = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::transaction(&mut con, |con| {
+            let previous = fetch_dmx_device(con, device_id)?;
+            ensure_free(con, &patch, data.endpoint_count, Some(device_id))?;
+            use crate::schema::devices::dsl::*;
+            let device = diesel::update(devices.find(device_id))
+            .set((
+                endpoint_count.eq(data.endpoint_count),
+                transport.eq(patch.protocol.transport()),
+                universe.eq(data.universe),
+                dmx_channel.eq(data.channel),
+            ))
+            .get_result::<Devices>(con)
+            .map_err(|err| {
+                log::error!("Failed to update dmx device [{}]: {}", device_id, err);
+                ApiError::InternalErr
+            })?;
+
+            let db_points = crate::schema::points::dsl::points
+            .filter(crate::schema::points::dsl::device_id.eq(device_id))
+            .load::<Points>(con)
+            .map_err(|err| {
+                log::error!("Fetching points failed: {}", err);
+                ApiError::InternalErr
+            })?;
+            let point_count = device.endpoint_count as usize;
+            let diff = db_points.len().abs_diff(point_count);
+            if db_points.len() < point_count {
+                let max_value = db_points.iter().map(|point| point.device_position).max().unwrap_or(-1);
+                db::points::fill_diff(con, diff, device_id, max_value)?;
+            } else if db_points.len() > point_count {
+                db::points::reduce_diff(con, diff, db_points)?;
+            }
+            Ok((previous, device))
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for dmx device update failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    // channels the fixture moved away from would keep their level
+    let moved = previous.transport != device.transport
+        || previous.universe != device.universe
+        || previous.dmx_channel != device.dmx_channel
+        || previous.endpoint_count != device.endpoint_count;
+    if moved {
+        release(&dmx, &previous);
+    }
+    frames.forget(device.id);
+    batcher.request();
+    Ok(web::Json(device))
+}
+
+// removes fixture and its points, channels it used are zeroed
+pub async fn delete(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+    frames: web::Data<FrameCache>,
+    dmx: web::Data<DmxOutput>,
+) -> Result<HttpResponse, ApiError> {
+    let device_id = path.into_inner();
+    let device = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        let device = fetch_dmx_device(&mut con, device_id)?;
+        use crate::schema::devices::dsl::*;
+        diesel::delete(devices.find(device_id))
+        .execute(&mut con)
+        .map_err(|err| {
+            log::error!("Failed to delete dmx device [{}]: {}", device_id, err);
+            ApiError::InternalErr
+        })?;
+        Ok(device)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for dmx device delete failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    release(&dmx, &device);
+    frames.forget(device_id);
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Zeroes channels the stored patch of `device` covers
+fn release(dmx: &DmxOutput, device: &Devices) {
+    let patch = device.universe.zip(device.dmx_channel)
+    .and_then(|(universe, channel)| DmxPatch::new(&device.transport, universe, channel, device.endpoint_count));
+    if let Some(v) = patch {
+        dmx.release(device.id, &v, device.endpoint_count as usize);
+    }
+}
+
+/// Reports every field that keeps the request from being patched
+fn valid_patch(data: &DmxRequest) -> Result<DmxPatch, ApiError> {
+    let channels = DMX_CHANNELS as i32;
+    let mut validator = Validator::new();
+    validator
+    .one_of("transport", &data.transport, DMX_TRANSPORTS)
+    .range("channel", data.channel, 1, channels)
+    .range("endpoint_count", data.endpoint_count, 1, channels / 2);
+    match data.transport.as_str() {
+        "artnet" => validator.range("universe", data.universe, 0, ARTNET_UNIVERSE_MAX),
+        "sacn" => validator.range("universe", data.universe, SACN_UNIVERSE_MIN, SACN_UNIVERSE_MAX),
+        _ => &mut validator,
+    };
+    validator.finish()?;
+    // every point takes two channels, the last one has to stay within the universe
+    Validator::new()
+    .range("endpoint_count", data.endpoint_count, 1, (channels - data.channel + 1) / 2)
+    .finish()?;
+    DmxPatch::new(&data.transport, data.universe, data.channel, data.endpoint_count)
+    .ok_or(ApiError::InternalErr)
+}
+
+fn fetch_dmx_device(con: &mut DbCon, device_id: i32) -> Result<Devices, ApiError> {
+    use crate::schema::devices::dsl::*;
+    let device = devices.find(device_id)
+    .first::<Devices>(con)
+    .optional()
+    .map_err(|err| {
+        log::error!("Fetching device [{}] failed: {}", device_id, err);
+        ApiError::InternalErr
+    })?
+    .ok_or(ApiError::NotFound)?;
+    if device.bus != DMX_BUS {
+        return Err(ApiError::Conflict);
+    }
+    Ok(device)
+}
+
+/// Rejects patch overlapping channels of another fixture in the same universe
+fn ensure_free(con: &mut DbCon, patch: &DmxPatch, endpoints: i32, except: Option<i32>) -> Result<(), ApiError> {
+    use crate::schema::devices::dsl::*;
+    let others = devices.filter(bus.eq(DMX_BUS))
+    .filter(transport.eq(patch.protocol.transport()))
+    .filter(universe.eq(patch.universe as i32))
+    .load::<Devices>(con)
+    .map_err(|err| {
+        log::error!("Fetching dmx devices failed: {}", err);
+        ApiError::InternalErr
+    })?;
+    let overlapping = others.iter()
+    .filter(|other| Some(other.id) != except)
+    .any(|other| {
+        let other_patch = other.universe.zip(other.dmx_channel)
+        .and_then(|(other_universe, other_channel)| DmxPatch::new(&other.transport, other_universe, other_channel, other.endpoint_count));
+        match other_patch {
+            Some(v) => patch.overlaps(endpoints, &v, other.endpoint_count),
+            None => false,
+        }
+    });
+    match overlapping {
+        true => Err(ApiError::Conflict),
+        false => Ok(()),
+    }
+}
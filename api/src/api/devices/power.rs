@@ -0,0 +1,59 @@
+use crate::api::ApiError;
+use crate::api::helpers::batcher::Batcher;
+use crate::api::helpers::power::{
+    PowerBudget,
+    PowerReport,
+};
+use crate::api::helpers::validation::Validator;
+use crate::types::DbPool;
+use crate::models::{
+    DevicePower,
+    Devices,
+};
+use actix_web::web;
+use diesel::prelude::*;
+
+// budget enforcement of the last dispatch, limited devices have a scale below 1
+pub async fn get(power: web::Data<PowerBudget>) -> web::Json<PowerReport> {
+    web::Json(power.report())
+}
+
+// sets maximum load of one device or its supply, null removes it
+pub async fn put(
+    path: web::Path<i32>,
+    data: web::Json<DevicePower>,
+    pool: web::Data<DbPool>,
+    batcher: web::Data<Batcher>,
+) -> Result<web::Json<Devices>, ApiError> {
+    if let Some(v) = data.max_watts {
+        // a limit of 0 would keep the device dark, null is how it is removed
+        Validator::new().check("max_watts", v.is_finite() && v > 0.0, "positive").finish()?;
+    }
+    let device_id = path.into_inner();
+    let device = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::devices::dsl::*;
+        diesel::update(devices.find(device_id))
+        .set(max_watts.eq(data.max_watts))
+        .get_result::<Devices>(&mut con)
+        .optional()
+        .map_err(|err| {
+            log::error!("Failed to update max watts of device [{}]: {}", device_id, err);
+            ApiError::InternalErr
+        })?
+        .ok_or(ApiError::NotFound)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for device power failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    // levels are scaled again with the new limit
+    batcher.request();
+    Ok(web::Json(device))
+}
@@ -0,0 +1,91 @@
+use super::{
+    fetch_device,
+    open_controller,
+};
+use crate::api::ApiError;
+use crate::api::helpers::i2c::LightDevices;
+use crate::api::helpers::i2c::capabilities::Capabilities;
+use crate::api::helpers::props::{
+    I2C_FEATURE_PWM_FREQUENCY,
+    PWM_FREQUENCY_MAX,
+    PWM_FREQUENCY_MIN,
+};
+use crate::api::helpers::validation::Validator;
+use crate::types::{
+    DbPool,
+    SharedStorage,
+};
+use crate::models::{
+    DeviceSettings,
+    Devices,
+};
+use actix_web::web;
+
+// read from controller, settings it does not advertise are null
+pub async fn get(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+    shared_data: web::Data<SharedStorage>,
+) -> Result<web::Json<DeviceSettings>, ApiError> {
+    let device = fetch_device(pool, path.into_inner()).await?;
+    let shared = shared_data.clone();
+    let settings = web::block(move || {
+        let mut controller = open_controller(&shared, &device)?;
+        read_settings(&mut controller, &device)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for device settings failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(settings))
+}
+
+// writes provided settings to controller and returns what it reports back
+pub async fn put(
+    path: web::Path<i32>,
+    data: web::Json<DeviceSettings>,
+    pool: web::Data<DbPool>,
+    shared_data: web::Data<SharedStorage>,
+) -> Result<web::Json<DeviceSettings>, ApiError> {
+    if let Some(v) = data.pwm_frequency {
+        Validator::new().range("pwm_frequency", v, PWM_FREQUENCY_MIN, PWM_FREQUENCY_MAX).finish()?;
+    }
+    let device = fetch_device(pool, path.into_inner()).await?;
+    let capabilities = Capabilities::from(&device);
+    if data.pwm_frequency.is_some() && !capabilities.supports(I2C_FEATURE_PWM_FREQUENCY) {
+        return Err(ApiError::Conflict);
+    }
+
+    let shared = shared_data.clone();
+    let settings = web::block(move || {
+        let mut controller = open_controller(&shared, &device)?;
+        if let Some(v) = data.pwm_frequency {
+            controller.set_pwm_frequency(device.adr as u16, v as u16)
+            .map_err(|err| {
+                log::error!("Failed to set pwm frequency of device [{}]: {}", device.id, err);
+                ApiError::Unavailable
+            })?;
+        }
+        read_settings(&mut controller, &device)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for device settings failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(settings))
+}
+
+fn read_settings(controller: &mut LightDevices, device: &Devices) -> Result<DeviceSettings, ApiError> {
+    let capabilities = Capabilities::from(device);
+    let pwm_frequency = match capabilities.supports(I2C_FEATURE_PWM_FREQUENCY) {
+        true => Some(controller.get_pwm_frequency(device.adr as u16)
+            .map_err(|err| {
+                log::error!("Failed to read pwm frequency of device [{}]: {}", device.id, err);
+                ApiError::Unavailable
+            })? as i32),
+        false => None,
+    };
+    Ok(DeviceSettings { pwm_frequency })
+}
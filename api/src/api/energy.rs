@@ -0,0 +1,131 @@
+use crate::api::ApiError;
+use crate::api::helpers::validation::Validator;
+use crate::types::{
+    DbCon,
+    DbPool,
+};
+use crate::models::{
+    DeviceEnergy,
+    EnergyQuery,
+    PointEnergy,
+    TagEnergy,
+};
+use actix_web::web;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+// Wh per point in the range, most used first, removed points are summed up under null
+pub async fn points(
+    query: web::Query<EnergyQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<web::Json<Vec<PointEnergy>>, ApiError> {
+    let totals = with_totals(pool, query.into_inner(), |_, totals| {
+        Ok(totals.into_iter()
+        .map(|(point_id, wh)| PointEnergy { point_id, wh })
+        .collect())
+    }).await?;
+    Ok(web::Json(sorted(totals, |v| v.wh)))
+}
+
+// Wh per device in the range, most used first
+pub async fn devices(
+    query: web::Query<EnergyQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<web::Json<Vec<DeviceEnergy>>, ApiError> {
+    let totals = with_totals(pool, query.into_inner(), |con, totals| {
+        use crate::schema::points::dsl::*;
+        let owners = points.select((id, device_id))
+        .load::<(i32, i32)>(con)
+        .map_err(|err| {
+            log::error!("Fetching points failed: {}", err);
+            ApiError::InternalErr
+        })?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        let mut grouped: HashMap<i32, f64> = HashMap::new();
+        for (point, wh) in totals {
+            if let Some(device) = point.and_then(|v| owners.get(&v)) {
+                *grouped.entry(*device).or_default() += wh;
+            }
+        }
+        Ok(grouped.into_iter()
+        .map(|(device, wh)| DeviceEnergy { device_id: device, wh })
+        .collect())
+    }).await?;
+    Ok(web::Json(sorted(totals, |v| v.wh)))
+}
+
+// Wh per point tag in the range, untagged points are summed up under null
+pub async fn tags(
+    query: web::Query<EnergyQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<web::Json<Vec<TagEnergy>>, ApiError> {
+    let totals = with_totals(pool, query.into_inner(), |con, totals| {
+        use crate::schema::points::dsl::*;
+        let tags = points.select((id, tag))
+        .load::<(i32, Option<String>)>(con)
+        .map_err(|err| {
+            log::error!("Fetching points failed: {}", err);
+            ApiError::InternalErr
+        })?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        let mut grouped: HashMap<Option<String>, f64> = HashMap::new();
+        for (point, wh) in totals {
+            if let Some(point_tag) = point.and_then(|v| tags.get(&v)) {
+                *grouped.entry(point_tag.clone()).or_default() += wh;
+            }
+        }
+        Ok(grouped.into_iter()
+        .map(|(point_tag, wh)| TagEnergy { tag: point_tag, wh })
+        .collect())
+    }).await?;
+    Ok(web::Json(sorted(totals, |v| v.wh)))
+}
+
+/// Runs `group` on Wh per point of samples that ended within the range
+async fn with_totals<T, F>(pool: web::Data<DbPool>, query: EnergyQuery, group: F) -> Result<Vec<T>, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut DbCon, Vec<(Option<i32>, f64)>) -> Result<Vec<T>, ApiError> + Send + 'static,
+{
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        Validator::new().check("from", from <= to, "order").finish()?;
+    }
+    web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::energy::dsl::*;
+        let mut totals = energy.group_by(point_id)
+        .select((point_id, diesel::dsl::sum(wh)))
+        .into_boxed();
+        if let Some(v) = query.from {
+            totals = totals.filter(sampled_at.gt(v));
+        }
+        if let Some(v) = query.to {
+            totals = totals.filter(sampled_at.le(v));
+        }
+        let totals = totals.load::<(Option<i32>, Option<f64>)>(&mut con)
+        .map_err(|err| {
+            log::error!("Fetching energy totals failed: {}", err);
+            ApiError::InternalErr
+        })?
+        .into_iter()
+        .map(|(point, wh_sum)| (point, wh_sum.unwrap_or_default()))
+        .collect();
+        group(&mut con, totals)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block for energy totals failed with: {}", err);
+        ApiError::InternalErr
+    })?
+}
+
+fn sorted<T>(mut totals: Vec<T>, wh: fn(&T) -> f64) -> Vec<T> {
+    totals.sort_by(|a, b| wh(b).total_cmp(&wh(a)));
+    totals
+}
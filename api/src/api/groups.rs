@@ -0,0 +1,396 @@
+use crate::{
+    api::ApiError,
+    types::{
+        DbCon,
+        DbPool,
+    },
+    models::{
+        GroupCommand,
+        GroupRequest,
+        GroupState,
+        Limited,
+        NewPointGroupItems,
+        NewPointGroups,
+        PointGroupItems,
+        PointGroups,
+        Points,
+        TransitionQuery,
+    },
+    api::helpers::{
+        batcher::Batcher,
+        db,
+        power::PowerBudget,
+        props::{
+            LIGHT_LEVEL_MAX,
+            LIGHT_LEVEL_MIN,
+        },
+        transitions::Transitions,
+        validation::Validator,
+    },
+};
+use actix_web::web;
+use diesel::{
+    prelude::*,
+    insert_into,
+    update,
+    delete,
+};
+
+static GROUP_ACTIONS: &[&str] = &["set", "scale", "toggle"];
+static GROUP_KINDS: &[&str] = &["room", "group"];
+
+pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<GroupState>>, ApiError> {
+    let groups = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        group_states(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Group fetching block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(groups))
+}
+
+pub async fn post(
+    pool: web::Data<DbPool>,
+    data: web::Json<GroupRequest>,
+) -> Result<web::Json<Vec<GroupState>>, ApiError> {
+    let data = valid_request(data.into_inner())?;
+    let groups = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::transaction(&mut con, |con| {
+            preflight(con, &data, None)?;
+            use crate::schema::point_groups::dsl::*;
+            let inserted_group = insert_into(point_groups).values(NewPointGroups {
+                group_name: data.group_name.clone(),
+                kind: data.kind.clone(),
+                icon: data.icon.clone(),
+            })
+            .get_result::<PointGroups>(con)
+            .map_err(|err| {
+                log::error!("Failed to insert new group: {}", err);
+                ApiError::InternalErr
+            })?;
+            set_members(con, inserted_group.id, &data.points)
+        })?;
+        group_states(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Group inserting block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(groups))
+}
+
+pub async fn put(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+    data: web::Json<GroupRequest>,
+) -> Result<web::Json<Vec<GroupState>>, ApiError> {
+    let target_id = path.into_inner();
+    let data = valid_request(data.into_inner())?;
+    let groups = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::transaction(&mut con, |con| {
+            preflight(con, &data, Some(target_id))?;
+            use crate::schema::point_groups::dsl::*;
+            let update_count = update(point_groups.find(target_id))
+            .set((
+                group_name.eq(data.group_name.clone()),
+                kind.eq(data.kind.clone()),
+                icon.eq(data.icon.clone()),
+            ))
+            .execute(con)
+            .map_err(|err| {
+                log::error!("Failed to update group id [{}]: {}", target_id, err);
+                ApiError::InternalErr
+            })?;
+            if update_count < 1 {
+                return Err(ApiError::NotFound);
+            }
+            set_members(con, target_id, &data.points)
+        })?;
+        group_states(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Group update block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(groups))
+}
+
+pub async fn del(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+) -> Result<web::Json<Vec<GroupState>>, ApiError> {
+    let target_id = path.into_inner();
+    let groups = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        // memberships go with it due to cascade
+        use crate::schema::point_groups::dsl::*;
+        let delete_count = delete(point_groups.find(target_id))
+        .execute(&mut con)
+        .map_err(|err| {
+            log::error!("Failed to delete group: {}", err);
+            ApiError::InternalErr
+        })?;
+        if delete_count < 1 {
+            return Err(ApiError::NotFound);
+        }
+        group_states(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Group deleting block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(groups))
+}
+
+// sets, scales or toggles every member at once, dispatched as one batch
+pub async fn command(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+    data: web::Json<GroupCommand>,
+    query: web::Query<TransitionQuery>,
+    batcher: web::Data<Batcher>,
+    transitions: web::Data<Transitions>,
+    power: web::Data<PowerBudget>,
+) -> Result<web::Json<Limited<GroupState>>, ApiError> {
+    let mut validator = Validator::new();
+    validator.transition(&query).one_of("action", &data.action, GROUP_ACTIONS);
+    match (data.action.as_str(), data.value, data.factor) {
+        ("set", None, _) => {
+            validator.check("value", false, "required");
+        },
+        ("scale", _, None) => {
+            validator.check("factor", false, "required");
+        },
+        ("scale", _, Some(v)) => {
+            validator.min("factor", v, 0.0);
+        },
+        _ => {},
+    }
+    if let Some(v) = data.value {
+        validator.range("value", v, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+    }
+    validator.finish()?;
+
+    let target_id = path.into_inner();
+    let group = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        db::transaction(&mut con, |con| {
+            let group = group_states(con)?
+            .into_iter()
+            .find(|v| v.id == target_id)
+            .ok_or(ApiError::NotFound)?;
+
+            use crate::schema::points::dsl::*;
+            let members = points.filter(id.eq_any(&group.points))
+            .load::<Points>(con)
+            .map_err(|err| {
+                log::error!("Fetching group [{}] points failed: {}", target_id, err);
+                ApiError::InternalErr
+            })?;
+            match data.action.as_str() {
+                "set" | "scale" => scale_or_set(con, &data, &members),
+                _ => toggle(con, &data, &members, group.on),
+            }
+        })?;
+
+        let group = group_states(&mut con)?
+        .into_iter()
+        .find(|v| v.id == target_id)
+        .ok_or(ApiError::NotFound)?;
+        let limited = db::points::limited(&mut con, &power)?;
+        Ok::<_, ApiError>(Limited {
+            limited: group.points.iter().any(|v| limited.contains(v)),
+            item: group,
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Group command block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    transitions.request(&group.item.points, query.transition_ms);
+    batcher.request();
+    Ok(web::Json(group))
+}
+
+/// Sets or scales the level of every member, refusing factors that go over the level range
+fn scale_or_set(con: &mut DbCon, data: &GroupCommand, members: &[Points]) -> Result<(), ApiError> {
+    if let ("scale", Some(factor)) = (data.action.as_str(), data.factor) {
+        // brightest member limits how far the group can be scaled up
+        let brightest = members.iter().map(|member| member.val).max().unwrap_or_default();
+        if brightest > LIGHT_LEVEL_MIN && (brightest as f32 * factor).round() > LIGHT_LEVEL_MAX as f32 {
+            Validator::new()
+            .range("factor", factor, 0.0, LIGHT_LEVEL_MAX as f32 / brightest as f32)
+            .finish()?;
+        }
+    }
+    for member in members {
+        let new_value = match data.action.as_str() {
+            "set" => data.value.unwrap_or(member.val),
+            _ => (member.val as f32 * data.factor.unwrap_or(1.0)).round() as i32,
+        };
+        db::points::set_value(con, member.id, new_value)?;
+        // lit members have to be active, `on` only counts those
+        if new_value > LIGHT_LEVEL_MIN && !member.active {
+            db::points::set_active(con, member.id, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Switches members off or on by `active`, levels are kept to come back on at
+fn toggle(con: &mut DbCon, data: &GroupCommand, members: &[Points], on: bool) -> Result<(), ApiError> {
+    for member in members {
+        if !on {
+            // dark members would stay dark, they come on at full unless a level is given
+            match data.value {
+                Some(v) => db::points::set_value(con, member.id, v)?,
+                None if member.val == LIGHT_LEVEL_MIN => db::points::set_value(con, member.id, LIGHT_LEVEL_MAX)?,
+                None => {},
+            }
+        }
+        db::points::set_active(con, member.id, !on)?;
+    }
+    Ok(())
+}
+
+/// Every group with member ids, average level and whether any active member is lit
+fn group_states(con: &mut DbCon) -> Result<Vec<GroupState>, ApiError> {
+    use crate::schema::point_groups::dsl::*;
+    let groups = point_groups.order(id.asc())
+    .load::<PointGroups>(con)
+    .map_err(|err| {
+        log::error!("Fetching groups failed: {}", err);
+        ApiError::InternalErr
+    })?;
+    let items = crate::schema::point_group_items::dsl::point_group_items
+    .load::<PointGroupItems>(con)
+    .map_err(|err| {
+        log::error!("Fetching group items failed: {}", err);
+        ApiError::InternalErr
+    })?;
+    let point_list = crate::schema::points::dsl::points
+    .load::<Points>(con)
+    .map_err(|err| {
+        log::error!("Fetching points failed: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    Ok(groups.into_iter()
+    .map(|group| {
+        let mut members = items.iter()
+        .filter(|item| item.group_id == group.id)
+        .filter_map(|item| point_list.iter().find(|point| point.id == item.point_id))
+        .collect::<Vec<_>>();
+        members.sort_by_key(|point| point.id);
+        let level = match members.len() {
+            0 => 0,
+            count => (members.iter().map(|point| point.val as i64).sum::<i64>() / count as i64) as i32,
+        };
+        GroupState {
+            id: group.id,
+            group_name: group.group_name,
+            kind: group.kind,
+            icon: group.icon,
+            points: members.iter().map(|point| point.id).collect(),
+            level,
+            on: members.iter().any(|point| point.active && point.val > LIGHT_LEVEL_MIN),
+        }
+    })
+    .collect())
+}
+
+fn valid_request(mut data: GroupRequest) -> Result<GroupRequest, ApiError> {
+    data.group_name = data.group_name.trim().to_string();
+    Validator::new()
+    .length("group_name", data.group_name.chars().count(), 1, None)
+    .one_of("kind", &data.kind, GROUP_KINDS)
+    .finish()?;
+    Ok(data)
+}
+
+/// Names are unique per kind, a room and a group may share one, members have to exist
+fn preflight(con: &mut DbCon, data: &GroupRequest, except: Option<i32>) -> Result<(), ApiError> {
+    use crate::schema::points::dsl::{
+        points,
+        id as point_table_id,
+    };
+    let known = points.select(point_table_id)
+    .filter(point_table_id.eq_any(&data.points))
+    .load::<i32>(con)
+    .map_err(|err| {
+        log::error!("Failed to check group points: {}", err);
+        ApiError::InternalErr
+    })?;
+    let mut validator = Validator::new();
+    for (index, point) in data.points.iter().enumerate() {
+        validator.check(&format!("points[{}]", index), known.contains(point), "exists");
+    }
+    validator.finish()?;
+
+    use crate::schema::point_groups::dsl::*;
+    let same_name = point_groups.select(id)
+    .filter(group_name.eq(&data.group_name).and(kind.eq(&data.kind)))
+    .load::<i32>(con)
+    .map_err(|err| {
+        log::error!("Failed to do preflight check for group: {}", err);
+        ApiError::InternalErr
+    })?;
+    if same_name.iter().any(|v| Some(*v) != except) {
+        return Err(ApiError::Conflict);
+    }
+    Ok(())
+}
+
+/// Replaces members of a group
+fn set_members(con: &mut DbCon, target_id: i32, members: &[i32]) -> Result<(), ApiError> {
+    use crate::schema::point_group_items::dsl::*;
+    delete(point_group_items.filter(group_id.eq(target_id)))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("Failed to clear group [{}] items: {}", target_id, err);
+        ApiError::InternalErr
+    })?;
+    let mut members = members.to_vec();
+    members.sort_unstable();
+    members.dedup();
+    let new_items = members.iter()
+    .map(|v| NewPointGroupItems { group_id: target_id, point_id: *v })
+    .collect::<Vec<_>>();
+    insert_into(point_group_items).values(new_items)
+    .execute(con)
+    .map_err(|err| {
+        log::error!("Failed to insert group [{}] items: {}", target_id, err);
+        ApiError::InternalErr
+    })?;
+    Ok(())
+}
@@ -1,5 +1,13 @@
 pub mod auth;
 pub mod db;
+pub mod dmx;
+pub mod energy;
 pub mod props;
 pub mod i2c;
 pub mod batcher;
+pub mod curves;
+pub mod frames;
+pub mod transitions;
+pub mod power;
+pub mod spatial;
+pub mod validation;
@@ -0,0 +1,170 @@
+use crate::models::Points;
+use super::props::{
+    LIGHT_LEVEL_MAX,
+    LIGHT_LEVEL_MIN,
+    CURVE_GAMMA_MIN,
+    CURVE_GAMMA_MAX,
+    CURVE_TABLE_MIN_LENGTH,
+    CURVE_TABLE_MAX_LENGTH,
+};
+
+/// Dimming curve mapping perceptual `points.val` to output level
+///
+/// Only applied when levels are converted for output, stored and preset values stay perceptual.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// output = input ^ exponent
+    Gamma(f32),
+    /// CIE 1931 lightness, input is L*
+    Cie1931,
+    /// non decreasing output levels spread evenly over the input range, interpolated in between
+    Table(Vec<i32>),
+}
+
+impl Curve {
+    /// Builds curve from stored columns, `None` for unknown kind or invalid parameters
+    pub fn new(kind: &str, gamma: f32, table: Option<&[i32]>) -> Option<Self> {
+        let curve = match kind {
+            "linear" => Curve::Linear,
+            "gamma" => Curve::Gamma(gamma),
+            "cie1931" => Curve::Cie1931,
+            "table" => Curve::Table(table?.to_vec()),
+            _ => return None,
+        };
+        match curve.is_valid() {
+            true => Some(curve),
+            false => None,
+        }
+    }
+
+    /// Curve of a point, falling back to linear on invalid stored parameters
+    pub fn from_point(point: &Points) -> Self {
+        Self::new(&point.curve, point.curve_gamma, point.curve_table.as_deref()).unwrap_or_else(|| {
+            log::warn!("Point [{}] has invalid {} curve, using linear", point.id, point.curve);
+            Curve::Linear
+        })
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Curve::Linear | Curve::Cie1931 => true,
+            Curve::Gamma(v) => v.is_finite() && *v >= CURVE_GAMMA_MIN && *v <= CURVE_GAMMA_MAX,
+            Curve::Table(v) => v.len() >= CURVE_TABLE_MIN_LENGTH
+                && v.len() <= CURVE_TABLE_MAX_LENGTH
+                && v.iter().all(|l| *l >= LIGHT_LEVEL_MIN && *l <= LIGHT_LEVEL_MAX)
+                && v.windows(2).all(|w| w[0] <= w[1]),
+        }
+    }
+
+    /// Maps perceptual level to output level
+    pub fn apply(&self, val: i32) -> i32 {
+        let val = val.clamp(LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+        let input = (val - LIGHT_LEVEL_MIN) as f64 / (LIGHT_LEVEL_MAX - LIGHT_LEVEL_MIN) as f64;
+        let output = match self {
+            Curve::Linear => return val,
+            Curve::Gamma(exponent) => input.powf(*exponent as f64),
+            Curve::Cie1931 => {
+                let lightness = input * 100.0;
+                match lightness <= 8.0 {
+                    true => lightness / 903.3,
+                    false => ((lightness + 16.0) / 116.0).powi(3),
+                }
+            },
+            Curve::Table(table) => {
+                let position = input * (table.len() - 1) as f64;
+                let index = (position.floor() as usize).min(table.len() - 2);
+                let fraction = position - index as f64;
+                let level = table[index] as f64 + (table[index + 1] - table[index]) as f64 * fraction;
+                return (level.round() as i32).clamp(LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+            },
+        };
+        let level = LIGHT_LEVEL_MIN as f64 + output * (LIGHT_LEVEL_MAX - LIGHT_LEVEL_MIN) as f64;
+        (level.round() as i32).clamp(LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX)
+    }
+
+    /// Smallest perceptual level reaching output `level`, used when adopting hardware state
+    pub fn invert(&self, level: i32) -> i32 {
+        if *self == Curve::Linear {
+            return level.clamp(LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+        }
+        let (mut low, mut high) = (LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.apply(middle) >= level {
+                true => high = middle,
+                false => low = middle + 1,
+            }
+        }
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curves() -> Vec<Curve> {
+        vec![
+            Curve::Linear,
+            Curve::Gamma(2.2),
+            Curve::Gamma(0.5),
+            Curve::Cie1931,
+            Curve::Table(vec![0, 100, 1000, LIGHT_LEVEL_MAX]),
+        ]
+    }
+
+    #[test]
+    fn curves_keep_the_ends_and_never_decrease() {
+        for curve in curves() {
+            assert_eq!(curve.apply(LIGHT_LEVEL_MIN), LIGHT_LEVEL_MIN, "{:?}", curve);
+            assert_eq!(curve.apply(LIGHT_LEVEL_MAX), LIGHT_LEVEL_MAX, "{:?}", curve);
+            assert_eq!(curve.apply(LIGHT_LEVEL_MAX + 10), LIGHT_LEVEL_MAX, "{:?}", curve);
+            assert_eq!(curve.apply(-10), LIGHT_LEVEL_MIN, "{:?}", curve);
+            let levels = (LIGHT_LEVEL_MIN..=LIGHT_LEVEL_MAX).step_by(97).map(|v| curve.apply(v)).collect::<Vec<_>>();
+            assert!(levels.windows(2).all(|w| w[0] <= w[1]), "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn curves_map_midpoint() {
+        let half = LIGHT_LEVEL_MAX / 2;
+        assert_eq!(Curve::Linear.apply(half), half);
+        assert!((16383..=16384).contains(&Curve::Gamma(2.0).apply(half)));
+        // L* 50 is 18.4% luminance
+        assert!((12060..=12080).contains(&Curve::Cie1931.apply(half)));
+        assert_eq!(Curve::Table(vec![0, 100, LIGHT_LEVEL_MAX]).apply(half), 100);
+    }
+
+    #[test]
+    fn cie1931_is_linear_in_the_dark() {
+        let level = (LIGHT_LEVEL_MAX as f64 * 0.04).round() as i32;
+        let expected = (4.0 / 903.3 * LIGHT_LEVEL_MAX as f64).round() as i32;
+        assert!((Curve::Cie1931.apply(level) - expected).abs() <= 1);
+    }
+
+    #[test]
+    fn invert_finds_smallest_level_reaching_output() {
+        for curve in curves() {
+            for level in [1, 10, 500, 12345, LIGHT_LEVEL_MAX - 1, LIGHT_LEVEL_MAX] {
+                let val = curve.invert(level);
+                assert!(curve.apply(val) >= level, "{:?} {}", curve, level);
+                assert!(val == LIGHT_LEVEL_MIN || curve.apply(val - 1) < level, "{:?} {}", curve, level);
+            }
+            assert_eq!(curve.invert(LIGHT_LEVEL_MIN), LIGHT_LEVEL_MIN);
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_refused() {
+        assert_eq!(Curve::new("gamma", 2.2, None), Some(Curve::Gamma(2.2)));
+        assert_eq!(Curve::new("gamma", 0.05, None), None);
+        assert_eq!(Curve::new("gamma", f32::NAN, None), None);
+        assert_eq!(Curve::new("table", 1.0, None), None);
+        assert_eq!(Curve::new("table", 1.0, Some(&[0])), None);
+        assert_eq!(Curve::new("table", 1.0, Some(&[0, 10, 5])), None);
+        assert_eq!(Curve::new("table", 1.0, Some(&[0, LIGHT_LEVEL_MAX + 1])), None);
+        assert_eq!(Curve::new("table", 1.0, Some(&[0, 10])), Some(Curve::Table(vec![0, 10])));
+        assert_eq!(Curve::new("log", 1.0, None), None);
+    }
+}
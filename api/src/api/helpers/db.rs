@@ -1,2 +1,34 @@
 pub mod devices;
-pub mod points;
\ No newline at end of file
+pub mod points;
+pub mod presets;
+pub mod scan;
+pub mod sensors;
+
+use diesel::Connection;
+use diesel::result::Error;
+use crate::api::ApiError;
+use crate::types::DbCon;
+
+/// Runs `queries` in one transaction, nothing of it is kept when it fails
+///
+/// Errors of `queries` are returned as they are, failures to begin or commit become `InternalErr`.
+pub fn transaction<T, F>(con: &mut DbCon, queries: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut DbCon) -> Result<T, ApiError>,
+{
+    let mut failed = None;
+    let result = con.transaction::<_, Error, _>(|con| {
+        queries(con).map_err(|err| {
+            failed = Some(err);
+            Error::RollbackTransaction
+        })
+    });
+    match (result, failed) {
+        (Ok(v), _) => Ok(v),
+        (Err(_), Some(err)) => Err(err),
+        (Err(err), None) => {
+            log::error!("Transaction failed: {}", err);
+            Err(ApiError::InternalErr)
+        },
+    }
+}
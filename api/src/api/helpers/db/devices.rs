@@ -0,0 +1,44 @@
+use chrono::Utc;
+use diesel::{
+    RunQueryDsl,
+    update,
+    prelude::*,
+};
+use crate::api::helpers::props::DEVICE_OFFLINE_FAILURES;
+use crate::schema::devices::dsl::*;
+use crate::types::DbCon;
+
+/// Records successful contact with devices
+pub fn mark_seen(con: &mut DbCon, device_ids: Vec<i32>) -> QueryResult<usize> {
+    if device_ids.is_empty() {
+        return Ok(0);
+    }
+    update(devices.filter(id.eq_any(device_ids)))
+    .set((
+        online.eq(true),
+        last_seen.eq(Utc::now().naive_utc()),
+        failure_count.eq(0),
+    ))
+    .execute(con)
+}
+
+/// Records failed contact, device goes offline after `DEVICE_OFFLINE_FAILURES` in a row
+pub fn mark_failed(con: &mut DbCon, device_id: i32, error_kind: &str) -> QueryResult<usize> {
+    update(devices.find(device_id))
+    .set((
+        failure_count.eq(failure_count + 1),
+        online.eq(failure_count.lt(DEVICE_OFFLINE_FAILURES - 1)),
+        last_error.eq(error_kind),
+    ))
+    .execute(con)
+}
+
+/// Records hardware levels that did not match the stored ones
+pub fn mark_drift(con: &mut DbCon, device_id: i32) -> QueryResult<usize> {
+    update(devices.find(device_id))
+    .set((
+        drift_count.eq(drift_count + 1),
+        last_drift.eq(Utc::now().naive_utc()),
+    ))
+    .execute(con)
+}
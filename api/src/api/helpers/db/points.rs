@@ -2,9 +2,18 @@ use diesel::{
     insert_into,
     RunQueryDsl,
     delete,
+    update,
     prelude::*,
 };
+use std::collections::HashSet;
 use crate::api::ApiError;
+use crate::api::helpers::power::PowerBudget;
+use crate::api::helpers::validation::Validator;
+use crate::api::helpers::props::{
+    I2C_LIGHT_CONTROLLER,
+    LIGHT_LEVEL_MAX,
+    LIGHT_LEVEL_MIN,
+};
 use crate::models::{
     NewPoints,
     Points,
@@ -65,4 +74,70 @@ pub fn reduce_diff(con: &mut DbCon, diff: usize, point_list: Vec<Points>) -> Res
         ApiError::InternalErr
     })?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Turns point on or off without touching its level
+pub fn set_active(con: &mut DbCon, point_id: i32, state: bool) -> Result<(), ApiError> {
+    update(points).filter(id.eq(point_id))
+    .set(active.eq(state))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("updating single [{}] point state failed: {}", point_id, err);
+        ApiError::InternalErr
+    })?;
+    Ok(())
+}
+
+/// Sets level of one point, active preset no longer applies after it
+///
+/// Levels out of the light level range are refused, callers report them against their own fields first.
+pub fn set_value(con: &mut DbCon, point_id: i32, value: i32) -> Result<(), ApiError> {
+    Validator::new().range("value", value, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX).finish()?;
+    update(points).filter(id.eq(point_id))
+    .set(val.eq(value))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("updating single [{}] point failed: {}", point_id, err);
+        ApiError::InternalErr
+    })?;
+
+    use crate::schema::presets::dsl::{
+        presets,
+        active as preset_active,
+    };
+    update(presets).filter(preset_active.eq(true))
+    .set(preset_active.eq(false))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("failed to update presets: {}", err);
+        ApiError::InternalErr
+    })?;
+    Ok(())
+}
+
+/// Points the power budget scales down with the stored levels, see `PowerBudget::limited_points`
+pub fn limited(con: &mut DbCon, power: &PowerBudget) -> Result<HashSet<i32>, ApiError> {
+    let point_list = points.load::<Points>(con)
+    .map_err(|err| {
+        log::error!("Fetching points failed: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    use crate::schema::devices::dsl::{
+        devices,
+        device_type,
+        max_watts,
+        id as device_id,
+    };
+    let limits = devices.select((device_id, max_watts))
+    .filter(device_type.eq(I2C_LIGHT_CONTROLLER as i32))
+    .load::<(i32, Option<f32>)>(con)
+    .map_err(|err| {
+        log::error!("Fetching device power limits failed: {}", err);
+        ApiError::InternalErr
+    })?
+    .into_iter()
+    .filter_map(|(dev_id, dev_max_watts)| Some((dev_id, dev_max_watts?)))
+    .collect();
+    Ok(power.limited_points(point_list, &limits))
+}
@@ -0,0 +1,78 @@
+use diesel::{
+    RunQueryDsl,
+    update,
+    prelude::*,
+};
+use crate::api::ApiError;
+use crate::models::{
+    PresetItems,
+    Presets,
+};
+use crate::schema::presets::dsl::*;
+use crate::types::DbCon;
+
+/// Marks preset as the active one and copies its levels to points
+///
+/// With `owner` only presets of that user can be activated.
+pub fn activate(con: &mut DbCon, active_id: i32, owner: Option<i32>) -> Result<Vec<i32>, ApiError> {
+    let mut query = presets.filter(id.eq(active_id)).into_boxed();
+    if let Some(uid) = owner {
+        query = query.filter(user_id.eq(uid));
+    }
+    let selected_presets = query
+    .load::<Presets>(con)
+    .map_err(|err| {
+        log::error!("Failed to fetch user related preset: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    if selected_presets.len() > 1 {
+        return Err(ApiError::InternalErr);
+    }
+    if selected_presets.is_empty() {
+        return Err(ApiError::Conflict);
+    }
+
+    update(presets).filter(active.eq(true))
+    .set(active.eq(false))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("Failed to fetch set presets as inactive: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    update(presets).filter(id.eq(active_id))
+    .set(active.eq(true))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("Failed to set active preset: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    use crate::schema::preset_items::dsl::{
+        preset_items,
+        preset_id,
+    };
+    let selected_preset_items = preset_items.filter(preset_id.eq(active_id))
+    .load::<PresetItems>(con)
+    .map_err(|err| {
+        log::error!("Failed to fetch selected preset items: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    use crate::schema::points::dsl::{
+        points,
+        id as point_table_id,
+        val as point_table_val,
+    };
+    for p_item in &selected_preset_items {
+        update(points).filter(point_table_id.eq(p_item.point_id))
+        .set(point_table_val.eq(p_item.val))
+        .execute(con)
+        .map_err(|err| {
+            log::error!("Failed to update point [{}]: {}", p_item.point_id, err);
+            ApiError::InternalErr
+        })?;
+    }
+    Ok(selected_preset_items.iter().map(|p_item| p_item.point_id).collect())
+}
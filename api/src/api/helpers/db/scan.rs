@@ -0,0 +1,109 @@
+use diesel::{
+    insert_into,
+    RunQueryDsl,
+    delete,
+    prelude::*,
+};
+use crate::api::ApiError;
+use crate::api::helpers::i2c::scan::{
+    ScanEntry,
+    ScanReport,
+};
+use crate::models::{
+    NewScanEntries,
+    ScanEntries,
+    ScanReports,
+};
+use crate::schema::scan_entries::dsl::scan_entries;
+use crate::schema::scan_reports::dsl::*;
+use crate::types::DbCon;
+
+/// Replaces stored reports of the buses in `reports`, reports of buses never scanned are left out
+pub fn store(con: &mut DbCon, reports: &[ScanReport]) -> Result<(), ApiError> {
+    super::transaction(con, |con| {
+        for report in reports {
+            let report_time = match report.scanned_at {
+                Some(v) => v,
+                None => continue,
+            };
+            // entries go with it due to cascade
+            delete(scan_reports.find(report.bus))
+            .execute(con)
+            .map_err(|err| {
+                log::error!("Failed to delete bus [{}] scan report: {}", report.bus, err);
+                ApiError::InternalErr
+            })?;
+            insert_into(scan_reports).values(ScanReports {
+                bus: report.bus,
+                scanned_at: report_time,
+                addresses: report.addresses as i32,
+            })
+            .execute(con)
+            .map_err(|err| {
+                log::error!("Failed to insert bus [{}] scan report: {}", report.bus, err);
+                ApiError::InternalErr
+            })?;
+            let new_entries = report.entries.iter()
+            .map(|entry| NewScanEntries {
+                bus: report.bus,
+                adr: entry.adr as i32,
+                identifier: entry.identifier as i32,
+                known: entry.known,
+                endpoint_count: entry.endpoint_count as i32,
+                probed: entry.probed,
+            })
+            .collect::<Vec<_>>();
+            insert_into(scan_entries).values(&new_entries)
+            .execute(con)
+            .map_err(|err| {
+                log::error!("Failed to insert bus [{}] scan entries: {}", report.bus, err);
+                ApiError::InternalErr
+            })?;
+        }
+        Ok(())
+    })
+}
+
+/// Stored reports with their entries by address
+pub fn load(con: &mut DbCon) -> Result<Vec<ScanReport>, ApiError> {
+    let report_list = scan_reports.order(bus.asc())
+    .load::<ScanReports>(con)
+    .map_err(|err| {
+        log::error!("Fetching scan reports failed: {}", err);
+        ApiError::InternalErr
+    })?;
+    let entry_list = scan_entries.order(crate::schema::scan_entries::dsl::adr.asc())
+    .load::<ScanEntries>(con)
+    .map_err(|err| {
+        log::error!("Fetching scan entries failed: {}", err);
+        ApiError::InternalErr
+    })?;
+    Ok(report_list.into_iter()
+    .map(|report| ScanReport {
+        bus: report.bus,
+        scanned_at: Some(report.scanned_at),
+        addresses: report.addresses as usize,
+        entries: entry_list.iter()
+        .filter(|entry| entry.bus == report.bus)
+        .map(|entry| ScanEntry {
+            adr: entry.adr as u16,
+            identifier: entry.identifier as u8,
+            known: entry.known,
+            endpoint_count: entry.endpoint_count as u8,
+            probed: entry.probed,
+        })
+        .collect(),
+    })
+    .collect())
+}
+
+/// Forgets every stored report
+pub fn clear(con: &mut DbCon) -> Result<(), ApiError> {
+    delete(scan_reports)
+    .execute(con)
+    .map_err(|err| {
+        log::error!("Failed to delete scan reports: {}", err);
+        ApiError::InternalErr
+    })?;
+    Ok(())
+}
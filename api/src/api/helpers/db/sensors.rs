@@ -0,0 +1,49 @@
+use diesel::{
+    insert_into,
+    RunQueryDsl,
+    delete,
+    prelude::*,
+};
+use crate::api::ApiError;
+use crate::models::{
+    NewSensors,
+    Sensors,
+};
+use crate::schema::sensors::dsl::*;
+use crate::types::DbCon;
+
+pub fn fill_diff(con: &mut DbCon, diff: usize, devc_id: i32, fill_start: i32) -> Result<usize, ApiError> {
+    let insert_sensors = (1..=diff as i32)
+    .map(|offset| NewSensors {
+        device_id: devc_id,
+        device_position: fill_start + offset,
+        occupied: false,
+    })
+    .collect::<Vec<NewSensors>>();
+    insert_into(sensors).values(&insert_sensors).execute(con)
+    .map_err(|err| {
+        log::error!("Failed inserting device sensors: {}", err);
+        ApiError::InternalErr
+    })
+}
+
+/// deleting items from the last `device_position`
+pub fn reduce_diff(con: &mut DbCon, diff: usize, sensor_list: Vec<Sensors>) -> Result<(), ApiError> {
+    if sensor_list.is_empty() {
+        return Ok(());
+    }
+    if sensor_list.len() < diff {
+        return Err(ApiError::InternalErr);
+    }
+
+    let mut sorted_sensors = sensor_list;
+    sorted_sensors.sort_by_key(|sensor| sensor.device_position);
+    let delete_ids = sorted_sensors.iter().rev().take(diff).map(|sensor| sensor.id).collect::<Vec<i32>>();
+
+    delete(sensors.filter(id.eq_any(delete_ids))).execute(con)
+    .map_err(|err| {
+        log::error!("Failed deleting device sensors: {}", err);
+        ApiError::InternalErr
+    })?;
+    Ok(())
+}
@@ -0,0 +1,372 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::net::{
+    IpAddr,
+    Ipv4Addr,
+    SocketAddr,
+    UdpSocket,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use super::i2c::bus::BusError;
+use super::props::{
+    ARTNET_PORT,
+    ARTNET_UNIVERSE_MAX,
+    DMX_CHANNELS,
+    SACN_PORT,
+    SACN_UNIVERSE_MAX,
+    SACN_UNIVERSE_MIN,
+};
+
+/// DMX over IP protocol, stored as device transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmxProtocol {
+    ArtNet,
+    /// E1.31
+    Sacn,
+}
+
+impl DmxProtocol {
+    pub fn new(transport: &str) -> Option<Self> {
+        match transport {
+            "artnet" => Some(DmxProtocol::ArtNet),
+            "sacn" => Some(DmxProtocol::Sacn),
+            _ => None,
+        }
+    }
+
+    pub fn transport(&self) -> &'static str {
+        match self {
+            DmxProtocol::ArtNet => "artnet",
+            DmxProtocol::Sacn => "sacn",
+        }
+    }
+}
+
+/// Where a device starts in DMX space, every point takes coarse and fine channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DmxPatch {
+    pub protocol: DmxProtocol,
+    pub universe: u16,
+    /// first channel, 1 based
+    pub channel: u16,
+}
+
+impl DmxPatch {
+    /// Checks universe against protocol and that `endpoint_count` points fit into the universe
+    pub fn new(transport: &str, universe: i32, channel: i32, endpoint_count: i32) -> Option<Self> {
+        let protocol = DmxProtocol::new(transport)?;
+        let universe_valid = match protocol {
+            DmxProtocol::ArtNet => (0..=ARTNET_UNIVERSE_MAX).contains(&universe),
+            DmxProtocol::Sacn => (SACN_UNIVERSE_MIN..=SACN_UNIVERSE_MAX).contains(&universe),
+        };
+        // bounds first, so the last channel can not overflow
+        let channel_valid = (1..=DMX_CHANNELS as i32).contains(&channel);
+        let count_valid = (1..=DMX_CHANNELS as i32 / 2).contains(&endpoint_count);
+        if !universe_valid || !channel_valid || !count_valid || channel + endpoint_count * 2 - 1 > DMX_CHANNELS as i32 {
+            return None;
+        }
+        Some(Self {
+            protocol,
+            universe: universe as u16,
+            channel: channel as u16,
+        })
+    }
+
+    /// Whether channels of both patches meet in one universe, `endpoint_count` points each
+    pub fn overlaps(&self, endpoint_count: i32, other: &DmxPatch, other_count: i32) -> bool {
+        let first = self.channel as i32;
+        let last = first + endpoint_count * 2 - 1;
+        let other_first = other.channel as i32;
+        let other_last = other_first + other_count * 2 - 1;
+        self.protocol == other.protocol && self.universe == other.universe
+        && first <= other_last && other_first <= last
+    }
+}
+
+/// Art-Net and sACN sender keeping last state of every universe
+///
+/// Universes are sent whole, so devices sharing one keep each others channels.
+/// Without `DMX_TARGET` Art-Net is broadcast and sACN goes to the universe multicast group.
+#[derive(Clone)]
+pub struct DmxOutput {
+    state: Arc<Mutex<DmxState>>,
+}
+
+struct DmxState {
+    socket: Option<UdpSocket>,
+    target: Option<IpAddr>,
+    patch: HashMap<i32, DmxPatch>,
+    universes: HashMap<(DmxProtocol, u16), Vec<u8>>,
+    sequence: u8,
+}
+
+impl DmxOutput {
+    pub fn new(target: Option<IpAddr>) -> Self {
+        DmxOutput {
+            state: Arc::new(Mutex::new(DmxState {
+                socket: None,
+                target,
+                patch: HashMap::new(),
+                universes: HashMap::new(),
+                sequence: 0,
+            })),
+        }
+    }
+
+    /// Replaces device patch, loaded by the dispatcher with every dispatch
+    pub fn set_patch(&self, patch: HashMap<i32, DmxPatch>) {
+        self.state.lock().unwrap().patch = patch;
+    }
+
+    /// Writes 16 bit levels of every device as coarse/fine channels and sends each touched universe once
+    pub fn send(&self, frames: &[(i32, Vec<i32>)]) -> Vec<(i32, Result<(), BusError>)> {
+        let mut state = self.state.lock().unwrap();
+        let mut touched: HashSet<(DmxProtocol, u16)> = HashSet::new();
+        let mut result: Vec<(i32, Result<(), BusError>)> = vec![];
+        let mut pending: Vec<(i32, (DmxProtocol, u16))> = vec![];
+        for (device_id, levels) in frames {
+            let patch = match state.patch.get(device_id) {
+                Some(v) => *v,
+                None => {
+                    result.push((*device_id, Err(BusError::InvalidInput)));
+                    continue;
+                },
+            };
+            let key = (patch.protocol, patch.universe);
+            let universe = state.universes.entry(key).or_insert_with(|| vec![0; DMX_CHANNELS]);
+            for (position, level) in levels.iter().enumerate() {
+                let [coarse, fine] = (*level as u16).to_be_bytes();
+                let index = patch.channel as usize - 1 + position * 2;
+                if index + 1 < universe.len() {
+                    universe[index] = coarse;
+                    universe[index + 1] = fine;
+                }
+            }
+            touched.insert(key);
+            pending.push((*device_id, key));
+        }
+
+        let mut sent: HashMap<(DmxProtocol, u16), Result<(), std::io::ErrorKind>> = HashMap::new();
+        for key in touched {
+            sent.insert(key, state.transmit(key).map_err(|err| {
+                log::error!("Failed to send {} universe {}: {}", key.0.transport(), key.1, err);
+                err.kind()
+            }));
+        }
+        for (device_id, key) in pending {
+            let outcome = match sent.get(&key) {
+                Some(Err(kind)) => Err(BusError::from(std::io::Error::from(*kind))),
+                _ => Ok(()),
+            };
+            result.push((device_id, outcome));
+        }
+        result
+    }
+
+    /// Zeroes channels of a removed or repatched device and sends its universe once
+    ///
+    /// Refresh would keep sending the last levels on them otherwise.
+    pub fn release(&self, device_id: i32, patch: &DmxPatch, endpoint_count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.patch.remove(&device_id);
+        let key = (patch.protocol, patch.universe);
+        let universe = match state.universes.get_mut(&key) {
+            Some(v) => v,
+            None => return,
+        };
+        let first = patch.channel as usize - 1;
+        let last = (first + endpoint_count * 2).min(universe.len());
+        if let Some(channels) = universe.get_mut(first..last) {
+            channels.iter_mut().for_each(|v| *v = 0);
+        }
+        if let Err(err) = state.transmit(key) {
+            log::error!("Failed to release {} universe {}: {}", key.0.transport(), key.1, err);
+        }
+    }
+
+    /// Resends every known universe, sACN receivers drop a source that goes quiet
+    pub fn refresh(&self) {
+        let mut state = self.state.lock().unwrap();
+        let keys = state.universes.keys().copied().collect::<Vec<_>>();
+        for key in keys {
+            if let Err(err) = state.transmit(key) {
+                log::error!("Failed to refresh {} universe {}: {}", key.0.transport(), key.1, err);
+            }
+        }
+    }
+}
+
+impl DmxState {
+    fn transmit(&mut self, key: (DmxProtocol, u16)) -> std::io::Result<()> {
+        if self.socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_broadcast(true)?;
+            self.socket = Some(socket);
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        // 0 means sequencing is off for Art-Net
+        if self.sequence == 0 {
+            self.sequence = 1;
+        }
+        let (protocol, universe) = key;
+        let data = self.universes.get(&key).cloned().unwrap_or_else(|| vec![0; DMX_CHANNELS]);
+        let (packet, destination) = match protocol {
+            DmxProtocol::ArtNet => (
+                artnet_packet(universe, self.sequence, &data),
+                SocketAddr::new(self.target.unwrap_or(IpAddr::V4(Ipv4Addr::BROADCAST)), ARTNET_PORT),
+            ),
+            DmxProtocol::Sacn => {
+                let [high, low] = universe.to_be_bytes();
+                (
+                    sacn_packet(universe, self.sequence, &data),
+                    SocketAddr::new(self.target.unwrap_or(IpAddr::V4(Ipv4Addr::new(239, 255, high, low))), SACN_PORT),
+                )
+            },
+        };
+        let socket = self.socket.as_ref().ok_or(std::io::ErrorKind::NotConnected)?;
+        socket.send_to(&packet, destination)?;
+        Ok(())
+    }
+}
+
+/// ArtDmx packet
+fn artnet_packet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = b"Art-Net\0".to_vec();
+    // OpDmx, little endian
+    packet.extend_from_slice(&[0x00, 0x50]);
+    // protocol version 14
+    packet.extend_from_slice(&[0x00, 0x0e]);
+    packet.push(sequence);
+    // physical port
+    packet.push(0);
+    // sub-net/universe, then net
+    packet.push((universe & 0xff) as u8);
+    packet.push(((universe >> 8) & 0x7f) as u8);
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// E1.31 data packet with root, framing and DMP layers
+fn sacn_packet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    // identifies this sender, receivers merge by it
+    const CID: [u8; 16] = [
+        0x4c, 0x31, 0x67, 0x68, 0x74, 0x73, 0x2d, 0x61,
+        0x70, 0x69, 0x2d, 0x73, 0x61, 0x63, 0x6e, 0x01,
+    ];
+    let flags_length = |length: usize| (0x7000 | length as u16).to_be_bytes();
+    let total = 126 + data.len();
+
+    let mut packet: Vec<u8> = vec![];
+    // root layer
+    packet.extend_from_slice(&[0x00, 0x10, 0x00, 0x00]);
+    packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+    packet.extend_from_slice(&flags_length(total - 16));
+    packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x04]);
+    packet.extend_from_slice(&CID);
+    // framing layer
+    packet.extend_from_slice(&flags_length(total - 38));
+    packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x02]);
+    let mut source_name = b"L1ghts".to_vec();
+    source_name.resize(64, 0);
+    packet.extend_from_slice(&source_name);
+    // priority, synchronization address, sequence, options
+    packet.push(100);
+    packet.extend_from_slice(&[0x00, 0x00]);
+    packet.push(sequence);
+    packet.push(0);
+    packet.extend_from_slice(&universe.to_be_bytes());
+    // dmp layer
+    packet.extend_from_slice(&flags_length(total - 115));
+    packet.push(0x02);
+    packet.push(0xa1);
+    packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+    packet.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+    // start code
+    packet.push(0x00);
+    packet.extend_from_slice(data);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(transport: &str, universe: i32, channel: i32) -> DmxPatch {
+        DmxPatch::new(transport, universe, channel, 1).unwrap()
+    }
+
+    #[test]
+    fn patches_have_to_fit_their_universe() {
+        assert!(DmxPatch::new("artnet", 0, 1, 256).is_some());
+        assert!(DmxPatch::new("artnet", ARTNET_UNIVERSE_MAX, 511, 1).is_some());
+        assert!(DmxPatch::new("artnet", ARTNET_UNIVERSE_MAX + 1, 1, 1).is_none());
+        assert!(DmxPatch::new("sacn", 0, 1, 1).is_none());
+        assert!(DmxPatch::new("sacn", SACN_UNIVERSE_MAX, 1, 1).is_some());
+        assert!(DmxPatch::new("sacn", SACN_UNIVERSE_MAX + 1, 1, 1).is_none());
+        // the fine channel of the last point would be 513
+        assert!(DmxPatch::new("artnet", 0, 512, 1).is_none());
+        assert!(DmxPatch::new("artnet", 0, 2, 256).is_none());
+        assert!(DmxPatch::new("artnet", 0, 0, 1).is_none());
+        assert!(DmxPatch::new("artnet", 0, 1, 0).is_none());
+        assert!(DmxPatch::new("dmx", 0, 1, 1).is_none());
+    }
+
+    #[test]
+    fn overlapping_channels_are_found() {
+        let first = patch("artnet", 1, 1);
+        // channels 1-4 and 5-6 are adjacent
+        assert!(!first.overlaps(2, &patch("artnet", 1, 5), 1));
+        assert!(first.overlaps(2, &patch("artnet", 1, 4), 1));
+        assert!(patch("artnet", 1, 4).overlaps(1, &first, 2));
+        assert!(first.overlaps(1, &patch("artnet", 1, 1), 1));
+        assert!(!first.overlaps(2, &patch("artnet", 2, 1), 2));
+        assert!(!first.overlaps(2, &patch("sacn", 1, 1), 2));
+    }
+
+    #[test]
+    fn artnet_packets_carry_the_universe_little_endian() {
+        let data = (0..DMX_CHANNELS).map(|v| v as u8).collect::<Vec<_>>();
+        let packet = artnet_packet(0x1234, 7, &data);
+        assert_eq!(packet.len(), 18 + DMX_CHANNELS);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        assert_eq!(&packet[8..12], &[0x00, 0x50, 0x00, 0x0e]);
+        assert_eq!((packet[12], packet[13]), (7, 0));
+        assert_eq!((packet[14], packet[15]), (0x34, 0x12));
+        assert_eq!(&packet[16..18], &[0x02, 0x00]);
+        assert_eq!(&packet[18..], &data[..]);
+        // net is 7 bits
+        assert_eq!(artnet_packet(0xffff, 1, &data)[15], 0x7f);
+    }
+
+    #[test]
+    fn sacn_packets_follow_e131_layout() {
+        let data = vec![0xab; DMX_CHANNELS];
+        let packet = sacn_packet(0x0102, 9, &data);
+        assert_eq!(packet.len(), 638);
+        // root layer
+        assert_eq!(&packet[..4], &[0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(&packet[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(&packet[16..18], &(0x7000u16 | 622).to_be_bytes());
+        assert_eq!(&packet[18..22], &[0x00, 0x00, 0x00, 0x04]);
+        // framing layer
+        assert_eq!(&packet[38..40], &(0x7000u16 | 600).to_be_bytes());
+        assert_eq!(&packet[40..44], &[0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(&packet[44..50], b"L1ghts");
+        assert!(packet[50..108].iter().all(|v| *v == 0));
+        assert_eq!(packet[108], 100);
+        assert_eq!((packet[111], packet[112]), (9, 0));
+        assert_eq!(&packet[113..115], &[0x01, 0x02]);
+        // dmp layer
+        assert_eq!(&packet[115..117], &(0x7000u16 | 523).to_be_bytes());
+        assert_eq!(&packet[117..123], &[0x02, 0xa1, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(&packet[123..125], &513u16.to_be_bytes());
+        assert_eq!(packet[125], 0x00);
+        assert_eq!(&packet[126..], &data[..]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use chrono::{
+  NaiveDateTime,
+  Utc,
+};
+use std::sync::{
+  Arc,
+  Mutex,
+};
+use std::time::Instant;
+use super::frames::FrameCache;
+use super::props::LIGHT_LEVEL_MAX;
+
+
+struct EnergyState {
+  /// `(point id, watts)` of every device in `device_position` order
+  points: HashMap<i32, Vec<(i32, f32)>>,
+  /// Levels last written to every device, kept when `FrameCache` forgets them
+  outputs: HashMap<i32, Vec<i32>>,
+  /// Wh per point since `started_at`
+  totals: HashMap<i32, f64>,
+  started_at: NaiveDateTime,
+  last: Option<Instant>,
+}
+
+/// Energy used by every point, integrated from the levels the dispatcher wrote
+///
+/// Levels are taken from `FrameCache`, so fades in progress are accounted for as they really are.
+/// A device that misses a write keeps the levels it was last written to, which are
+/// still counted while the frame cache has forgotten them.
+#[derive(Clone)]
+pub struct EnergyMeter {
+  state: Arc<Mutex<EnergyState>>,
+}
+
+
+impl EnergyMeter {
+  pub fn new() -> Self {
+    EnergyMeter {
+      state: Arc::new(Mutex::new(EnergyState {
+        points: HashMap::new(),
+        outputs: HashMap::new(),
+        totals: HashMap::new(),
+        started_at: Utc::now().naive_utc(),
+        last: None,
+      })),
+    }
+  }
+
+  /// Replaces point watts, loaded by the dispatcher with every dispatch
+  ///
+  /// Totals of points that are gone are kept until the next `take`.
+  pub fn set_points(&self, points: HashMap<i32, Vec<(i32, f32)>>) {
+    let mut state = self.state.lock().unwrap();
+    state.outputs.retain(|device_id, _| points.contains_key(device_id));
+    state.points = points;
+  }
+
+  /// Adds energy of the levels held since the last call, has to run before levels change
+  pub fn accumulate(&self, frames: &FrameCache) {
+    let now = Instant::now();
+    let mut state = self.state.lock().unwrap();
+    let state = &mut *state;
+    for device_id in state.points.keys() {
+      if let Some(v) = frames.last(*device_id) {
+        state.outputs.insert(*device_id, v);
+      }
+    }
+    let hours = match state.last.replace(now) {
+      Some(v) => now.saturating_duration_since(v).as_secs_f64() / 3600.0,
+      None => return,
+    };
+    let mut used: Vec<(i32, f64)> = vec![];
+    for (device_id, points) in state.points.iter() {
+      let levels = match state.outputs.get(device_id) {
+        Some(v) => v,
+        None => continue,
+      };
+      for ((point_id, watts), level) in points.iter().zip(levels.iter()) {
+        if *level > 0 && *watts > 0.0 {
+          used.push((*point_id, *watts as f64 * *level as f64 / LIGHT_LEVEL_MAX as f64 * hours));
+        }
+      }
+    }
+    for (point_id, wh) in used {
+      *state.totals.entry(point_id).or_default() += wh;
+    }
+  }
+
+  /// Wh per point since the last sample with the start and end of the sampled period, starts a new one
+  pub fn take(&self) -> (NaiveDateTime, NaiveDateTime, Vec<(i32, f64)>) {
+    let now = Utc::now().naive_utc();
+    let mut state = self.state.lock().unwrap();
+    let started_at = std::mem::replace(&mut state.started_at, now);
+    let totals = state.totals.drain().collect();
+    (started_at, now, totals)
+  }
+}
+
+impl Default for EnergyMeter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn meter(points: Vec<(i32, Vec<(i32, f32)>)>) -> EnergyMeter {
+    let energy = EnergyMeter::new();
+    energy.set_points(points.into_iter().collect());
+    energy
+  }
+
+  // moves the last accumulation back, so the next one counts `hours`
+  fn wait(energy: &EnergyMeter, hours: f64) {
+    let mut state = energy.state.lock().unwrap();
+    let last = state.last.unwrap();
+    state.last = Some(last - Duration::from_secs_f64(hours * 3600.0));
+  }
+
+  fn totals(energy: &EnergyMeter) -> HashMap<i32, f64> {
+    energy.take().2.into_iter().collect()
+  }
+
+  fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 0.01
+  }
+
+  #[test]
+  fn levels_are_counted_by_watts_and_time() {
+    let energy = meter(vec![(1, vec![(10, 40.0), (11, 20.0), (12, 0.0)])]);
+    let frames = FrameCache::new();
+    frames.store(1, vec![LIGHT_LEVEL_MAX, LIGHT_LEVEL_MAX / 2, LIGHT_LEVEL_MAX]);
+    energy.accumulate(&frames);
+    wait(&energy, 2.0);
+    energy.accumulate(&frames);
+
+    let totals = totals(&energy);
+    assert!(close(totals[&10], 80.0));
+    assert!(close(totals[&11], 20.0));
+    assert_eq!(totals.get(&12), None);
+  }
+
+  #[test]
+  fn first_call_only_starts_the_clock() {
+    let energy = meter(vec![(1, vec![(10, 40.0)])]);
+    let frames = FrameCache::new();
+    frames.store(1, vec![LIGHT_LEVEL_MAX]);
+    energy.accumulate(&frames);
+    assert!(totals(&energy).is_empty());
+  }
+
+  #[test]
+  fn forgotten_frames_keep_their_last_output() {
+    let energy = meter(vec![(1, vec![(10, 40.0)])]);
+    let frames = FrameCache::new();
+    frames.store(1, vec![LIGHT_LEVEL_MAX]);
+    energy.accumulate(&frames);
+    frames.forget(1);
+    wait(&energy, 1.0);
+    energy.accumulate(&frames);
+    assert!(close(totals(&energy)[&10], 40.0));
+  }
+
+  #[test]
+  fn unknown_devices_are_not_counted() {
+    let energy = meter(vec![(1, vec![(10, 40.0)])]);
+    let frames = FrameCache::new();
+    energy.accumulate(&frames);
+    wait(&energy, 1.0);
+    energy.accumulate(&frames);
+    assert!(totals(&energy).is_empty());
+  }
+
+  #[test]
+  fn totals_of_removed_points_are_kept_until_taken() {
+    let energy = meter(vec![(1, vec![(10, 40.0)]), (2, vec![(20, 10.0)])]);
+    let frames = FrameCache::new();
+    frames.store(1, vec![LIGHT_LEVEL_MAX]);
+    frames.store(2, vec![LIGHT_LEVEL_MAX]);
+    energy.accumulate(&frames);
+    wait(&energy, 1.0);
+    energy.accumulate(&frames);
+    energy.set_points(vec![(2, vec![(20, 10.0)])].into_iter().collect());
+    wait(&energy, 1.0);
+    energy.accumulate(&frames);
+
+    let totals = totals(&energy);
+    assert!(close(totals[&10], 40.0));
+    assert!(close(totals[&20], 20.0));
+  }
+
+  #[test]
+  fn take_starts_a_new_period() {
+    let energy = meter(vec![(1, vec![(10, 40.0)])]);
+    let frames = FrameCache::new();
+    frames.store(1, vec![LIGHT_LEVEL_MAX]);
+    energy.accumulate(&frames);
+    wait(&energy, 1.0);
+    energy.accumulate(&frames);
+
+    let (started_at, sampled_at, first) = energy.take();
+    assert!(started_at <= sampled_at);
+    assert_eq!(first.len(), 1);
+    let (next_start, _, next) = energy.take();
+    assert_eq!(next_start, sampled_at);
+    assert!(next.is_empty());
+  }
+}
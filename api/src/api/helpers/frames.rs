@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use std::sync::{
+  Arc,
+  Mutex,
+};
+
+
+/// Totals of frames the dispatcher did not have to write
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct FrameStats {
+  pub frames_sent: u64,
+  pub frames_skipped: u64,
+  pub bytes_saved: u64,
+  pub transactions_saved: u64,
+}
+
+/// Last level frame written to every device
+#[derive(Clone)]
+pub struct FrameCache {
+  sent: Arc<Mutex<HashMap<i32, Vec<i32>>>>,
+  stats: Arc<Mutex<FrameStats>>,
+}
+
+
+impl FrameCache {
+  pub fn new() -> Self {
+    FrameCache {
+      sent: Arc::new(Mutex::new(HashMap::new())),
+      stats: Arc::new(Mutex::new(FrameStats::default())),
+    }
+  }
+
+  /// `true` when `levels` differ from what was last written to `device_id`
+  pub fn changed(&self, device_id: i32, levels: &[i32]) -> bool {
+    let lock = self.sent.lock().unwrap();
+    match lock.get(&device_id) {
+      Some(v) => v.as_slice() != levels,
+      None => true,
+    }
+  }
+
+  /// Frame last written to `device_id`
+  pub fn last(&self, device_id: i32) -> Option<Vec<i32>> {
+    self.sent.lock().unwrap().get(&device_id).cloned()
+  }
+
+  pub fn store(&self, device_id: i32, levels: Vec<i32>) {
+    self.sent.lock().unwrap().insert(device_id, levels);
+    self.stats.lock().unwrap().frames_sent += 1;
+  }
+
+  /// Forces next dispatch to write `device_id`
+  pub fn forget(&self, device_id: i32) {
+    self.sent.lock().unwrap().remove(&device_id);
+  }
+
+  pub fn clear(&self) {
+    self.sent.lock().unwrap().clear();
+  }
+
+  pub fn skip(&self, bytes: usize) {
+    let mut lock = self.stats.lock().unwrap();
+    lock.frames_skipped += 1;
+    lock.bytes_saved += bytes as u64;
+    lock.transactions_saved += 1;
+  }
+
+  pub fn stats(&self) -> FrameStats {
+    *self.stats.lock().unwrap()
+  }
+}
+
+impl Default for FrameCache {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stored_frames_are_unchanged_until_levels_differ() {
+    let frames = FrameCache::new();
+    assert!(frames.changed(1, &[1, 2]));
+    frames.store(1, vec![1, 2]);
+    assert!(!frames.changed(1, &[1, 2]));
+    assert!(frames.changed(1, &[1, 3]));
+    assert!(frames.changed(1, &[1, 2, 0]));
+    assert!(frames.changed(2, &[1, 2]));
+  }
+
+  #[test]
+  fn forgotten_frames_are_changed_again() {
+    let frames = FrameCache::new();
+    frames.store(1, vec![1]);
+    frames.store(2, vec![2]);
+    frames.forget(1);
+    assert!(frames.changed(1, &[1]));
+    assert_eq!(frames.last(1), None);
+    assert_eq!(frames.last(2), Some(vec![2]));
+    assert!(!frames.changed(2, &[2]));
+    frames.clear();
+    assert!(frames.changed(2, &[2]));
+  }
+
+  #[test]
+  fn stats_count_sent_and_skipped_frames() {
+    let frames = FrameCache::new();
+    frames.store(1, vec![1, 2]);
+    frames.skip(4);
+    frames.skip(6);
+    let stats = frames.stats();
+    assert_eq!((stats.frames_sent, stats.frames_skipped, stats.bytes_saved, stats.transactions_saved), (1, 2, 10, 2));
+  }
+
+  #[test]
+  fn clones_share_frames_and_stats() {
+    let frames = FrameCache::new();
+    let other = frames.clone();
+    other.store(1, vec![1]);
+    assert!(!frames.changed(1, &[1]));
+    assert_eq!(frames.stats().frames_sent, 1);
+  }
+}
@@ -1,35 +1,76 @@
+pub mod bus;
+pub mod capabilities;
+#[cfg(target_os = "linux")]
+pub mod linux;
+pub mod scan;
+#[cfg(target_os = "linux")]
+pub mod serial;
+pub mod simulator;
+
 use std::collections::HashMap;
-use std::io::{self, ErrorKind};
-use i2cdev::core::*;
-use i2cdev::linux::{
-  LinuxI2CBus,
-  LinuxI2CError,
-  LinuxI2CMessage,
-};
+use std::thread;
+use std::time::Duration;
 use crate::models::{NewDevices, Points};
+use capabilities::Capabilities;
+use scan::{
+    ScanCache,
+    ScanEntry,
+    ScanReport,
+    ScanSettings,
+};
+use bus::{
+    BoxedBus,
+    BusConfig,
+    BusError,
+    EndpointCache,
+    LightBus,
+};
+use super::curves::Curve;
 use super::props::{
     I2C_LIGHT_LEVEL_START_OFFSET,
     I2C_RANGE_MIN,
     I2C_RANGE_MAX,
     I2C_BYTES_PER_LIGHT,
+    I2C_CAPABILITY_OFFSET,
+    I2C_FIRMWARE_VERSION_LENGTH,
+    I2C_LIGHT_CONTROLLER,
+    I2C_PRESENCE_DETECTOR,
+    I2C_PRESENCE_STATE_START_OFFSET,
+    I2C_PWM_FREQUENCY_REGISTER,
+    I2C_ADDRESS_REGISTER,
+    I2C_ADDRESS_CHANGE_ATTEMPTS,
+    I2C_ADDRESS_CHANGE_INTERVAL_MS,
     LIGHT_LEVEL_MAX,
 };
 
 /// Light device interface
 /// 
-/// Testing device: `255`
-pub struct LightDevices {
-    driver: Driver,
+/// Generic over the bus backend, see [`BusConfig`] for runtime selection
+pub struct LightDevices<B: LightBus = BoxedBus> {
+    bus: B,
+    bus_id: i32,
+    /// `i2c` or `serial`
+    transport: &'static str,
+    endpoint_counts: EndpointCache,
+    scan: ScanSettings,
+    scan_report: ScanCache,
 }
 
 impl LightDevices {
-    pub fn new(connection: u8) -> Result<Self, LinuxI2CError> {
-        let driver = Driver::new(connection)?;
-        Ok(Self { driver })
+    pub fn new(config: &BusConfig) -> Result<Self, BusError> {
+        let bus = config.open()?;
+        Ok(Self {
+            bus,
+            bus_id: config.id,
+            transport: config.transport(),
+            endpoint_counts: config.endpoint_counts.clone(),
+            scan: config.scan.clone(),
+            scan_report: config.scan_report.clone(),
+        })
     }
 
-    pub fn test(connection: u8) -> Result<(), LinuxI2CError> {
-        Driver::new(connection)?;
+    pub fn test(config: &BusConfig) -> Result<(), BusError> {
+        config.open()?;
         Ok(())
     }
 
@@ -49,7 +90,7 @@ impl LightDevices {
             let mut val_collection: Vec<i32> = vec![];
             for point in points {
                 let val = match override_active || point.active {
-                    true => point.val.clone(),
+                    true => Curve::from_point(&point).apply(point.val),
                     false => 0,
                 };
                 val_collection.push(val);
@@ -58,168 +99,479 @@ impl LightDevices {
         }
         result
     }
+}
 
-    pub fn controllers(&mut self) -> Result<Vec<NewDevices>, LinuxI2CError> {
-        let result = self.get_controller_identities()
-            ?.iter()
-            .map(|(adr, endpoint_count)| NewDevices {
-                adr: adr.clone() as i32,
-                endpoint_count: endpoint_count.clone() as i32,
-            })
-            .collect();
+impl<B: LightBus> LightDevices<B> {
+    pub fn with_bus(bus_id: i32, bus: B) -> Self {
+        Self {
+            bus,
+            bus_id,
+            transport: "i2c",
+            endpoint_counts: EndpointCache::default(),
+            scan: ScanSettings::default(),
+            scan_report: ScanCache::default(),
+        }
+    }
+
+    pub fn controllers(&mut self) -> Result<Vec<NewDevices>, BusError> {
+        let mut result: Vec<NewDevices> = vec![];
+        for (adr, device_type, endpoint_count) in self.get_controller_identities()? {
+            let capabilities = self.get_capabilities(adr).unwrap_or_else(|err| {
+                log::warn!("Failed to read capabilities of {} on bus {}, assuming legacy firmware: {}", adr, self.bus_id, err);
+                Capabilities::default()
+            });
+            result.push(NewDevices {
+                adr: adr as i32,
+                endpoint_count: endpoint_count as i32,
+                device_type: device_type as i32,
+                bus: self.bus_id,
+                protocol_version: capabilities.protocol_version as i32,
+                features: capabilities.features as i32,
+                firmware_version: capabilities.firmware_version,
+                transport: self.transport.to_string(),
+            });
+        }
         Ok(result)
     }
 
-    pub fn get_light_levels(&mut self, address: u16) -> Result<Vec<i32>, LinuxI2CError> {
-        let light_bits = self.get_light_controller_light_levels(address)?;
-        let mut result: Vec<i32> = vec![];
-        // should not ever be a non unsigned int number
-        let result_lenght = light_bits.len() as u8 / I2C_BYTES_PER_LIGHT;
-        for i in 0..result_lenght {
-            let offset: usize = (i as usize) * 2;
-            result.push((((light_bits[offset] as u16) << 8) | light_bits[offset + 1] as u16) as i32);
+    /// PWM frequency in Hz, only on controllers advertising `I2C_FEATURE_PWM_FREQUENCY`
+    pub fn get_pwm_frequency(&mut self, address: u16) -> Result<u16, BusError> {
+        let block = self.bus.read_block(address, I2C_PWM_FREQUENCY_REGISTER, 2)
+            .map_err(|err| self.forget(address, err))?;
+        Ok(u16::from_be_bytes([block[0], block[1]]))
+    }
+
+    pub fn set_pwm_frequency(&mut self, address: u16, frequency: u16) -> Result<(), BusError> {
+        let [high, low] = frequency.to_be_bytes();
+        self.bus.write(address, &[I2C_PWM_FREQUENCY_REGISTER, high, low])
+            .map_err(|err| self.forget(address, err))
+    }
+
+    /// `true` when anything acknowledges on `address`
+    pub fn probe(&mut self, address: u16) -> bool {
+        self.bus.read_register(address, 0).is_ok()
+    }
+
+    /// Moves controller advertising `I2C_FEATURE_ADDRESS_CHANGE` to `new_address`
+    ///
+    /// Addresses outside the scan range or already answering are refused with `InvalidInput`.
+    /// Succeeds once a light controller answers on the new address.
+    pub fn set_address(&mut self, address: u16, new_address: u16) -> Result<(), BusError> {
+        if !(I2C_RANGE_MIN..I2C_RANGE_MAX).contains(&new_address) || self.probe(new_address) {
+            return Err(BusError::InvalidInput);
         }
+        let target = new_address as u8;
+        self.bus.write(address, &[I2C_ADDRESS_REGISTER, target, !target])
+            .map_err(|err| self.forget(address, err))?;
+        let endpoint_count = self.endpoint_counts.lock().unwrap().remove(&address);
+
+        let mut last_error = BusError::Timeout;
+        for _ in 0..I2C_ADDRESS_CHANGE_ATTEMPTS {
+            match self.bus.read_register(new_address, 0) {
+                Ok(v) if v == I2C_LIGHT_CONTROLLER => {
+                    if let Some(count) = endpoint_count {
+                        self.endpoint_counts.lock().unwrap().insert(new_address, count);
+                    }
+                    return Ok(());
+                },
+                Ok(_) => return Err(BusError::UnknownDevice),
+                Err(err) => last_error = err,
+            }
+            thread::sleep(Duration::from_millis(I2C_ADDRESS_CHANGE_INTERVAL_MS));
+        }
+        Err(last_error)
+    }
+
+    /// Reads capability block, legacy firmware comes back as protocol version 0
+    pub fn get_capabilities(&mut self, address: u16) -> Result<Capabilities, BusError> {
+        let block = self.bus.read_block(address, I2C_CAPABILITY_OFFSET, 4 + I2C_FIRMWARE_VERSION_LENGTH as usize)
+            .map_err(|err| self.forget(address, err))?;
+        Ok(Capabilities::from_block(&block))
+    }
+
+    pub fn get_light_levels(&mut self, address: u16) -> Result<Vec<i32>, BusError> {
+        let light_bits = self.get_light_controller_light_levels(address)?;
+        let result = light_bits.chunks(I2C_BYTES_PER_LIGHT as usize)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]) as i32)
+            .collect();
         Ok(result)
     }
 
-    pub fn set_light_levels(&mut self, address: u16, values: Vec<i32>) -> Result<(), LinuxI2CError> {
+    pub fn set_light_levels(&mut self, address: u16, values: Vec<i32>) -> Result<(), BusError> {
         let endpoint_count = (self.get_controller_endpoint_count(address)?) as usize;
         if endpoint_count < values.len() {
-            return Err(
-                LinuxI2CError::Io(io::Error::from(ErrorKind::InvalidInput))
-            );
+            return Err(BusError::InvalidInput);
         }
         if endpoint_count > values.len() {
-            return Err(
-                LinuxI2CError::Io(io::Error::from(ErrorKind::InvalidInput))
-            );
+            return Err(BusError::InvalidInput);
         }
-        if values.iter().any(|val| val.clone() < 0 || val.clone() > LIGHT_LEVEL_MAX) {
-            return Err(
-                LinuxI2CError::Io(io::Error::from(ErrorKind::InvalidInput))
-            );
+        if values.iter().any(|val| *val < 0 || *val > LIGHT_LEVEL_MAX) {
+            return Err(BusError::InvalidInput);
         }
 
-        let converted_values = values.iter().map(|v| (v.clone() as u16).to_be_bytes()).flatten().collect::<Vec<_>>();
-        self.driver.write_bytes(address, converted_values)?;
+        let converted_values = values.iter().flat_map(|v| (*v as u16).to_be_bytes()).collect::<Vec<_>>();
+        self.bus.write(address, &converted_values)
+            .map_err(|err| self.forget(address, err))?;
 
         Ok(())
     }
 
-    fn get_light_controller_light_levels(&mut self, address: u16) -> Result<Vec<u8>, LinuxI2CError> {
-        let endpoint_count = self.get_controller_endpoint_count(address)? * I2C_BYTES_PER_LIGHT;
-        let mut result: Vec<u8> = vec![];
-        for i in 0..endpoint_count {
-            let level = self.driver.read_byte(address, i + I2C_LIGHT_LEVEL_START_OFFSET)?;
-            result.push(level);
+    /// Occupancy of every sensor on presence detector
+    pub fn get_presence_states(&mut self, address: u16) -> Result<Vec<bool>, BusError> {
+        let states = self.read_endpoint_registers(address, I2C_PRESENCE_DETECTOR, 1, I2C_PRESENCE_STATE_START_OFFSET)?;
+        Ok(states.iter().map(|v| *v != 0).collect())
+    }
+
+    fn get_light_controller_light_levels(&mut self, address: u16) -> Result<Vec<u8>, BusError> {
+        self.read_endpoint_registers(address, I2C_LIGHT_CONTROLLER, I2C_BYTES_PER_LIGHT, I2C_LIGHT_LEVEL_START_OFFSET)
+    }
+
+    /// Reads every endpoint register of a device
+    ///
+    /// With a cached endpoint count identity, count and registers are fetched in one combined transfer,
+    /// otherwise (or when the device no longer matches the cache) identity and registers take one transfer each.
+    fn read_endpoint_registers(&mut self, address: u16, device_type: u8, bytes_per_endpoint: u8, start_offset: u8) -> Result<Vec<u8>, BusError> {
+        if let Some(cached_count) = self.cached_endpoint_count(address, device_type) {
+            let register_length = cached_count as usize * bytes_per_endpoint as usize;
+            let data = self.bus.read_block(address, 0x00, start_offset as usize + register_length)
+                .map_err(|err| self.forget(address, err))?;
+            if data[0] == device_type && data[1] == cached_count {
+                return Ok(data[start_offset as usize..].to_vec());
+            }
+            self.endpoint_counts.lock().unwrap().remove(&address);
         }
-        Ok(result)
+        let endpoint_count = self.get_device_endpoint_count(address, device_type)?;
+        self.bus.read_block(address, start_offset, endpoint_count as usize * bytes_per_endpoint as usize)
+            .map_err(|err| self.forget(address, err))
     }
 
-    fn get_controller_identities(&mut self) -> Result<Vec<(u16, u8)>, LinuxI2CError> {
-        let mut device_map: Vec<(u16, u8)> = vec![];
-        for i in I2C_RANGE_MIN..I2C_RANGE_MAX {
-            let device_type = match self.driver.read_byte(i, 0x00) {
+    /// (address, device type, endpoint count) of every supported device on the bus
+    /// Probes addresses allowed by scan settings and records every acknowledgement in the scan report
+    ///
+    /// Chips that answered with a foreign identifier before are not touched again,
+    /// neither are devices on fixed addresses, they are listed as they answered last time.
+    fn get_controller_identities(&mut self) -> Result<Vec<(u16, u8, u8)>, BusError> {
+        let previous = self.scan_report.lock().unwrap().entries.clone();
+        let addresses = self.scan.addresses();
+        let mut entries: Vec<ScanEntry> = vec![];
+        let mut device_map: Vec<(u16, u8, u8)> = vec![];
+        for i in addresses.iter().copied() {
+            if let Some(entry) = previous.iter().find(|v| v.adr == i && (!v.known || self.scan.is_fixed(i))) {
+                if entry.known {
+                    device_map.push((i, entry.identifier, entry.endpoint_count));
+                }
+                entries.push(ScanEntry { probed: false, ..entry.clone() });
+                continue;
+            }
+            let identity = match self.bus.read_block(i, 0x00, 2) {
                 Ok(v) => v,
-                Err(_) => continue
+                Err(_) => {
+                    self.endpoint_counts.lock().unwrap().remove(&i);
+                    continue;
+                },
             };
-            if device_type != 0x10 {
+            let device_type = identity[0];
+            let known = device_type == I2C_LIGHT_CONTROLLER || device_type == I2C_PRESENCE_DETECTOR;
+            let endpoint_count = if known { identity[1] } else { 0 };
+            entries.push(ScanEntry { adr: i, identifier: device_type, known, endpoint_count, probed: true });
+            if !known {
+                self.endpoint_counts.lock().unwrap().remove(&i);
                 continue;
             }
-            let controlled_count = self.get_controller_endpoint_count(i)?;
-            device_map.push((i, controlled_count));
+            self.endpoint_counts.lock().unwrap().insert(i, (device_type, identity[1]));
+            device_map.push((i, device_type, identity[1]));
         }
+        *self.scan_report.lock().unwrap() = ScanReport {
+            bus: self.bus_id,
+            scanned_at: Some(chrono::Utc::now().naive_utc()),
+            addresses: addresses.len(),
+            entries,
+        };
         Ok(device_map)
     }
 
-    fn get_controller_endpoint_count(&mut self, address: u16) -> Result<u8, LinuxI2CError> {
-        let identifier = self.driver.read_byte(address, 0x00)?;
-        if identifier != 0x10 {
-            return Err(
-                LinuxI2CError::Io(io::Error::from(ErrorKind::AddrNotAvailable))
-            );
+    fn get_controller_endpoint_count(&mut self, address: u16) -> Result<u8, BusError> {
+        if let Some(cached_count) = self.cached_endpoint_count(address, I2C_LIGHT_CONTROLLER) {
+            return Ok(cached_count);
         }
-        let result = self.driver.read_byte(address, 0x01)?;
-        return Ok(result);
+        self.get_device_endpoint_count(address, I2C_LIGHT_CONTROLLER)
+    }
+
+    /// Reads identity and endpoint count in one transfer, refreshing the cache
+    fn get_device_endpoint_count(&mut self, address: u16, device_type: u8) -> Result<u8, BusError> {
+        let identity = self.bus.read_block(address, 0x00, 2)
+            .map_err(|err| self.forget(address, err))?;
+        if identity[0] != device_type {
+            self.endpoint_counts.lock().unwrap().remove(&address);
+            return Err(BusError::UnknownDevice);
+        }
+        self.endpoint_counts.lock().unwrap().insert(address, (device_type, identity[1]));
+        Ok(identity[1])
+    }
+
+    fn cached_endpoint_count(&self, address: u16, device_type: u8) -> Option<u8> {
+        self.endpoint_counts.lock().unwrap().get(&address)
+            .filter(|(cached_type, _)| *cached_type == device_type)
+            .map(|(_, count)| *count)
     }
-}
 
-struct Driver {
-    con: Option<LinuxI2CBus>,
+    /// Drops cached endpoint count of a device that failed a transfer
+    fn forget(&self, address: u16, err: BusError) -> BusError {
+        self.endpoint_counts.lock().unwrap().remove(&address);
+        err
+    }
 }
 
-impl Driver {
-    pub fn new(connection: u8) -> Result<Self, LinuxI2CError> {
-        if connection == 255 {
-            return Ok(Self { con: None });
-        }
-        let con = match LinuxI2CBus::new(format!("/dev/i2c-{}", connection)) {
-            Ok(v) => Ok(v),
-            Err(_) => {
-                match LinuxI2CBus::new(format!("/dev/i2c/{}", connection)) {
-                    Ok(v) => Ok(v),
-                    Err(err) => Err(err)
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::simulator::Simulator;
+    use std::collections::HashMap;
+
+    /// Registers of every address, keeps what was written and counts transfers
+    #[derive(Default)]
+    struct RecordingBus {
+        registers: HashMap<u16, Vec<u8>>,
+        writes: Vec<(u16, Vec<u8>)>,
+        transfers: usize,
+    }
+
+    impl LightBus for RecordingBus {
+        fn read_register(&mut self, address: u16, register: u8) -> Result<u8, BusError> {
+            Ok(self.read_block(address, register, 1)?[0])
+        }
+
+        fn read_block(&mut self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+            self.transfers += 1;
+            let registers = self.registers.get(&address).ok_or(BusError::Nack)?;
+            Ok((0..length).map(|i| registers.get(register as usize + i).copied().unwrap_or(0)).collect())
+        }
+
+        fn write(&mut self, address: u16, values: &[u8]) -> Result<(), BusError> {
+            if !self.registers.contains_key(&address) {
+                return Err(BusError::Nack);
             }
-        }?;
-        Ok(Self { con: Some(con) })
-    }
-
-    pub fn read_byte(&mut self, address: u16, value: u8) -> Result<u8, LinuxI2CError> {
-        match self.con {
-            Some(_) => {
-                let res = self.read_device_byte(address, value)?;
-                Ok(res)
-            },
-            None => {
-                match address {
-                    0x0008 | 0x0009 => {
-                        let res = match value {
-                            0 => 0x10,
-                            1 => 0x0f,
-                            _ => 0x00,
-                        };
-                        Ok(res)
-                    },
-                    _ => {
-                        Err(LinuxI2CError::Io(io::Error::from(ErrorKind::AddrNotAvailable)))
-                    },
-                }
-            },
+            self.writes.push((address, values.to_vec()));
+            Ok(())
         }
     }
 
-    fn read_device_byte(&mut self, address: u16, value: u8) -> Result<u8, LinuxI2CError> {
-        if self.con.is_none() {
-            return Err(LinuxI2CError::Io(io::Error::from(ErrorKind::AddrNotAvailable)));
-        }
-        let mut read_data = [0];
-        let mut msgs = [
-            LinuxI2CMessage::write(&[value]).with_address(address),
-            LinuxI2CMessage::read(&mut read_data).with_address(address),
-        ];
-        self.con.as_mut().unwrap().transfer(&mut msgs)?;
-        Ok(read_data[0])
+    fn devices(registers: &[(u16, Vec<u8>)]) -> LightDevices<RecordingBus> {
+        LightDevices::with_bus(1, RecordingBus {
+            registers: registers.iter().cloned().collect(),
+            ..RecordingBus::default()
+        })
     }
 
-    pub fn write_bytes(&mut self, address: u16, values: Vec<u8>) -> Result<(), LinuxI2CError> {
-        match self.con {
-            Some(_) => {
-                self.write_device_bytes(address, values)?;
-            },
-            None => {},
-        }
-        Ok(())
+    #[test]
+    fn simulator_backend_answers_with_default_devices() {
+        let mut light_devices = LightDevices::new(&BusConfig::new("simulator", 1, "", "").unwrap()).unwrap();
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| (v.adr, v.endpoint_count)).collect::<Vec<_>>(), vec![(0x08, 15), (0x09, 15)]);
+    }
+
+    #[test]
+    fn discovers_only_light_controllers() {
+        let mut light_devices = devices(&[(0x08, vec![0x10, 2]), (0x20, vec![0x42, 3])]);
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| (v.adr, v.endpoint_count)).collect::<Vec<_>>(), vec![(0x08, 2)]);
+    }
+
+    #[test]
+    fn levels_are_written_big_endian() {
+        let mut light_devices = devices(&[(0x08, vec![0x10, 2])]);
+        light_devices.set_light_levels(0x08, vec![1, 0x1234]).unwrap();
+        assert_eq!(light_devices.bus.writes, vec![(0x08, vec![0x00, 0x01, 0x12, 0x34])]);
+    }
+
+    #[test]
+    fn levels_are_read_from_level_registers() {
+        let mut light_devices = devices(&[(0x08, vec![0x10, 2, 0x00, 0x01, 0x12, 0x34])]);
+        assert_eq!(light_devices.get_light_levels(0x08).unwrap(), vec![1, 0x1234]);
+    }
+
+    #[test]
+    fn frames_not_matching_the_controller_are_refused() {
+        let mut light_devices = devices(&[(0x08, vec![0x10, 2]), (0x20, vec![0x42, 3])]);
+        assert!(matches!(light_devices.set_light_levels(0x08, vec![1]), Err(BusError::InvalidInput)));
+        assert!(matches!(light_devices.set_light_levels(0x08, vec![1, 2, 3]), Err(BusError::InvalidInput)));
+        assert!(matches!(light_devices.set_light_levels(0x08, vec![1, LIGHT_LEVEL_MAX + 1]), Err(BusError::InvalidInput)));
+        assert!(matches!(light_devices.set_light_levels(0x20, vec![1, 2, 3]), Err(BusError::UnknownDevice)));
+        assert!(matches!(light_devices.set_light_levels(0x30, vec![1]), Err(BusError::Nack)));
+        assert!(light_devices.bus.writes.is_empty());
+    }
+
+    #[test]
+    fn discovers_presence_detectors_next_to_controllers() {
+        let simulator = Simulator::from_spec("0x08:2,0x21:3:presence").unwrap();
+        let mut light_devices = LightDevices::with_bus(3, simulator);
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| (v.adr, v.device_type, v.endpoint_count)).collect::<Vec<_>>(), vec![
+            (0x08, I2C_LIGHT_CONTROLLER as i32, 2),
+            (0x21, I2C_PRESENCE_DETECTOR as i32, 3),
+        ]);
+        assert!(found.iter().all(|v| v.bus == 3));
+    }
+
+    #[test]
+    fn discovery_reads_capabilities_and_tolerates_legacy_firmware() {
+        let simulator = Simulator::from_spec("0x08:2,0x09:2:legacy").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator);
+        let found = light_devices.controllers().unwrap();
+        let capabilities = found.iter()
+            .map(|v| (v.adr, v.protocol_version, v.firmware_version.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(capabilities, vec![
+            (0x08, 1, Some("simulator".to_string())),
+            (0x09, 0, None),
+        ]);
+    }
+
+    #[test]
+    fn presence_states_follow_occupancy() {
+        let simulator = Simulator::from_spec("0x08:2,0x21:3:presence").unwrap();
+        assert!(simulator.set_occupancy(0x21, 1, true));
+        assert!(!simulator.set_occupancy(0x21, 3, true));
+        assert!(!simulator.set_occupancy(0x08, 0, true));
+        let mut light_devices = LightDevices::with_bus(1, simulator);
+        assert_eq!(light_devices.get_presence_states(0x21).unwrap(), vec![false, true, false]);
+        assert!(matches!(light_devices.get_presence_states(0x08), Err(BusError::UnknownDevice)));
+        assert!(matches!(light_devices.set_light_levels(0x21, vec![1, 2, 3]), Err(BusError::UnknownDevice)));
+    }
+
+    #[test]
+    fn known_endpoint_count_reads_levels_in_one_transfer() {
+        let mut light_devices = devices(&[(0x08, vec![0x10, 2, 0x00, 0x01, 0x12, 0x34])]);
+        assert_eq!(light_devices.get_light_levels(0x08).unwrap(), vec![1, 0x1234]);
+        assert_eq!(light_devices.bus.transfers, 2);
+        light_devices.bus.transfers = 0;
+        assert_eq!(light_devices.get_light_levels(0x08).unwrap(), vec![1, 0x1234]);
+        assert_eq!(light_devices.bus.transfers, 1);
+    }
+
+    #[test]
+    fn discovery_fills_the_endpoint_cache() {
+        let mut light_devices = devices(&[(0x08, vec![0x10, 2]), (0x20, vec![0x42, 3])]);
+        light_devices.controllers().unwrap();
+        assert_eq!(light_devices.cached_endpoint_count(0x08, I2C_LIGHT_CONTROLLER), Some(2));
+        assert_eq!(light_devices.cached_endpoint_count(0x20, I2C_LIGHT_CONTROLLER), None);
+        light_devices.bus.transfers = 0;
+        light_devices.set_light_levels(0x08, vec![1, 2]).unwrap();
+        assert_eq!(light_devices.bus.transfers, 0);
+    }
+
+    #[test]
+    fn replaced_device_is_read_again() {
+        let simulator = Simulator::from_spec("0x08:2").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        light_devices.controllers().unwrap();
+        simulator.add_device(0x08, 3);
+        assert_eq!(light_devices.get_light_levels(0x08).unwrap(), vec![0, 0, 0]);
+        assert_eq!(light_devices.cached_endpoint_count(0x08, I2C_LIGHT_CONTROLLER), Some(3));
+        light_devices.set_light_levels(0x08, vec![1, 2, 3]).unwrap();
+    }
+
+    #[test]
+    fn failed_transfer_forgets_the_endpoint_count() {
+        let simulator = Simulator::from_spec("0x08:2").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        light_devices.controllers().unwrap();
+        simulator.inject(0x08, super::simulator::Fault::Nack(1));
+        assert!(matches!(light_devices.get_light_levels(0x08), Err(BusError::Nack)));
+        assert_eq!(light_devices.cached_endpoint_count(0x08, I2C_LIGHT_CONTROLLER), None);
+        assert_eq!(light_devices.get_light_levels(0x08).unwrap(), vec![0, 0]);
+    }
+
+    #[test]
+    fn foreign_chips_are_reported_and_left_alone_afterwards() {
+        let simulator = Simulator::from_spec("0x08:2,0x50:0xa5:chip").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator);
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| v.adr).collect::<Vec<_>>(), vec![0x08]);
+
+        let report = light_devices.scan_report.lock().unwrap().clone();
+        let entries = report.entries.iter().map(|v| (v.adr, v.identifier, v.known, v.probed)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(0x08, I2C_LIGHT_CONTROLLER, true, true), (0x50, 0xa5, false, true)]);
+        assert_eq!(report.addresses, (I2C_RANGE_MIN..I2C_RANGE_MAX).count());
+        assert!(report.scanned_at.is_some());
+
+        light_devices.controllers().unwrap();
+        let report = light_devices.scan_report.lock().unwrap().clone();
+        assert!(!report.entries.iter().find(|v| v.adr == 0x50).unwrap().probed);
+        assert!(report.entries.iter().find(|v| v.adr == 0x08).unwrap().probed);
     }
 
-    fn write_device_bytes(&mut self, address: u16, values: Vec<u8>) -> Result<(), LinuxI2CError> {
-        if self.con.is_none() {
-            return Err(LinuxI2CError::Io(io::Error::from(ErrorKind::AddrNotAvailable)));
+    #[test]
+    fn discovery_only_probes_allowed_addresses() {
+        let simulator = Simulator::from_spec("0x08:2,0x09:2,0x20:1:presence").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator);
+        light_devices.scan = scan::ScanSettings::new("0x08-0x0f", "0x09", "").unwrap();
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| v.adr).collect::<Vec<_>>(), vec![0x08]);
+        assert_eq!(light_devices.scan_report.lock().unwrap().addresses, 7);
+    }
+
+    #[test]
+    fn fixed_devices_are_only_probed_until_they_answered() {
+        let simulator = Simulator::from_spec("0x08:2,0x20:1:presence").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        light_devices.scan = scan::ScanSettings::new("", "", "0x08,0x20,0x30").unwrap();
+        light_devices.controllers().unwrap();
+        let report = light_devices.scan_report.lock().unwrap().clone();
+        let entries = report.entries.iter().map(|v| (v.adr, v.endpoint_count, v.probed)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(0x08, 2, true), (0x20, 1, true)]);
+
+        // nothing answers anymore, yet fixed devices are still listed
+        simulator.inject(0x08, super::simulator::Fault::Nack(10));
+        simulator.inject(0x20, super::simulator::Fault::Nack(10));
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| (v.adr, v.device_type)).collect::<Vec<_>>(), vec![(0x08, I2C_LIGHT_CONTROLLER as i32), (0x20, I2C_PRESENCE_DETECTOR as i32)]);
+        let report = light_devices.scan_report.lock().unwrap().clone();
+        let entries = report.entries.iter().map(|v| (v.adr, v.endpoint_count, v.probed)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(0x08, 2, false), (0x20, 1, false)]);
+    }
+
+    #[test]
+    fn moved_controller_keeps_its_levels() {
+        let simulator = Simulator::from_spec("0x08:2").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        light_devices.controllers().unwrap();
+        light_devices.set_light_levels(0x08, vec![1, 0x1234]).unwrap();
+        light_devices.set_address(0x08, 0x10).unwrap();
+        assert!(!light_devices.probe(0x08));
+        assert_eq!(simulator.levels(0x10), Some(vec![1, 0x1234]));
+        assert_eq!(light_devices.cached_endpoint_count(0x08, I2C_LIGHT_CONTROLLER), None);
+        assert_eq!(light_devices.cached_endpoint_count(0x10, I2C_LIGHT_CONTROLLER), Some(2));
+    }
+
+    #[test]
+    fn address_of_another_device_is_refused() {
+        let simulator = Simulator::from_spec("0x08:2,0x09:3,0x50:0xa5:chip").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        assert!(matches!(light_devices.set_address(0x08, 0x09), Err(BusError::InvalidInput)));
+        assert!(matches!(light_devices.set_address(0x08, 0x50), Err(BusError::InvalidInput)));
+        assert_eq!(simulator.levels(0x08), Some(vec![0, 0]));
+        assert_eq!(simulator.levels(0x09), Some(vec![0, 0, 0]));
+        assert!(light_devices.probe(0x50));
+    }
+
+    #[test]
+    fn address_outside_the_scan_range_is_refused() {
+        let simulator = Simulator::from_spec("0x08:2").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        for target in [0x00, I2C_RANGE_MIN - 1, I2C_RANGE_MAX, 0x7f] {
+            assert!(matches!(light_devices.set_address(0x08, target), Err(BusError::InvalidInput)));
         }
-        let mut msgs = [
-            LinuxI2CMessage::write(&values).with_address(address),
-        ];
-        self.con.as_mut().unwrap().transfer(&mut msgs)?;
-        Ok(())
+        light_devices.set_address(0x08, I2C_RANGE_MAX - 1).unwrap();
+        assert!(light_devices.probe(I2C_RANGE_MAX - 1));
+    }
+
+    #[test]
+    fn firmware_without_address_change_stays() {
+        let simulator = Simulator::from_spec("0x08:2:legacy").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        assert!(matches!(light_devices.set_address(0x08, 0x10), Err(BusError::Nack)));
+        assert!(light_devices.probe(0x08));
+        assert!(!light_devices.probe(0x10));
     }
 }
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+#[cfg(target_os = "linux")]
+use super::linux::LinuxBus;
+#[cfg(target_os = "linux")]
+use super::serial::{
+    spawn_pty,
+    SerialBus,
+};
+use super::super::props::SERIAL_BUS_OFFSET;
+use super::scan::{
+    ScanCache,
+    ScanSettings,
+};
+use super::simulator::{
+    Simulator,
+    SIMULATOR_DEFAULT_SPEC,
+};
+
+/// Boxed bus used when the backend is picked at runtime from config
+pub type BoxedBus = Box<dyn LightBus + Send>;
+
+/// Register level access to light controllers
+///
+/// Every transaction follows `rpi_pico/main.py` protocol:
+/// writing a single byte moves the device register counter,
+/// reading auto increments it.
+pub trait LightBus {
+    /// Reads one register from device at `address`
+    fn read_register(&mut self, address: u16, register: u8) -> Result<u8, BusError>;
+
+    /// Reads `length` consecutive registers starting from `register`
+    ///
+    /// Defaults to one transaction per register, backends able to do combined transfers should override it.
+    fn read_block(&mut self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+        let mut result: Vec<u8> = vec![];
+        for i in 0..length {
+            result.push(self.read_register(address, register.wrapping_add(i as u8))?);
+        }
+        Ok(result)
+    }
+
+    /// Writes raw bytes to device at `address`
+    fn write(&mut self, address: u16, values: &[u8]) -> Result<(), BusError>;
+}
+
+impl<B: LightBus + ?Sized> LightBus for Box<B> {
+    fn read_register(&mut self, address: u16, register: u8) -> Result<u8, BusError> {
+        (**self).read_register(address, register)
+    }
+
+    fn read_block(&mut self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+        (**self).read_block(address, register, length)
+    }
+
+    fn write(&mut self, address: u16, values: &[u8]) -> Result<(), BusError> {
+        (**self).write(address, values)
+    }
+}
+
+/// Bus backend selection
+///
+/// Picked with `I2C_BACKEND` (`linux` by default), `I2C` holds the linux bus number.
+/// `simulator` (alias `mock`) devices are described by `I2C_SIMULATOR` and `I2C_SIMULATOR_FAULTS`,
+/// for compatibility `I2C=255` still selects the simulator.
+/// `SERIAL` adds USB serial ports, see `SerialBus`.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    Linux(u8),
+    Simulator(Simulator),
+    /// terminal path
+    Serial(String),
+}
+
+/// (device type, endpoint count) by device address, shared between every bus opened from the same config
+pub type EndpointCache = Arc<Mutex<HashMap<u16, (u8, u8)>>>;
+
+#[derive(Debug, Clone)]
+pub struct BusConfig {
+    /// bus number stored on devices
+    pub id: i32,
+    pub backend: Backend,
+    pub endpoint_counts: EndpointCache,
+    pub scan: ScanSettings,
+    pub scan_report: ScanCache,
+}
+
+impl BusConfig {
+    pub fn new(backend: &str, connection: u8, simulator_spec: &str, simulator_faults: &str) -> Result<Self, BusError> {
+        let simulator = || -> Result<Backend, BusError> {
+            let spec = match simulator_spec.trim().is_empty() {
+                true => SIMULATOR_DEFAULT_SPEC,
+                false => simulator_spec,
+            };
+            let simulator = Simulator::from_spec(spec)?;
+            simulator.inject_spec(simulator_faults)?;
+            Ok(Backend::Simulator(simulator))
+        };
+        let backend = match backend.trim().to_lowercase().as_str() {
+            "" | "linux" if connection == 255 => simulator()?,
+            "" | "linux" => Backend::Linux(connection),
+            "simulator" | "mock" => simulator()?,
+            _ => return Err(BusError::InvalidInput),
+        };
+        Ok(Self {
+            id: connection as i32,
+            backend,
+            endpoint_counts: EndpointCache::default(),
+            scan: ScanSettings::default(),
+            scan_report: ScanCache::default(),
+        })
+    }
+
+    /// Serial port numbered `SERIAL_BUS_OFFSET + index`
+    ///
+    /// `pty` opens a pseudo-terminal answered by simulated devices from `simulator_spec`.
+    pub fn serial(index: usize, path: &str, simulator_spec: &str) -> Result<Self, BusError> {
+        let path = match path.trim() {
+            "" => return Err(BusError::InvalidInput),
+            "pty" => {
+                let spec = match simulator_spec.trim().is_empty() {
+                    true => SIMULATOR_DEFAULT_SPEC,
+                    false => simulator_spec,
+                };
+                open_pty(Simulator::from_spec(spec)?)?
+            },
+            v => v.to_string(),
+        };
+        Ok(Self {
+            id: SERIAL_BUS_OFFSET + index as i32,
+            backend: Backend::Serial(path),
+            endpoint_counts: EndpointCache::default(),
+            scan: ScanSettings::default(),
+            scan_report: ScanCache::default(),
+        })
+    }
+
+    pub fn open(&self) -> Result<BoxedBus, BusError> {
+        match &self.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Linux(connection) => Ok(Box::new(LinuxBus::new(*connection)?)),
+            #[cfg(not(target_os = "linux"))]
+            Backend::Linux(_) => Err(BusError::Io(io::Error::from(ErrorKind::Unsupported))),
+            Backend::Simulator(simulator) => Ok(Box::new(simulator.clone())),
+            #[cfg(target_os = "linux")]
+            Backend::Serial(path) => Ok(Box::new(SerialBus::new(path)?)),
+            #[cfg(not(target_os = "linux"))]
+            Backend::Serial(_) => Err(BusError::Io(io::Error::from(ErrorKind::Unsupported))),
+        }
+    }
+
+    /// Transport stored on devices
+    pub fn transport(&self) -> &'static str {
+        match self.backend {
+            Backend::Serial(_) => "serial",
+            _ => "i2c",
+        }
+    }
+}
+
+/// Every configured bus
+///
+/// `I2C` lists bus numbers separated by `,`, e.g. `1,3`.
+/// Simulator specs and faults are given per bus in the same order, separated by `;`.
+#[derive(Debug, Clone)]
+pub struct Buses {
+    configs: Vec<BusConfig>,
+}
+
+impl Buses {
+    pub fn new(backend: &str, connections: &str, simulator_specs: &str, simulator_faults: &str) -> Result<Self, BusError> {
+        let specs = simulator_specs.split(';').collect::<Vec<_>>();
+        let faults = simulator_faults.split(';').collect::<Vec<_>>();
+        let mut configs: Vec<BusConfig> = vec![];
+        for (index, connection) in connections.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()).enumerate() {
+            let connection = connection.parse::<u8>().map_err(|_| BusError::InvalidInput)?;
+            if configs.iter().any(|v| v.id == connection as i32) {
+                return Err(BusError::InvalidInput);
+            }
+            configs.push(BusConfig::new(
+                backend,
+                connection,
+                specs.get(index).unwrap_or(&""),
+                faults.get(index).unwrap_or(&""),
+            )?);
+        }
+        Ok(Self { configs })
+    }
+
+    /// Adds serial ports listed in `SERIAL` separated by `,`, simulator specs of `pty` ports separated by `;`
+    pub fn with_serial(mut self, ports: &str, simulator_specs: &str) -> Result<Self, BusError> {
+        let specs = simulator_specs.split(';').collect::<Vec<_>>();
+        for (index, port) in ports.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()).enumerate() {
+            self.configs.push(BusConfig::serial(index, port, specs.get(index).unwrap_or(&""))?);
+        }
+        Ok(self)
+    }
+
+    /// Applies scan settings given per bus in the same order, separated by `;`
+    pub fn with_scan(mut self, include: &str, exclude: &str, fixed: &str) -> Result<Self, BusError> {
+        let include = include.split(';').collect::<Vec<_>>();
+        let exclude = exclude.split(';').collect::<Vec<_>>();
+        let fixed = fixed.split(';').collect::<Vec<_>>();
+        for (index, config) in self.configs.iter_mut().enumerate() {
+            config.scan = ScanSettings::new(
+                include.get(index).unwrap_or(&""),
+                exclude.get(index).unwrap_or(&""),
+                fixed.get(index).unwrap_or(&""),
+            )?;
+        }
+        Ok(self)
+    }
+
+    pub fn get(&self, id: i32) -> Option<&BusConfig> {
+        self.configs.iter().find(|v| v.id == id)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, BusConfig> {
+        self.configs.iter()
+    }
+}
+
+#[derive(Debug)]
+pub enum BusError {
+    /// Address did not acknowledge
+    Nack,
+    Timeout,
+    /// Device answered with an identifier other than expected
+    UnknownDevice,
+    InvalidInput,
+    Io(io::Error),
+}
+
+impl BusError {
+    /// Short machine readable error kind, stored on devices
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BusError::Nack => "nack",
+            BusError::Timeout => "timeout",
+            BusError::UnknownDevice => "unknown_device",
+            BusError::InvalidInput => "invalid_input",
+            BusError::Io(_) => "io",
+        }
+    }
+}
+
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BusError::Nack => write!(f, "device did not acknowledge"),
+            BusError::Timeout => write!(f, "bus timed out"),
+            BusError::UnknownDevice => write!(f, "unexpected device identifier"),
+            BusError::InvalidInput => write!(f, "invalid input"),
+            BusError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+impl From<io::Error> for BusError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::TimedOut => BusError::Timeout,
+            ErrorKind::AddrNotAvailable => BusError::Nack,
+            _ => match e.raw_os_error() {
+                // ENXIO, EREMOTEIO: nothing answered on address
+                Some(6) | Some(121) => BusError::Nack,
+                _ => BusError::Io(e),
+            },
+        }
+    }
+}
+
+/// Decimal or `0x` prefixed hex number
+pub(super) fn parse_number(value: &str) -> Option<u16> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u16>().ok(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_pty(simulator: Simulator) -> Result<String, BusError> {
+    spawn_pty(simulator)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_pty(_: Simulator) -> Result<String, BusError> {
+    Err(BusError::Io(io::Error::from(ErrorKind::Unsupported)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_is_picked_from_config() {
+        let backend = |name: &str, connection: u8| BusConfig::new(name, connection, "", "").map(|v| v.backend);
+        assert!(matches!(backend("", 1), Ok(Backend::Linux(1))));
+        assert!(matches!(backend(" Linux ", 3), Ok(Backend::Linux(3))));
+        assert!(matches!(backend("", 255), Ok(Backend::Simulator(_))));
+        assert!(matches!(backend("mock", 1), Ok(Backend::Simulator(_))));
+        assert!(matches!(backend("spi", 1), Err(BusError::InvalidInput)));
+    }
+
+    #[test]
+    fn simulator_specs_are_checked() {
+        let config = BusConfig::new("simulator", 1, "0x20:4", "0x20:nack:1").unwrap();
+        let simulator = match config.backend {
+            Backend::Simulator(v) => v,
+            _ => panic!("simulator expected"),
+        };
+        assert_eq!(simulator.levels(0x20), Some(vec![0; 4]));
+        assert_eq!(simulator.levels(0x08), None);
+        assert!(matches!(BusConfig::new("simulator", 1, "0x20", ""), Err(BusError::InvalidInput)));
+        assert!(matches!(BusConfig::new("simulator", 1, "", "0x08:flicker"), Err(BusError::InvalidInput)));
+    }
+
+    #[test]
+    fn buses_are_listed_with_their_own_specs() {
+        let buses = Buses::new("simulator", "1, 3", "0x08:2;0x09:1", ";0x09:nack").unwrap();
+        assert_eq!(buses.iter().map(|v| v.id).collect::<Vec<_>>(), vec![1, 3]);
+        let simulator = |id: i32| match &buses.get(id).unwrap().backend {
+            Backend::Simulator(v) => v.clone(),
+            _ => panic!("simulator expected"),
+        };
+        assert_eq!(simulator(1).levels(0x08), Some(vec![0; 2]));
+        assert_eq!(simulator(1).levels(0x09), None);
+        assert_eq!(simulator(3).levels(0x09), Some(vec![0]));
+        assert!(buses.get(2).is_none());
+    }
+
+    #[test]
+    fn bad_bus_lists_are_refused() {
+        assert!(matches!(Buses::new("", "1,1", "", ""), Err(BusError::InvalidInput)));
+        assert!(matches!(Buses::new("", "1,x", "", ""), Err(BusError::InvalidInput)));
+        assert!(matches!(Buses::new("", "256", "", ""), Err(BusError::InvalidInput)));
+        assert!(matches!(Buses::new("simulator", "1,2", "0x08:1;0x08", ""), Err(BusError::InvalidInput)));
+    }
+
+    #[test]
+    fn scan_settings_are_given_per_bus() {
+        let buses = Buses::new("simulator", "1,3", "", "").unwrap()
+            .with_scan("0x08-0x09;", "", ";0x20")
+            .unwrap();
+        assert_eq!(buses.get(1).unwrap().scan.addresses(), vec![0x08, 0x09]);
+        assert_eq!(buses.get(3).unwrap().scan.addresses(), vec![0x20]);
+        assert!(Buses::new("simulator", "1", "", "").unwrap().with_scan("0x08-0x80", "", "").is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn serial_ports_follow_i2c_buses() {
+        let buses = Buses::new("", "1", "", "").unwrap().with_serial("pty, pty", "0x08:1").unwrap();
+        let listed = buses.iter().map(|v| (v.id, v.transport())).collect::<Vec<_>>();
+        assert_eq!(listed, vec![(1, "i2c"), (SERIAL_BUS_OFFSET, "serial"), (SERIAL_BUS_OFFSET + 1, "serial")]);
+        let serial_only = Buses::new("", "", "", "").unwrap().with_serial("pty", "").unwrap();
+        assert_eq!(serial_only.iter().map(|v| v.id).collect::<Vec<_>>(), vec![SERIAL_BUS_OFFSET]);
+        assert!(Buses::new("", "", "", "").unwrap().with_serial("pty", "0x08").is_err());
+    }
+
+    #[test]
+    fn missing_devices_map_to_nack() {
+        assert!(matches!(BusError::from(io::Error::from_raw_os_error(6)), BusError::Nack));
+        assert!(matches!(BusError::from(io::Error::from_raw_os_error(121)), BusError::Nack));
+        assert!(matches!(BusError::from(io::Error::from(ErrorKind::TimedOut)), BusError::Timeout));
+        assert!(matches!(BusError::from(io::Error::from(ErrorKind::PermissionDenied)), BusError::Io(_)));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::models::Devices;
+use super::super::props::{
+    I2C_CAPABILITY_MAGIC,
+    I2C_FIRMWARE_VERSION_LENGTH,
+};
+
+/// Versioned capability block advertised by newer firmware
+///
+/// Layout from `I2C_CAPABILITY_OFFSET`:
+/// magic, protocol version, feature flags, firmware version length, firmware version (ascii).
+/// Legacy firmware answers zeros there and is treated as protocol version 0 without features.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    pub protocol_version: u8,
+    pub features: u8,
+    pub firmware_version: Option<String>,
+}
+
+impl Capabilities {
+    pub fn from_block(block: &[u8]) -> Self {
+        if block.len() < 4 || block[0] != I2C_CAPABILITY_MAGIC || block[1] == 0 {
+            return Self::default();
+        }
+        let version_length = (block[3] as usize)
+            .min(I2C_FIRMWARE_VERSION_LENGTH as usize)
+            .min(block.len() - 4);
+        let firmware_version = String::from_utf8(block[4..4 + version_length].to_vec())
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        Self {
+            protocol_version: block[1],
+            features: block[2],
+            firmware_version,
+        }
+    }
+
+    pub fn to_block(&self) -> Vec<u8> {
+        if self.protocol_version == 0 {
+            return vec![0; 4 + I2C_FIRMWARE_VERSION_LENGTH as usize];
+        }
+        let version = self.firmware_version.clone().unwrap_or_default();
+        let version_bytes = version.as_bytes()
+            .iter()
+            .take(I2C_FIRMWARE_VERSION_LENGTH as usize)
+            .copied()
+            .collect::<Vec<u8>>();
+        let mut block = vec![I2C_CAPABILITY_MAGIC, self.protocol_version, self.features, version_bytes.len() as u8];
+        block.extend(version_bytes);
+        block.resize(4 + I2C_FIRMWARE_VERSION_LENGTH as usize, 0);
+        block
+    }
+
+    /// `true` when every flag in `feature` is advertised
+    pub fn supports(&self, feature: u8) -> bool {
+        self.protocol_version > 0 && self.features & feature == feature
+    }
+}
+
+/// Capabilities stored on the device record at discovery
+impl From<&Devices> for Capabilities {
+    fn from(device: &Devices) -> Self {
+        Self {
+            protocol_version: device.protocol_version as u8,
+            features: device.features as u8,
+            firmware_version: device.firmware_version.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_round_trips() {
+        let capabilities = Capabilities {
+            protocol_version: 2,
+            features: 0b101,
+            firmware_version: Some("1.4.0".to_string()),
+        };
+        let block = capabilities.to_block();
+        assert_eq!(block.len(), 4 + I2C_FIRMWARE_VERSION_LENGTH as usize);
+        assert_eq!(&block[..4], &[I2C_CAPABILITY_MAGIC, 2, 0b101, 5]);
+        assert_eq!(Capabilities::from_block(&block), capabilities);
+    }
+
+    #[test]
+    fn legacy_firmware_has_no_features() {
+        let legacy = Capabilities::default();
+        assert_eq!(legacy.to_block(), vec![0; 4 + I2C_FIRMWARE_VERSION_LENGTH as usize]);
+        assert_eq!(Capabilities::from_block(&legacy.to_block()), legacy);
+        assert_eq!(Capabilities::from_block(&[I2C_CAPABILITY_MAGIC, 0, 0xff, 0]), legacy);
+        assert_eq!(Capabilities::from_block(&[0xff, 1, 0xff, 0]), legacy);
+        assert_eq!(Capabilities::from_block(&[I2C_CAPABILITY_MAGIC, 1]), legacy);
+        assert!(!legacy.supports(0));
+    }
+
+    #[test]
+    fn firmware_version_is_cut_to_the_block() {
+        let long = Capabilities {
+            protocol_version: 1,
+            features: 0,
+            firmware_version: Some("x".repeat(40)),
+        };
+        let parsed = Capabilities::from_block(&long.to_block());
+        assert_eq!(parsed.firmware_version, Some("x".repeat(I2C_FIRMWARE_VERSION_LENGTH as usize)));
+        assert_eq!(Capabilities::from_block(&[I2C_CAPABILITY_MAGIC, 1, 0, 9, b'a', b'b']).firmware_version, Some("ab".to_string()));
+        assert_eq!(Capabilities::from_block(&[I2C_CAPABILITY_MAGIC, 1, 0, 2, b' ', b' ']).firmware_version, None);
+    }
+
+    #[test]
+    fn supports_needs_every_flag() {
+        let capabilities = Capabilities {
+            protocol_version: 1,
+            features: 0b011,
+            firmware_version: None,
+        };
+        assert!(capabilities.supports(0b001));
+        assert!(capabilities.supports(0b011));
+        assert!(!capabilities.supports(0b110));
+    }
+}
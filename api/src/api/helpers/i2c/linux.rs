@@ -0,0 +1,61 @@
+use i2cdev::core::*;
+use i2cdev::linux::{
+    LinuxI2CBus,
+    LinuxI2CError,
+    LinuxI2CMessage,
+};
+use super::bus::{
+    BusError,
+    LightBus,
+};
+
+/// `/dev/i2c-*` backed bus
+pub struct LinuxBus {
+    con: LinuxI2CBus,
+}
+
+impl LinuxBus {
+    pub fn new(connection: u8) -> Result<Self, BusError> {
+        let con = match LinuxI2CBus::new(format!("/dev/i2c-{}", connection)) {
+            Ok(v) => v,
+            Err(_) => LinuxI2CBus::new(format!("/dev/i2c/{}", connection))?,
+        };
+        Ok(Self { con })
+    }
+}
+
+impl LightBus for LinuxBus {
+    fn read_register(&mut self, address: u16, register: u8) -> Result<u8, BusError> {
+        let mut read_data = [0];
+        let mut msgs = [
+            LinuxI2CMessage::write(&[register]).with_address(address),
+            LinuxI2CMessage::read(&mut read_data).with_address(address),
+        ];
+        self.con.transfer(&mut msgs)?;
+        Ok(read_data[0])
+    }
+
+    fn read_block(&mut self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+        let mut read_data = vec![0; length];
+        let mut msgs = [
+            LinuxI2CMessage::write(&[register]).with_address(address),
+            LinuxI2CMessage::read(&mut read_data).with_address(address),
+        ];
+        self.con.transfer(&mut msgs)?;
+        Ok(read_data)
+    }
+
+    fn write(&mut self, address: u16, values: &[u8]) -> Result<(), BusError> {
+        let mut msgs = [
+            LinuxI2CMessage::write(values).with_address(address),
+        ];
+        self.con.transfer(&mut msgs)?;
+        Ok(())
+    }
+}
+
+impl From<LinuxI2CError> for BusError {
+    fn from(e: LinuxI2CError) -> Self {
+        BusError::from(std::io::Error::from(e))
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use super::bus::{
+    parse_number,
+    BusError,
+};
+use super::super::props::{
+    I2C_RANGE_MAX,
+    I2C_RANGE_MIN,
+};
+
+/// Addresses discovery is allowed to probe, `I2C_RANGE_MAX` itself is not one of them
+///
+/// `I2C_SCAN` lists include ranges and single addresses, e.g. `0x08-0x0f,0x20`,
+/// `I2C_SCAN_EXCLUDE` addresses never touched and `I2C_SCAN_FIXED` addresses of known devices.
+/// Fixed addresses are only probed until they answered, later scans keep what they reported.
+/// With fixed addresses and no include ranges only the fixed ones are scanned.
+#[derive(Debug, Clone)]
+pub struct ScanSettings {
+    include: Vec<(u16, u16)>,
+    exclude: Vec<u16>,
+    fixed: Vec<u16>,
+}
+
+impl ScanSettings {
+    pub fn new(include: &str, exclude: &str, fixed: &str) -> Result<Self, BusError> {
+        let fixed = parse_addresses(fixed)?;
+        let include = match include.trim().is_empty() {
+            true if !fixed.is_empty() => vec![],
+            true => vec![(I2C_RANGE_MIN, I2C_RANGE_MAX - 1)],
+            false => parse_ranges(include)?,
+        };
+        Ok(Self {
+            include,
+            exclude: parse_addresses(exclude)?,
+            fixed,
+        })
+    }
+
+    /// Whether `address` belongs to a known device that is not probed again
+    pub fn is_fixed(&self, address: u16) -> bool {
+        self.fixed.contains(&address)
+    }
+
+    /// Sorted addresses to scan
+    pub fn addresses(&self) -> Vec<u16> {
+        let mut result = self.include.iter()
+            .flat_map(|(from, to)| *from..=*to)
+            .chain(self.fixed.iter().copied())
+            .filter(|v| !self.exclude.contains(v))
+            .collect::<Vec<u16>>();
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            include: vec![(I2C_RANGE_MIN, I2C_RANGE_MAX - 1)],
+            exclude: vec![],
+            fixed: vec![],
+        }
+    }
+}
+
+/// Address that acknowledged during a scan
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanEntry {
+    pub adr: u16,
+    /// register 0x00 content
+    pub identifier: u8,
+    /// light controller or presence detector
+    pub known: bool,
+    /// register 0x01 content of known devices
+    pub endpoint_count: u8,
+    /// `false` for unknown chips and fixed addresses carried over from an earlier scan without touching them
+    pub probed: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    pub bus: i32,
+    pub scanned_at: Option<NaiveDateTime>,
+    /// amount of addresses allowed by scan settings
+    pub addresses: usize,
+    pub entries: Vec<ScanEntry>,
+}
+
+/// Last scan of a bus, shared between every bus opened from the same config and stored after discovery
+pub type ScanCache = Arc<Mutex<ScanReport>>;
+
+fn parse_address(value: &str) -> Result<u16, BusError> {
+    let address = parse_number(value).ok_or(BusError::InvalidInput)?;
+    match (I2C_RANGE_MIN..I2C_RANGE_MAX).contains(&address) {
+        true => Ok(address),
+        false => Err(BusError::InvalidInput),
+    }
+}
+
+fn parse_addresses(spec: &str) -> Result<Vec<u16>, BusError> {
+    spec.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(parse_address)
+        .collect()
+}
+
+fn parse_ranges(spec: &str) -> Result<Vec<(u16, u16)>, BusError> {
+    let mut result: Vec<(u16, u16)> = vec![];
+    for item in spec.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let range = match item.split_once('-') {
+            Some((from, to)) => (parse_address(from)?, parse_address(to)?),
+            None => (parse_address(item)?, parse_address(item)?),
+        };
+        if range.0 > range.1 {
+            return Err(BusError::InvalidInput);
+        }
+        result.push(range);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_range_is_scanned_by_default() {
+        let addresses = ScanSettings::new("", "", "").unwrap().addresses();
+        assert_eq!(addresses, (I2C_RANGE_MIN..I2C_RANGE_MAX).collect::<Vec<_>>());
+        assert_eq!(addresses.last(), Some(&0x76));
+        assert_eq!(ScanSettings::default().addresses(), addresses);
+    }
+
+    #[test]
+    fn ranges_and_single_addresses_are_merged() {
+        let settings = ScanSettings::new("0x20, 0x08-0x0a, 9-12", "0x0b", "0x30").unwrap();
+        assert_eq!(settings.addresses(), vec![0x08, 0x09, 0x0a, 0x0c, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn fixed_addresses_alone_limit_the_scan() {
+        let settings = ScanSettings::new("", "", "0x21,0x08").unwrap();
+        assert_eq!(settings.addresses(), vec![0x08, 0x21]);
+        assert!(settings.is_fixed(0x21));
+        assert!(!settings.is_fixed(0x09));
+        assert!(!ScanSettings::default().is_fixed(0x08));
+    }
+
+    #[test]
+    fn whole_range_can_be_given() {
+        let settings = ScanSettings::new("0x08-0x76", "", "0x76").unwrap();
+        assert_eq!(settings.addresses(), ScanSettings::default().addresses());
+    }
+
+    #[test]
+    fn exclusion_wins_over_fixed_and_included() {
+        let settings = ScanSettings::new("0x08-0x09", "0x08,0x20", "0x20").unwrap();
+        assert_eq!(settings.addresses(), vec![0x09]);
+        // exclusions are single addresses
+        assert!(ScanSettings::new("", "0x08-0x0a", "").is_err());
+        let settings = ScanSettings::new("", " 0x10 , 0x11,", "").unwrap();
+        assert!(!settings.addresses().contains(&0x10));
+        assert!(!settings.addresses().contains(&0x11));
+        assert_eq!(settings.addresses().len(), ScanSettings::default().addresses().len() - 2);
+    }
+
+    #[test]
+    fn bad_specs_are_refused() {
+        assert!(ScanSettings::new("0x10-0x08", "", "").is_err());
+        assert!(ScanSettings::new("0x00-0x08", "", "").is_err());
+        assert!(ScanSettings::new("0x08-0x77", "", "").is_err());
+        assert!(ScanSettings::new("0x08-0x78", "", "").is_err());
+        assert!(ScanSettings::new("", "0x77", "").is_err());
+        assert!(ScanSettings::new("0x08-", "", "").is_err());
+        assert!(ScanSettings::new("", "eight", "").is_err());
+        assert!(ScanSettings::new("", "", "0x7f").is_err());
+        assert!(ScanSettings::new("", "", "0x07").is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::ffi::CStr;
+use std::fs::{
+    File,
+    OpenOptions,
+};
+use std::io::{
+    self,
+    ErrorKind,
+    Read,
+    Write,
+};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{
+    AsRawFd,
+    FromRawFd,
+    RawFd,
+};
+use std::thread;
+use super::bus::{
+    BusError,
+    LightBus,
+};
+use super::simulator::Simulator;
+use super::super::props::{
+    SERIAL_FRAME_REQUEST,
+    SERIAL_FRAME_RESPONSE,
+    SERIAL_OP_READ,
+    SERIAL_OP_WRITE,
+    SERIAL_STATUS_INVALID,
+    SERIAL_STATUS_NACK,
+    SERIAL_STATUS_OK,
+    SERIAL_TIMEOUT_TICKS,
+};
+
+/// Controller behind a USB serial (CDC-ACM) port
+///
+/// Register transactions are framed, every request gets one response:
+/// request `0xA5, op, address, length, payload, crc`, response `0x5A, status, length, data, crc`.
+/// Read requests carry `register, count` as payload, writes carry the raw bytes an i2c write would.
+/// Crc is CRC-8 (polynomial 0x07) over everything between start byte and crc.
+pub struct SerialBus {
+    port: File,
+}
+
+impl SerialBus {
+    pub fn new(path: &str) -> Result<Self, BusError> {
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        set_raw(port.as_raw_fd())?;
+        Ok(Self { port })
+    }
+
+    fn transaction(&mut self, op: u8, address: u16, payload: &[u8]) -> Result<Vec<u8>, BusError> {
+        if address > u8::MAX as u16 || payload.len() > u8::MAX as usize {
+            return Err(BusError::InvalidInput);
+        }
+        // whatever is left from a timed out transaction would shift every later response
+        unsafe { libc::tcflush(self.port.as_raw_fd(), libc::TCIFLUSH) };
+        let mut request = vec![op, address as u8, payload.len() as u8];
+        request.extend_from_slice(payload);
+        request.push(crc8(&request));
+        request.insert(0, SERIAL_FRAME_REQUEST);
+        self.port.write_all(&request)?;
+
+        let mut start = [0u8];
+        loop {
+            self.read_exact(&mut start)?;
+            if start[0] == SERIAL_FRAME_RESPONSE {
+                break;
+            }
+        }
+        let mut header = [0u8; 2];
+        self.read_exact(&mut header)?;
+        let mut data = vec![0u8; header[1] as usize + 1];
+        self.read_exact(&mut data)?;
+        let crc = data.pop().unwrap_or_default();
+        let mut checked = header.to_vec();
+        checked.extend_from_slice(&data);
+        if crc8(&checked) != crc {
+            return Err(BusError::Io(io::Error::new(ErrorKind::InvalidData, "serial frame checksum mismatch")));
+        }
+        match header[0] {
+            v if v == SERIAL_STATUS_OK => Ok(data),
+            v if v == SERIAL_STATUS_NACK => Err(BusError::Nack),
+            _ => Err(BusError::InvalidInput),
+        }
+    }
+
+    /// Reads exactly `buffer.len()` bytes, port reads return empty after a tick of silence
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), BusError> {
+        let mut filled = 0;
+        let mut idle = 0;
+        while filled < buffer.len() {
+            match self.port.read(&mut buffer[filled..])? {
+                0 => {
+                    idle += 1;
+                    if idle >= SERIAL_TIMEOUT_TICKS {
+                        return Err(BusError::Timeout);
+                    }
+                },
+                n => {
+                    filled += n;
+                    idle = 0;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LightBus for SerialBus {
+    fn read_register(&mut self, address: u16, register: u8) -> Result<u8, BusError> {
+        Ok(self.read_block(address, register, 1)?[0])
+    }
+
+    fn read_block(&mut self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+        if length > u8::MAX as usize {
+            return Err(BusError::InvalidInput);
+        }
+        let data = self.transaction(SERIAL_OP_READ, address, &[register, length as u8])?;
+        match data.len() == length {
+            true => Ok(data),
+            false => Err(BusError::InvalidInput),
+        }
+    }
+
+    fn write(&mut self, address: u16, values: &[u8]) -> Result<(), BusError> {
+        self.transaction(SERIAL_OP_WRITE, address, values)?;
+        Ok(())
+    }
+}
+
+/// Pseudo-terminal answering serial frames from `simulator`, stand-in for a controller on USB
+///
+/// Returns path of the terminal to open with `SerialBus`.
+pub fn spawn_pty(simulator: Simulator) -> Result<String, BusError> {
+    let mut master: RawFd = -1;
+    let mut slave: RawFd = -1;
+    let opened = unsafe {
+        libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null())
+    };
+    if opened != 0 {
+        return Err(BusError::Io(io::Error::last_os_error()));
+    }
+    set_raw(slave)?;
+    let name = unsafe { libc::ttyname(slave) };
+    if name.is_null() {
+        return Err(BusError::Io(io::Error::last_os_error()));
+    }
+    let path = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+
+    let mut port = unsafe { File::from_raw_fd(master) };
+    thread::spawn(move || {
+        // keeps the terminal alive between bus openings
+        let _slave = unsafe { File::from_raw_fd(slave) };
+        let mut simulator = simulator;
+        loop {
+            if let Err(e) = serve_frame(&mut port, &mut simulator) {
+                log::error!("Serial stand-in stopped: {}", e);
+                return;
+            }
+        }
+    });
+    Ok(path)
+}
+
+fn serve_frame(port: &mut File, simulator: &mut Simulator) -> io::Result<()> {
+    let mut start = [0u8];
+    port.read_exact(&mut start)?;
+    if start[0] != SERIAL_FRAME_REQUEST {
+        return Ok(());
+    }
+    let mut header = [0u8; 3];
+    port.read_exact(&mut header)?;
+    let mut payload = vec![0u8; header[2] as usize + 1];
+    port.read_exact(&mut payload)?;
+    let crc = payload.pop().unwrap_or_default();
+    let mut checked = header.to_vec();
+    checked.extend_from_slice(&payload);
+
+    let address = header[1] as u16;
+    let result = match (header[0], crc8(&checked) == crc) {
+        (_, false) => Err(BusError::InvalidInput),
+        (v, true) if v == SERIAL_OP_READ && payload.len() == 2 => simulator.read_block(address, payload[0], payload[1] as usize),
+        (v, true) if v == SERIAL_OP_WRITE => simulator.write(address, &payload).map(|_| vec![]),
+        _ => Err(BusError::InvalidInput),
+    };
+    let (status, data) = match result {
+        Ok(v) => (SERIAL_STATUS_OK, v),
+        Err(BusError::Nack) => (SERIAL_STATUS_NACK, vec![]),
+        // silence, like a controller that stopped answering
+        Err(BusError::Timeout) => return Ok(()),
+        Err(_) => (SERIAL_STATUS_INVALID, vec![]),
+    };
+    let mut response = vec![status, data.len() as u8];
+    response.extend_from_slice(&data);
+    response.push(crc8(&response));
+    response.insert(0, SERIAL_FRAME_RESPONSE);
+    port.write_all(&response)
+}
+
+/// Raw mode, reads return after one tick (0.1s) of silence
+fn set_raw(fd: RawFd) -> Result<(), BusError> {
+    unsafe {
+        let mut attributes: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut attributes) != 0 {
+            return Err(BusError::Io(io::Error::last_os_error()));
+        }
+        libc::cfmakeraw(&mut attributes);
+        libc::cfsetspeed(&mut attributes, libc::B115200);
+        attributes.c_cc[libc::VMIN] = 0;
+        attributes.c_cc[libc::VTIME] = 1;
+        if libc::tcsetattr(fd, libc::TCSANOW, &attributes) != 0 {
+            return Err(BusError::Io(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x07,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simulator::Fault;
+    use crate::api::helpers::i2c::LightDevices;
+    use crate::api::helpers::props::I2C_LIGHT_CONTROLLER;
+
+    fn serial_bus(spec: &str) -> (Simulator, SerialBus) {
+        let simulator = Simulator::from_spec(spec).unwrap();
+        let path = spawn_pty(simulator.clone()).unwrap();
+        (simulator, SerialBus::new(&path).unwrap())
+    }
+
+    #[test]
+    fn crc_matches_check_value() {
+        // CRC-8 check value over "123456789"
+        assert_eq!(crc8(b"123456789"), 0xf4);
+        assert_eq!(crc8(&[]), 0);
+    }
+
+    #[test]
+    fn frames_round_trip_through_pty() {
+        let (simulator, mut bus) = serial_bus("0x08:3");
+        assert_eq!(bus.read_block(0x08, 0x00, 2).unwrap(), vec![I2C_LIGHT_CONTROLLER, 3]);
+        bus.write(0x08, &[0x00, 0x01, 0x12, 0x34, 0xff, 0xff]).unwrap();
+        assert_eq!(simulator.levels(0x08), Some(vec![1, 0x1234, u16::MAX]));
+        assert_eq!(bus.read_register(0x08, 0x04).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn light_devices_work_over_serial() {
+        let (simulator, bus) = serial_bus("0x08:2,0x09:4");
+        let mut light_devices = LightDevices::with_bus(2, bus);
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| (v.adr, v.endpoint_count)).collect::<Vec<_>>(), vec![(0x08, 2), (0x09, 4)]);
+        light_devices.set_light_levels(0x09, vec![1, 2, 3, 4]).unwrap();
+        assert_eq!(simulator.levels(0x09), Some(vec![1, 2, 3, 4]));
+        assert_eq!(light_devices.get_light_levels(0x09).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn errors_come_back_over_serial() {
+        let (simulator, mut bus) = serial_bus("0x08:2");
+        assert!(matches!(bus.read_register(0x20, 0), Err(BusError::Nack)));
+        simulator.inject(0x08, Fault::Timeout(1));
+        assert!(matches!(bus.read_register(0x08, 0), Err(BusError::Timeout)));
+        // late bytes of the timed out transaction must not shift the next response
+        assert_eq!(bus.read_register(0x08, 0).unwrap(), I2C_LIGHT_CONTROLLER);
+        assert!(matches!(bus.read_block(0x08, 0, 300), Err(BusError::InvalidInput)));
+    }
+}
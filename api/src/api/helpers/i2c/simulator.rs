@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use super::capabilities::Capabilities;
+use super::bus::{
+    parse_number,
+    BusError,
+    LightBus,
+};
+use super::super::props::{
+    I2C_BYTES_PER_LIGHT,
+    I2C_CAPABILITY_OFFSET,
+    I2C_ADDRESS_REGISTER,
+    I2C_FEATURE_ADDRESS_CHANGE,
+    I2C_FEATURE_PWM_FREQUENCY,
+    I2C_RANGE_MAX,
+    I2C_RANGE_MIN,
+    I2C_PWM_FREQUENCY_REGISTER,
+    PWM_FREQUENCY_MAX,
+    PWM_FREQUENCY_MIN,
+    I2C_LIGHT_CONTROLLER,
+    I2C_PRESENCE_DETECTOR,
+};
+
+/// Devices used when no `I2C_SIMULATOR` spec is given
+pub static SIMULATOR_DEFAULT_SPEC: &str = "0x08:15,0x09:15";
+
+/// Protocol version advertised by simulated devices
+pub static SIMULATOR_PROTOCOL_VERSION: u8 = 1;
+/// PWM frequency simulated controllers start with, same as firmware
+pub static SIMULATOR_PWM_FREQUENCY: u16 = 1000;
+
+/// Fault injected into a simulated address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// address does not acknowledge for given amount of transactions
+    Nack(usize),
+    /// transactions time out for given amount of transactions
+    Timeout(usize),
+}
+
+/// In memory light controllers and presence detectors following `rpi_pico/main.py` register protocol
+///
+/// Clones share state, so every bus opened from the same config sees the same devices.
+#[derive(Debug, Clone, Default)]
+pub struct Simulator {
+    state: Arc<Mutex<HashMap<u16, SimulatedDevice>>>,
+    faults: Arc<Mutex<HashMap<u16, Fault>>>,
+}
+
+#[derive(Debug, Clone)]
+struct SimulatedDevice {
+    identifier: u8,
+    endpoint_count: u8,
+    counter: u8,
+    /// light levels for controllers, occupancy for presence detectors
+    registers: Vec<u8>,
+    /// capability block, zeros for legacy firmware
+    capabilities: Vec<u8>,
+    pwm_frequency: u16,
+}
+
+impl SimulatedDevice {
+    fn new(identifier: u8, endpoint_count: u8) -> Self {
+        let bytes_per_endpoint = match identifier {
+            v if v == I2C_LIGHT_CONTROLLER => I2C_BYTES_PER_LIGHT as usize,
+            _ => 1,
+        };
+        Self {
+            identifier,
+            endpoint_count,
+            counter: 0,
+            registers: vec![0; endpoint_count as usize * bytes_per_endpoint],
+            capabilities: Capabilities {
+                protocol_version: SIMULATOR_PROTOCOL_VERSION,
+                features: match identifier {
+                    v if v == I2C_LIGHT_CONTROLLER => I2C_FEATURE_PWM_FREQUENCY | I2C_FEATURE_ADDRESS_CHANGE,
+                    _ => 0,
+                },
+                firmware_version: Some("simulator".to_string()),
+            }.to_block(),
+            pwm_frequency: SIMULATOR_PWM_FREQUENCY,
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let transmitted = match self.counter {
+            0 => self.identifier,
+            1 => self.endpoint_count,
+            v if self.supports(I2C_FEATURE_PWM_FREQUENCY) && v == I2C_PWM_FREQUENCY_REGISTER => self.pwm_frequency.to_be_bytes()[0],
+            v if self.supports(I2C_FEATURE_PWM_FREQUENCY) && v == I2C_PWM_FREQUENCY_REGISTER + 1 => self.pwm_frequency.to_be_bytes()[1],
+            v if v >= I2C_CAPABILITY_OFFSET => self.capabilities.get((v - I2C_CAPABILITY_OFFSET) as usize).copied().unwrap_or(0),
+            v => self.registers.get(v as usize - 2).copied().unwrap_or(0),
+        };
+        self.counter = self.counter.wrapping_add(1);
+        transmitted
+    }
+
+    fn supports(&self, feature: u8) -> bool {
+        Capabilities::from_block(&self.capabilities).supports(feature)
+    }
+
+    /// Address requested by an address change write
+    fn address_change(&self, values: &[u8]) -> Option<u16> {
+        if values.len() != 3 || values[0] != I2C_ADDRESS_REGISTER || values[1] != !values[2] {
+            return None;
+        }
+        let target = values[1] as u16;
+        match self.supports(I2C_FEATURE_ADDRESS_CHANGE) && (I2C_RANGE_MIN..I2C_RANGE_MAX).contains(&target) {
+            true => Some(target),
+            false => None,
+        }
+    }
+
+    fn write(&mut self, values: &[u8]) {
+        if values.len() == 1 {
+            self.counter = values[0];
+        }
+        // register followed by u16 value, ignored by firmware without the feature
+        if values.len() == 3 && values[0] == I2C_PWM_FREQUENCY_REGISTER && self.supports(I2C_FEATURE_PWM_FREQUENCY) {
+            let frequency = u16::from_be_bytes([values[1], values[2]]);
+            if (PWM_FREQUENCY_MIN..=PWM_FREQUENCY_MAX).contains(&(frequency as i32)) {
+                self.pwm_frequency = frequency;
+            }
+        }
+        // on the right amount of bytes firmware updates pwm, anything else is dropped
+        if self.identifier == I2C_LIGHT_CONTROLLER && values.len() == self.registers.len() {
+            self.registers = values.to_vec();
+        }
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds simulator from `address:endpoint_count[:kind]` items separated by `,`
+    ///
+    /// Addresses can be written in decimal or `0x` prefixed hex,
+    /// kind is `light` (default), `legacy` (light controller without capability block), `presence`
+    /// or `chip` (foreign chip answering with endpoint_count as identifier),
+    /// e.g. `0x08:14,0x09:15:legacy,0x20:2:presence,0x50:0xa5:chip`.
+    pub fn from_spec(spec: &str) -> Result<Self, BusError> {
+        let simulator = Self::new();
+        for item in spec.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            let parts = item.split(':').collect::<Vec<_>>();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(BusError::InvalidInput);
+            }
+            let address = parse_number(parts[0]).ok_or(BusError::InvalidInput)?;
+            let endpoint_count = parse_number(parts[1]).ok_or(BusError::InvalidInput)?;
+            if endpoint_count > u8::MAX as u16 {
+                return Err(BusError::InvalidInput);
+            }
+            match parts.get(2).map(|v| v.trim().to_lowercase()).as_deref() {
+                None | Some("light") => simulator.add_device(address, endpoint_count as u8),
+                Some("legacy") => {
+                    simulator.add_device(address, endpoint_count as u8);
+                    simulator.set_capabilities(address, &Capabilities::default());
+                },
+                Some("presence") => simulator.add_presence_detector(address, endpoint_count as u8),
+                Some("chip") => simulator.add_chip(address, endpoint_count as u8),
+                _ => return Err(BusError::InvalidInput),
+            };
+        }
+        Ok(simulator)
+    }
+
+    /// Injects faults from `address:kind[:transactions]` items separated by `,`
+    ///
+    /// Kind is `nack` or `timeout`, without transaction count the fault never clears, e.g. `0x09:nack:3`.
+    pub fn inject_spec(&self, spec: &str) -> Result<(), BusError> {
+        for item in spec.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            let parts = item.split(':').collect::<Vec<_>>();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(BusError::InvalidInput);
+            }
+            let address = parse_number(parts[0]).ok_or(BusError::InvalidInput)?;
+            let transactions = match parts.get(2) {
+                Some(v) => v.trim().parse::<usize>().map_err(|_| BusError::InvalidInput)?,
+                None => usize::MAX,
+            };
+            let fault = match parts[1].trim().to_lowercase().as_str() {
+                "nack" => Fault::Nack(transactions),
+                "timeout" => Fault::Timeout(transactions),
+                _ => return Err(BusError::InvalidInput),
+            };
+            self.inject(address, fault);
+        }
+        Ok(())
+    }
+
+    /// Adds light controller to the bus, replacing whatever was on `address` with a dark one
+    pub fn add_device(&self, address: u16, endpoint_count: u8) {
+        self.state.lock().unwrap().insert(address, SimulatedDevice::new(I2C_LIGHT_CONTROLLER, endpoint_count));
+    }
+
+    /// Adds presence detector with `sensor_count` free sensors to the bus
+    pub fn add_presence_detector(&self, address: u16, sensor_count: u8) {
+        self.state.lock().unwrap().insert(address, SimulatedDevice::new(I2C_PRESENCE_DETECTOR, sensor_count));
+    }
+
+    /// Adds chip that is neither controller nor detector, it answers `identifier` on every register
+    pub fn add_chip(&self, address: u16, identifier: u8) {
+        let mut device = SimulatedDevice::new(identifier, identifier);
+        device.registers = vec![];
+        device.capabilities = Capabilities::default().to_block().iter().map(|_| identifier).collect();
+        self.state.lock().unwrap().insert(address, device);
+    }
+
+    /// Sets occupancy of presence detector sensor, returns `false` when there is no such sensor
+    pub fn set_occupancy(&self, address: u16, sensor: usize, occupied: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.get_mut(&address) {
+            Some(device) if device.identifier == I2C_PRESENCE_DETECTOR && sensor < device.registers.len() => {
+                device.registers[sensor] = occupied as u8;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Replaces advertised capabilities, `Capabilities::default()` behaves like legacy firmware
+    pub fn set_capabilities(&self, address: u16, capabilities: &Capabilities) -> bool {
+        match self.state.lock().unwrap().get_mut(&address) {
+            Some(device) => {
+                device.capabilities = capabilities.to_block();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes device from the bus, as if it lost power
+    pub fn remove_device(&self, address: u16) {
+        self.state.lock().unwrap().remove(&address);
+    }
+
+    pub fn inject(&self, address: u16, fault: Fault) {
+        self.faults.lock().unwrap().insert(address, fault);
+    }
+
+    pub fn clear_fault(&self, address: u16) {
+        self.faults.lock().unwrap().remove(&address);
+    }
+
+    /// Stored 16 bit levels of a light controller
+    pub fn levels(&self, address: u16) -> Option<Vec<u16>> {
+        self.state.lock().unwrap().get(&address)
+            .filter(|device| device.identifier == I2C_LIGHT_CONTROLLER)
+            .map(|device| {
+                device.registers.chunks(2)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                    .collect()
+            })
+    }
+
+    fn check_fault(&self, address: u16) -> Result<(), BusError> {
+        let mut faults = self.faults.lock().unwrap();
+        let (error, remaining) = match faults.get(&address) {
+            Some(Fault::Nack(v)) => (BusError::Nack, Fault::Nack(v.saturating_sub(1))),
+            Some(Fault::Timeout(v)) => (BusError::Timeout, Fault::Timeout(v.saturating_sub(1))),
+            None => return Ok(()),
+        };
+        match remaining {
+            Fault::Nack(0) | Fault::Timeout(0) => faults.remove(&address),
+            _ => faults.insert(address, remaining),
+        };
+        Err(error)
+    }
+
+    fn transfer(&self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+        self.check_fault(address)?;
+        let mut state = self.state.lock().unwrap();
+        let device = state.get_mut(&address).ok_or(BusError::Nack)?;
+        device.write(&[register]);
+        Ok((0..length).map(|_| device.read()).collect())
+    }
+}
+
+impl LightBus for Simulator {
+    fn read_register(&mut self, address: u16, register: u8) -> Result<u8, BusError> {
+        Ok(self.transfer(address, register, 1)?[0])
+    }
+
+    fn read_block(&mut self, address: u16, register: u8, length: usize) -> Result<Vec<u8>, BusError> {
+        self.transfer(address, register, length)
+    }
+
+    fn write(&mut self, address: u16, values: &[u8]) -> Result<(), BusError> {
+        self.check_fault(address)?;
+        let mut state = self.state.lock().unwrap();
+        let device = state.get_mut(&address).ok_or(BusError::Nack)?;
+        if let Some(target) = device.address_change(values) {
+            // like firmware, whatever already answered on target is shadowed
+            let device = state.remove(&address).ok_or(BusError::Nack)?;
+            state.insert(target, device);
+            return Ok(());
+        }
+        device.write(values);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::helpers::i2c::LightDevices;
+
+    #[test]
+    fn reads_auto_increment_from_the_requested_register() {
+        let mut simulator = Simulator::from_spec("0x08:2").unwrap();
+        simulator.write(0x08, &[0x01, 0x02, 0x03, 0x04]).unwrap();
+        assert_eq!(simulator.read_block(0x08, 0x00, 6).unwrap(), vec![0x10, 2, 0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(simulator.read_block(0x08, 0x03, 2).unwrap(), vec![0x02, 0x03]);
+        assert_eq!(simulator.read_register(0x08, 0x01).unwrap(), 2);
+    }
+
+    #[test]
+    fn writes_of_the_wrong_length_are_dropped() {
+        let mut simulator = Simulator::from_spec("0x08:2").unwrap();
+        simulator.write(0x08, &[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(simulator.levels(0x08), Some(vec![0, 0]));
+    }
+
+    #[test]
+    fn detectors_ignore_level_writes() {
+        let mut simulator = Simulator::from_spec("0x20:2:presence").unwrap();
+        simulator.set_occupancy(0x20, 0, true);
+        simulator.write(0x20, &[0x00, 0x00]).unwrap();
+        assert_eq!(simulator.read_block(0x20, 0x00, 4).unwrap(), vec![I2C_PRESENCE_DETECTOR, 2, 1, 0]);
+        assert_eq!(simulator.levels(0x20), None);
+    }
+
+    #[test]
+    fn levels_written_through_light_devices_are_stored() {
+        let simulator = Simulator::from_spec("0x08:3").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        light_devices.set_light_levels(0x08, vec![0, 0x1234, 0xffff]).unwrap();
+        assert_eq!(simulator.levels(0x08), Some(vec![0, 0x1234, 0xffff]));
+        assert_eq!(light_devices.get_light_levels(0x08).unwrap(), vec![0, 0x1234, 0xffff]);
+    }
+
+    #[test]
+    fn faults_clear_after_their_transactions() {
+        let mut simulator = Simulator::from_spec("0x08:2,0x09:2").unwrap();
+        simulator.inject_spec("0x08:nack:2,0x09:timeout:1").unwrap();
+        assert!(matches!(simulator.read_register(0x08, 0), Err(BusError::Nack)));
+        assert!(matches!(simulator.write(0x08, &[0, 0, 0, 0]), Err(BusError::Nack)));
+        assert_eq!(simulator.read_register(0x08, 0).unwrap(), 0x10);
+        assert!(matches!(simulator.read_register(0x09, 0), Err(BusError::Timeout)));
+        assert_eq!(simulator.read_register(0x09, 0).unwrap(), 0x10);
+    }
+
+    #[test]
+    fn faults_without_count_stay_until_cleared() {
+        let mut simulator = Simulator::from_spec("0x08:2").unwrap();
+        simulator.inject_spec("0x08:timeout").unwrap();
+        for _ in 0..3 {
+            assert!(matches!(simulator.read_register(0x08, 0), Err(BusError::Timeout)));
+        }
+        simulator.clear_fault(0x08);
+        assert!(simulator.read_register(0x08, 0).is_ok());
+    }
+
+    #[test]
+    fn faulted_and_vanished_devices_drop_out_of_discovery() {
+        let simulator = Simulator::from_spec("0x08:2,0x09:2,0x0a:2").unwrap();
+        simulator.inject(0x09, Fault::Nack(usize::MAX));
+        simulator.remove_device(0x0a);
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        let found = light_devices.controllers().unwrap();
+        assert_eq!(found.iter().map(|v| v.adr).collect::<Vec<_>>(), vec![0x08]);
+        assert!(matches!(light_devices.set_light_levels(0x09, vec![1, 2]), Err(BusError::Nack)));
+        assert!(matches!(light_devices.get_light_levels(0x0a), Err(BusError::Nack)));
+    }
+
+    #[test]
+    fn dispatch_recovers_once_the_fault_clears() {
+        let simulator = Simulator::from_spec("0x08:2").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        simulator.inject(0x08, Fault::Timeout(1));
+        assert!(matches!(light_devices.set_light_levels(0x08, vec![1, 2]), Err(BusError::Timeout)));
+        assert_eq!(simulator.levels(0x08), Some(vec![0, 0]));
+        light_devices.set_light_levels(0x08, vec![1, 2]).unwrap();
+        assert_eq!(simulator.levels(0x08), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn pwm_frequency_is_kept_within_range() {
+        let simulator = Simulator::from_spec("0x08:2").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator.clone());
+        let capabilities = light_devices.get_capabilities(0x08).unwrap();
+        assert!(capabilities.supports(I2C_FEATURE_PWM_FREQUENCY));
+        assert_eq!(light_devices.get_pwm_frequency(0x08).unwrap(), SIMULATOR_PWM_FREQUENCY);
+        light_devices.set_pwm_frequency(0x08, 2000).unwrap();
+        assert_eq!(light_devices.get_pwm_frequency(0x08).unwrap(), 2000);
+        light_devices.set_pwm_frequency(0x08, (PWM_FREQUENCY_MAX + 1) as u16).unwrap();
+        light_devices.set_pwm_frequency(0x08, (PWM_FREQUENCY_MIN - 1) as u16).unwrap();
+        assert_eq!(light_devices.get_pwm_frequency(0x08).unwrap(), 2000);
+        assert_eq!(simulator.levels(0x08), Some(vec![0, 0]));
+    }
+
+    #[test]
+    fn legacy_firmware_ignores_pwm_frequency() {
+        let simulator = Simulator::from_spec("0x08:2:legacy").unwrap();
+        let mut light_devices = LightDevices::with_bus(1, simulator);
+        assert!(!light_devices.get_capabilities(0x08).unwrap().supports(I2C_FEATURE_PWM_FREQUENCY));
+        light_devices.set_pwm_frequency(0x08, 2000).unwrap();
+        assert_ne!(light_devices.get_pwm_frequency(0x08).unwrap(), 2000);
+    }
+
+    #[test]
+    fn bad_specs_are_refused() {
+        for spec in ["0x08", "0x08:2:dimmer", "0x08:300", "nope:2"] {
+            assert!(matches!(Simulator::from_spec(spec), Err(BusError::InvalidInput)), "{}", spec);
+        }
+        for spec in ["0x08", "0x08:flicker", "0x08:nack:many"] {
+            assert!(matches!(Simulator::new().inject_spec(spec), Err(BusError::InvalidInput)), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{
+  HashMap,
+  HashSet,
+};
+use chrono::{
+  NaiveDateTime,
+  Utc,
+};
+use serde::Serialize;
+use std::sync::{
+  Arc,
+  Mutex,
+};
+use super::curves::Curve;
+use super::i2c::LightDevices;
+use super::props::LIGHT_LEVEL_MAX;
+use crate::models::Points;
+
+
+/// Draw of one device in the last dispatch
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceDraw {
+  pub device_id: i32,
+  pub max_watts: Option<f32>,
+  /// draw of the levels the points ask for
+  pub requested_watts: f32,
+  /// draw of the levels that went out
+  pub draw_watts: f32,
+  /// factor levels were multiplied with, 1 when nothing was limited
+  pub scale: f32,
+}
+
+/// Outcome of budget enforcement in the last dispatch
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PowerReport {
+  pub budget_watts: Option<f32>,
+  pub requested_watts: f32,
+  pub draw_watts: f32,
+  pub limited: bool,
+  /// last dispatch that had to scale levels down
+  pub limited_at: Option<NaiveDateTime>,
+  pub devices: Vec<DeviceDraw>,
+}
+
+/// Maximum load of the installation, enforced by the dispatcher before frames go out
+///
+/// Projected draw of a point is `watts * level / LIGHT_LEVEL_MAX` of the level sent to its controller,
+/// so dimming curves are accounted for. Devices over their own `max_watts` are scaled first,
+/// whatever is still over the installation budget is scaled evenly after.
+#[derive(Clone)]
+pub struct PowerBudget {
+  budget: Option<f32>,
+  report: Arc<Mutex<PowerReport>>,
+}
+
+
+impl PowerBudget {
+  pub fn new(budget: Option<f32>) -> Self {
+    PowerBudget {
+      budget,
+      report: Arc::new(Mutex::new(PowerReport {
+        budget_watts: budget,
+        ..Default::default()
+      })),
+    }
+  }
+
+  /// Scales `(device id, levels)` frames down proportionally where they would go over budget
+  ///
+  /// `watts` holds point watts of every device in `device_position` order, `limits` the devices with `max_watts`.
+  /// Outcome is kept for `report`.
+  pub fn limit(
+    &self,
+    frames: Vec<(i32, Vec<i32>)>,
+    watts: &HashMap<i32, Vec<f32>>,
+    limits: &HashMap<i32, f32>,
+  ) -> Vec<(i32, Vec<i32>)> {
+    let (result, mut devices) = self.scale(frames, watts, limits);
+    devices.sort_by_key(|v| v.device_id);
+    let limited = devices.iter().any(|v| v.scale < 1.0);
+    let mut report = self.report.lock().unwrap();
+    if limited && !report.limited {
+      log::warn!("Power budget exceeded, levels scaled down to {:.1}W", devices.iter().map(|v| v.draw_watts).sum::<f32>());
+    }
+    *report = PowerReport {
+      budget_watts: self.budget,
+      requested_watts: devices.iter().map(|v| v.requested_watts).sum(),
+      draw_watts: devices.iter().map(|v| v.draw_watts).sum(),
+      limited,
+      limited_at: match limited {
+        true => Some(Utc::now().naive_utc()),
+        false => report.limited_at,
+      },
+      devices,
+    };
+    result
+  }
+
+  /// Same scaling as `limit` without touching the report, for checking what the dispatcher sends
+  pub fn scale(
+    &self,
+    frames: Vec<(i32, Vec<i32>)>,
+    watts: &HashMap<i32, Vec<f32>>,
+    limits: &HashMap<i32, f32>,
+  ) -> (Vec<(i32, Vec<i32>)>, Vec<DeviceDraw>) {
+    let mut devices: Vec<DeviceDraw> = frames.iter()
+      .map(|(device_id, levels)| {
+        let requested_watts = draw(levels, watts.get(device_id));
+        let max_watts = limits.get(device_id).copied();
+        let scale = match max_watts {
+          Some(v) if requested_watts > v => v / requested_watts,
+          _ => 1.0,
+        };
+        DeviceDraw {
+          device_id: *device_id,
+          max_watts,
+          requested_watts,
+          draw_watts: requested_watts * scale,
+          scale,
+        }
+      })
+      .collect();
+
+    let total: f32 = devices.iter().map(|v| v.draw_watts).sum();
+    if let Some(budget) = self.budget {
+      if total > budget {
+        let scale = budget / total;
+        devices.iter_mut().for_each(|v| {
+          v.scale *= scale;
+          v.draw_watts *= scale;
+        });
+      }
+    }
+
+    let result: Vec<(i32, Vec<i32>)> = frames.into_iter()
+      .zip(devices.iter())
+      .map(|((device_id, levels), device)| {
+        if device.scale >= 1.0 {
+          return (device_id, levels);
+        }
+        // rounding down keeps the scaled draw within budget
+        (device_id, levels.iter().map(|v| (*v as f32 * device.scale).floor() as i32).collect())
+      })
+      .collect();
+    (result, devices)
+  }
+
+  /// Lit points the stored levels would be scaled down on, so changes report it before they are dispatched
+  ///
+  /// `limits` holds the devices with `max_watts`.
+  pub fn limited_points(&self, point_list: Vec<Points>, limits: &HashMap<i32, f32>) -> HashSet<i32> {
+    let watts = device_watts(&point_list);
+    let (_, devices) = self.scale(LightDevices::convert_points(point_list.clone(), false), &watts, limits);
+    point_list.iter()
+      .filter(|point| devices.iter().any(|v| v.device_id == point.device_id && v.scale < 1.0))
+      .filter(|point| point.active && Curve::from_point(point).apply(point.val) > 0)
+      .map(|point| point.id)
+      .collect()
+  }
+
+  pub fn report(&self) -> PowerReport {
+    self.report.lock().unwrap().clone()
+  }
+}
+
+/// Point watts of every device in `device_position` order, as `limit` takes them
+pub fn device_watts(point_list: &[Points]) -> HashMap<i32, Vec<f32>> {
+  let mut sorted = point_list.iter().collect::<Vec<_>>();
+  sorted.sort_by_key(|point| (point.device_id, point.device_position));
+  let mut result: HashMap<i32, Vec<f32>> = HashMap::new();
+  for point in sorted {
+    result.entry(point.device_id).or_default().push(point.watts);
+  }
+  result
+}
+
+fn draw(levels: &[i32], watts: Option<&Vec<f32>>) -> f32 {
+  let watts = match watts {
+    Some(v) => v,
+    None => return 0.0,
+  };
+  levels.iter()
+    .zip(watts.iter())
+    .map(|(level, watts)| watts * *level as f32 / LIGHT_LEVEL_MAX as f32)
+    .sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(id: i32, device_id: i32, device_position: i32, watts: f32) -> Points {
+    Points {
+      id,
+      device_id,
+      device_position,
+      val: LIGHT_LEVEL_MAX,
+      width: 1.0,
+      height: 1.0,
+      x: 0.0,
+      y: 0.0,
+      rotation: 0.0,
+      watts,
+      active: true,
+      tag: None,
+      curve: "linear".to_string(),
+      curve_gamma: 2.2,
+      curve_table: None,
+      layout_id: None,
+    }
+  }
+
+  fn watts(devices: Vec<(i32, Vec<f32>)>) -> HashMap<i32, Vec<f32>> {
+    devices.into_iter().collect()
+  }
+
+  #[test]
+  fn device_watts_are_grouped_in_position_order() {
+    let result = device_watts(&[
+      point(1, 2, 1, 20.0),
+      point(2, 1, 1, 5.0),
+      point(3, 2, 0, 10.0),
+      point(4, 1, 0, 2.5),
+    ]);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[&1], vec![2.5, 5.0]);
+    assert_eq!(result[&2], vec![10.0, 20.0]);
+  }
+
+  #[test]
+  fn frames_within_budget_are_kept() {
+    let power = PowerBudget::new(Some(100.0));
+    let frames = vec![(1, vec![LIGHT_LEVEL_MAX, 0]), (2, vec![LIGHT_LEVEL_MAX])];
+    let (result, devices) = power.scale(
+      frames.clone(),
+      &watts(vec![(1, vec![10.0, 10.0]), (2, vec![30.0])]),
+      &HashMap::new(),
+    );
+    assert_eq!(result, frames);
+    assert!(devices.iter().all(|v| v.scale == 1.0));
+    assert_eq!(devices.iter().map(|v| v.draw_watts).sum::<f32>(), 40.0);
+  }
+
+  #[test]
+  fn devices_over_their_limit_are_scaled_alone() {
+    let power = PowerBudget::new(None);
+    let (result, devices) = power.scale(
+      vec![(1, vec![LIGHT_LEVEL_MAX, LIGHT_LEVEL_MAX]), (2, vec![LIGHT_LEVEL_MAX])],
+      &watts(vec![(1, vec![10.0, 10.0]), (2, vec![30.0])]),
+      &HashMap::from([(1, 10.0), (2, 50.0)]),
+    );
+    assert_eq!(result, vec![(1, vec![32767, 32767]), (2, vec![LIGHT_LEVEL_MAX])]);
+    assert_eq!(devices[0].requested_watts, 20.0);
+    assert_eq!(devices[0].draw_watts, 10.0);
+    assert_eq!(devices[0].scale, 0.5);
+    assert_eq!(devices[1].scale, 1.0);
+  }
+
+  #[test]
+  fn budget_is_scaled_evenly_after_device_limits() {
+    let power = PowerBudget::new(Some(15.0));
+    let (result, devices) = power.scale(
+      vec![(1, vec![LIGHT_LEVEL_MAX]), (2, vec![LIGHT_LEVEL_MAX])],
+      &watts(vec![(1, vec![10.0]), (2, vec![30.0])]),
+      &HashMap::from([(2, 10.0)]),
+    );
+    // device 2 is limited to 10W first, the remaining 20W are scaled to 15W
+    assert_eq!(result, vec![(1, vec![49151]), (2, vec![16383])]);
+    assert_eq!(devices[0].scale, 0.75);
+    assert!((devices[1].scale - 0.25).abs() < 1e-6);
+    assert!((devices.iter().map(|v| v.draw_watts).sum::<f32>() - 15.0).abs() < 1e-4);
+  }
+
+  #[test]
+  fn devices_without_watts_draw_nothing() {
+    let power = PowerBudget::new(Some(1.0));
+    let frames = vec![(1, vec![LIGHT_LEVEL_MAX, LIGHT_LEVEL_MAX])];
+    let (result, devices) = power.scale(frames.clone(), &HashMap::new(), &HashMap::new());
+    assert_eq!(result, frames);
+    assert_eq!(devices[0].requested_watts, 0.0);
+  }
+
+  #[test]
+  fn limit_reports_the_last_dispatch() {
+    let power = PowerBudget::new(Some(10.0));
+    let device_watts = watts(vec![(2, vec![20.0]), (1, vec![20.0])]);
+    let result = power.limit(
+      vec![(2, vec![LIGHT_LEVEL_MAX]), (1, vec![0])],
+      &device_watts,
+      &HashMap::new(),
+    );
+    assert_eq!(result, vec![(2, vec![32767]), (1, vec![0])]);
+    let report = power.report();
+    assert!(report.limited);
+    assert_eq!(report.budget_watts, Some(10.0));
+    assert_eq!(report.requested_watts, 20.0);
+    assert_eq!(report.draw_watts, 10.0);
+    assert_eq!(report.devices.iter().map(|v| v.device_id).collect::<Vec<_>>(), vec![1, 2]);
+    let limited_at = report.limited_at;
+    assert!(limited_at.is_some());
+
+    // limited_at is kept once levels fit again
+    power.limit(vec![(2, vec![0]), (1, vec![0])], &device_watts, &HashMap::new());
+    let report = power.report();
+    assert!(!report.limited);
+    assert_eq!(report.draw_watts, 0.0);
+    assert_eq!(report.limited_at, limited_at);
+  }
+
+  #[test]
+  fn only_lit_points_are_reported_as_limited() {
+    let power = PowerBudget::new(None);
+    let mut dark = point(2, 1, 1, 10.0);
+    dark.val = 0;
+    let mut inactive = point(3, 1, 2, 10.0);
+    inactive.active = false;
+    let result = power.limited_points(
+      vec![point(1, 1, 0, 10.0), dark, inactive, point(4, 2, 0, 10.0)],
+      &HashMap::from([(1, 5.0)]),
+    );
+    assert_eq!(result, HashSet::from([1]));
+  }
+}
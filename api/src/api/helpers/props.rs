@@ -4,7 +4,72 @@ pub static LIGHT_LEVEL_MAX: i32 = u16::MAX as i32;
 pub static ROTATION_MIN: f32 = 0.0;
 pub static ROTATION_MAX: f32 = 360.0;
 
+// longest fade accepted through the api
+pub static TRANSITION_MAX_MS: u64 = 3_600_000;
+
+pub static CURVE_GAMMA_MIN: f32 = 0.1;
+pub static CURVE_GAMMA_MAX: f32 = 5.0;
+pub static CURVE_TABLE_MIN_LENGTH: usize = 2;
+pub static CURVE_TABLE_MAX_LENGTH: usize = 256;
+pub static CURVE_KINDS: &[&str] = &["linear", "gamma", "cie1931", "table"];
+
+// user names and passwords in characters, names with surrounding whitespace are refused
+pub static CREDENTIALS_MIN_LENGTH: usize = 8;
+
+// consecutive failed transfers before device is considered offline
+pub static DEVICE_OFFLINE_FAILURES: i32 = 3;
+
 pub static I2C_RANGE_MIN: u16 = 0x08;
 pub static I2C_RANGE_MAX: u16 = 0x77;
 pub static I2C_BYTES_PER_LIGHT: u8 = 2;
 pub static I2C_LIGHT_LEVEL_START_OFFSET: u8 = 2;
+
+// device identifiers found on register 0x00
+pub static I2C_LIGHT_CONTROLLER: u8 = 0x10;
+pub static I2C_PRESENCE_DETECTOR: u8 = 0x20;
+// presence detectors keep one occupancy byte (0 - free, anything else - occupied) per sensor
+pub static I2C_PRESENCE_STATE_START_OFFSET: u8 = 2;
+
+// capability block at the top of register space, legacy firmware answers zeros there
+pub static I2C_CAPABILITY_OFFSET: u8 = 0xF0;
+pub static I2C_CAPABILITY_MAGIC: u8 = 0xCA;
+pub static I2C_FIRMWARE_VERSION_LENGTH: u8 = 12;
+
+// feature flags advertised in the capability block
+pub static I2C_FEATURE_PWM_FREQUENCY: u8 = 0x01;
+pub static I2C_FEATURE_ADDRESS_CHANGE: u8 = 0x02;
+
+// pwm frequency in Hz, u16 big endian, written as register followed by both bytes
+pub static I2C_PWM_FREQUENCY_REGISTER: u8 = 0xE0;
+pub static PWM_FREQUENCY_MIN: i32 = 100;
+pub static PWM_FREQUENCY_MAX: i32 = 20000;
+
+// serial ports get bus numbers from here up in `SERIAL` order, below are i2c buses
+pub static SERIAL_BUS_OFFSET: i32 = 256;
+pub static SERIAL_FRAME_REQUEST: u8 = 0xA5;
+pub static SERIAL_FRAME_RESPONSE: u8 = 0x5A;
+pub static SERIAL_OP_READ: u8 = 0x01;
+pub static SERIAL_OP_WRITE: u8 = 0x02;
+pub static SERIAL_STATUS_OK: u8 = 0x00;
+pub static SERIAL_STATUS_NACK: u8 = 0x01;
+pub static SERIAL_STATUS_INVALID: u8 = 0x02;
+// silent 0.1s reads before a serial transaction times out
+pub static SERIAL_TIMEOUT_TICKS: u32 = 5;
+
+// dmx fixtures are not on any bus, they share this one
+pub static DMX_BUS: i32 = -1;
+pub static DMX_CHANNELS: usize = 512;
+pub static ARTNET_PORT: u16 = 6454;
+pub static ARTNET_UNIVERSE_MAX: i32 = 0x7fff;
+pub static SACN_PORT: u16 = 5568;
+pub static SACN_UNIVERSE_MIN: i32 = 1;
+pub static SACN_UNIVERSE_MAX: i32 = 63999;
+
+// new address followed by its complement, controller moves right after the write
+pub static I2C_ADDRESS_REGISTER: u8 = 0xE2;
+// probes of the new address before giving up on a moved controller
+pub static I2C_ADDRESS_CHANGE_ATTEMPTS: u32 = 10;
+pub static I2C_ADDRESS_CHANGE_INTERVAL_MS: u64 = 20;
+
+// wait before polling a failed broker connection again
+pub static MQTT_RECONNECT_MS: u64 = 5000;
@@ -0,0 +1,180 @@
+use crate::models::Points;
+
+/// Corners of a point box rotated around its center
+///
+/// Rotation is clockwise on the floor plan as y grows downwards, same as the front end draws it.
+pub fn corners(point: &Points) -> [(f32, f32); 4] {
+    let (center_x, center_y) = (point.x + point.width / 2.0, point.y + point.height / 2.0);
+    let (sin, cos) = point.rotation.to_radians().sin_cos();
+    let (half_width, half_height) = (point.width / 2.0, point.height / 2.0);
+    [
+        (-half_width, -half_height),
+        (half_width, -half_height),
+        (half_width, half_height),
+        (-half_width, half_height),
+    ]
+    .map(|(x, y)| (center_x + x * cos - y * sin, center_y + x * sin + y * cos))
+}
+
+/// Whether the rotated box of `point` lies inside `polygon`, boxes on its edge count
+///
+/// Concave polygons can have every corner inside while cutting through the box, so edges are checked too.
+pub fn inside(point: &Points, polygon: &[(f32, f32)]) -> bool {
+    let corners = corners(point);
+    if !corners.iter().all(|v| contains(polygon, *v)) {
+        return false;
+    }
+    let crossing = edges(&corners).any(|(a, b)| edges(polygon).any(|(c, d)| crosses(a, b, c, d)));
+    !crossing
+}
+
+/// Distance from `(x, y)` to the rotated box of `point`, 0 when inside
+pub fn distance(point: &Points, x: f32, y: f32) -> f32 {
+    let (center_x, center_y) = (point.x + point.width / 2.0, point.y + point.height / 2.0);
+    // rotating the position back lets the box be treated as unrotated
+    let (sin, cos) = (-point.rotation).to_radians().sin_cos();
+    let (offset_x, offset_y) = (x - center_x, y - center_y);
+    let local_x = offset_x * cos - offset_y * sin;
+    let local_y = offset_x * sin + offset_y * cos;
+    let outside_x = (local_x.abs() - point.width / 2.0).max(0.0);
+    let outside_y = (local_y.abs() - point.height / 2.0).max(0.0);
+    outside_x.hypot(outside_y)
+}
+
+fn edges(polygon: &[(f32, f32)]) -> impl Iterator<Item = ((f32, f32), (f32, f32))> + '_ {
+    polygon.iter()
+    .zip(polygon.iter().cycle().skip(1))
+    .map(|(a, b)| (*a, *b))
+}
+
+/// Even-odd rule, points on an edge are inside
+fn contains(polygon: &[(f32, f32)], (x, y): (f32, f32)) -> bool {
+    let mut inside = false;
+    for (a, b) in edges(polygon) {
+        if orientation(a, b, (x, y)) == 0.0 && within(a, b, (x, y)) {
+            return true;
+        }
+        if (a.1 > y) != (b.1 > y) && x < a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Whether segments cross each other, touching does not count
+fn crosses(a: (f32, f32), b: (f32, f32), c: (f32, f32), d: (f32, f32)) -> bool {
+    let (o1, o2) = (orientation(a, b, c), orientation(a, b, d));
+    let (o3, o4) = (orientation(c, d, a), orientation(c, d, b));
+    o1 * o2 < 0.0 && o3 * o4 < 0.0
+}
+
+/// Cross product sign, positive when `c` is clockwise of `a -> b` on the floor plan
+fn orientation(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Whether collinear `c` lies between `a` and `b`
+fn within(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> bool {
+    c.0 >= a.0.min(b.0) && c.0 <= a.0.max(b.0) && c.1 >= a.1.min(b.1) && c.1 <= a.1.max(b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, width: f32, height: f32, rotation: f32) -> Points {
+        Points {
+            id: 1,
+            device_id: 1,
+            device_position: 0,
+            val: 0,
+            width,
+            height,
+            x,
+            y,
+            rotation,
+            watts: 0.0,
+            active: true,
+            tag: None,
+            curve: "linear".to_string(),
+            curve_gamma: 2.2,
+            curve_table: None,
+            layout_id: None,
+        }
+    }
+
+    fn square(from: f32, to: f32) -> Vec<(f32, f32)> {
+        vec![(from, from), (to, from), (to, to), (from, to)]
+    }
+
+    // U shape open at the top, the notch spans x 3..7 down to y 3
+    fn notched() -> Vec<(f32, f32)> {
+        vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (7.0, 10.0), (7.0, 3.0), (3.0, 3.0), (3.0, 10.0), (0.0, 10.0)]
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.001
+    }
+
+    #[test]
+    fn rotated_boxes_need_their_corners_inside() {
+        let area = square(3.7, 6.3);
+        assert!(inside(&point(4.0, 4.0, 2.0, 2.0, 0.0), &area));
+        assert!(!inside(&point(4.0, 4.0, 2.0, 2.0, 45.0), &area));
+        assert!(inside(&point(4.0, 4.0, 2.0, 2.0, 45.0), &square(3.5, 6.5)));
+    }
+
+    #[test]
+    fn boxes_on_the_edge_are_inside() {
+        let area = square(0.0, 10.0);
+        assert!(inside(&point(0.0, 0.0, 10.0, 10.0, 0.0), &area));
+        assert!(inside(&point(8.0, 4.0, 2.0, 2.0, 0.0), &area));
+        assert!(!inside(&point(8.5, 4.0, 2.0, 2.0, 0.0), &area));
+    }
+
+    #[test]
+    fn boxes_across_a_concave_notch_are_outside() {
+        let area = notched();
+        let across = point(1.0, 5.0, 8.0, 2.0, 0.0);
+        assert!(corners(&across).iter().all(|v| contains(&area, *v)));
+        assert!(!inside(&across, &area));
+        assert!(inside(&point(1.0, 5.0, 1.0, 2.0, 0.0), &area));
+        assert!(inside(&point(1.0, 1.0, 8.0, 1.0, 0.0), &area));
+    }
+
+    #[test]
+    fn polygons_contain_their_edges() {
+        let area = notched();
+        assert!(contains(&area, (1.0, 1.0)));
+        assert!(contains(&area, (0.0, 5.0)));
+        assert!(contains(&area, (10.0, 10.0)));
+        assert!(contains(&area, (5.0, 3.0)));
+        assert!(!contains(&area, (5.0, 5.0)));
+        assert!(!contains(&area, (11.0, 5.0)));
+    }
+
+    #[test]
+    fn only_proper_crossings_count() {
+        assert!(crosses((0.0, 0.0), (2.0, 2.0), (0.0, 2.0), (2.0, 0.0)));
+        // touching at an end
+        assert!(!crosses((0.0, 0.0), (2.0, 2.0), (2.0, 2.0), (4.0, 0.0)));
+        assert!(!crosses((0.0, 0.0), (2.0, 0.0), (1.0, 0.0), (1.0, 2.0)));
+        // collinear and parallel
+        assert!(!crosses((0.0, 0.0), (2.0, 0.0), (1.0, 0.0), (3.0, 0.0)));
+        assert!(!crosses((0.0, 0.0), (2.0, 0.0), (0.0, 1.0), (2.0, 1.0)));
+    }
+
+    #[test]
+    fn distance_is_measured_to_the_rotated_box() {
+        let flat = point(0.0, 0.0, 4.0, 2.0, 0.0);
+        assert_eq!(distance(&flat, 1.0, 1.0), 0.0);
+        assert!(close(distance(&flat, 7.0, 1.0), 3.0));
+        assert!(close(distance(&flat, 7.0, 6.0), 5.0));
+        assert!(close(distance(&flat, 2.0, 5.0), 3.0));
+
+        let upright = point(0.0, 0.0, 4.0, 2.0, 90.0);
+        assert_eq!(distance(&upright, 2.0, 2.5), 0.0);
+        assert!(close(distance(&upright, 2.0, 5.0), 2.0));
+        assert!(close(distance(&upright, 5.0, 1.0), 2.0));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::sync::{
+  Arc,
+  Mutex,
+};
+use std::time::{
+  Duration,
+  Instant,
+};
+
+
+/// Fade of one light controller from `from` to `to` output levels
+#[derive(Clone, Debug)]
+struct Fade {
+  adr: i32,
+  bus: i32,
+  from: Vec<i32>,
+  to: Vec<i32>,
+  start: Instant,
+  duration: Duration,
+}
+
+impl Fade {
+  fn levels(&self, now: Instant) -> Vec<i32> {
+    let progress = match self.duration.is_zero() {
+      true => 1.0,
+      false => (now.saturating_duration_since(self.start).as_secs_f64() / self.duration.as_secs_f64()).min(1.0),
+    };
+    self.to.iter().enumerate()
+      .map(|(i, to)| {
+        let from = *self.from.get(i).unwrap_or(to);
+        from + ((to - from) as f64 * progress).round() as i32
+      })
+      .collect()
+  }
+
+  fn finished(&self, now: Instant) -> bool {
+    now.saturating_duration_since(self.start) >= self.duration
+  }
+}
+
+/// Fades requested through the API and run by the dispatcher
+///
+/// Durations are requested per point and used by the next dispatch for the devices of those points only,
+/// points changed without one are switched right away even when others in the same dispatch fade.
+#[derive(Clone)]
+pub struct Transitions {
+  fades: Arc<Mutex<HashMap<i32, Fade>>>,
+  /// duration by point id
+  next: Arc<Mutex<HashMap<i32, Duration>>>,
+}
+
+
+impl Transitions {
+  pub fn new() -> Self {
+    Transitions {
+      fades: Arc::new(Mutex::new(HashMap::new())),
+      next: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  /// Next dispatch fades `point_ids` to their levels over `duration_ms`, without it they are switched
+  ///
+  /// Has to be called for every change, so a change without duration drops one requested before.
+  pub fn request(&self, point_ids: &[i32], duration_ms: Option<u64>) {
+    let duration = duration_ms.map(Duration::from_millis).filter(|v| !v.is_zero());
+    let mut next = self.next.lock().unwrap();
+    for point_id in point_ids {
+      match duration {
+        Some(v) => next.insert(*point_id, v),
+        None => next.remove(point_id),
+      };
+    }
+  }
+
+  /// Durations requested for the running dispatch by device of `(point id, device id)`
+  ///
+  /// Devices fade over the longest duration requested for any of their points.
+  pub fn take(&self, point_devices: &[(i32, i32)]) -> HashMap<i32, Duration> {
+    let next = std::mem::take(&mut *self.next.lock().unwrap());
+    let mut result: HashMap<i32, Duration> = HashMap::new();
+    for (point_id, device_id) in point_devices {
+      if let Some(duration) = next.get(point_id) {
+        let longest = result.entry(*device_id).or_default();
+        *longest = (*longest).max(*duration);
+      }
+    }
+    result
+  }
+
+  pub fn active(&self) -> bool {
+    !self.fades.lock().unwrap().is_empty()
+  }
+
+  pub fn fading(&self, device_id: i32) -> bool {
+    self.fades.lock().unwrap().contains_key(&device_id)
+  }
+
+  /// Levels `device_id` is fading to
+  pub fn target(&self, device_id: i32) -> Option<Vec<i32>> {
+    self.fades.lock().unwrap().get(&device_id).map(|v| v.to.clone())
+  }
+
+  /// Levels `device_id` should be outputting right now
+  pub fn current(&self, device_id: i32) -> Option<Vec<i32>> {
+    self.fades.lock().unwrap().get(&device_id).map(|v| v.levels(Instant::now()))
+  }
+
+  pub fn start(&self, device_id: i32, adr: i32, bus: i32, from: Vec<i32>, to: Vec<i32>, duration: Duration) {
+    self.fades.lock().unwrap().insert(device_id, Fade {
+      adr,
+      bus,
+      from,
+      to,
+      start: Instant::now(),
+      duration,
+    });
+  }
+
+  pub fn cancel(&self, device_id: i32) {
+    self.fades.lock().unwrap().remove(&device_id);
+  }
+
+  /// Next frame `(device id, address, bus, levels)` of every fade, finished fades are dropped after their last frame
+  pub fn step(&self) -> Vec<(i32, i32, i32, Vec<i32>)> {
+    let now = Instant::now();
+    let mut lock = self.fades.lock().unwrap();
+    let result = lock.iter()
+      .map(|(device_id, fade)| (*device_id, fade.adr, fade.bus, fade.levels(now)))
+      .collect();
+    lock.retain(|_, fade| !fade.finished(now));
+    result
+  }
+}
+
+impl Default for Transitions {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fade(from: Vec<i32>, to: Vec<i32>, start: Instant) -> Fade {
+    Fade {
+      adr: 8,
+      bus: 1,
+      from,
+      to,
+      start,
+      duration: Duration::from_millis(1000),
+    }
+  }
+
+  // moves the start of the fade of `device_id` back, as if `elapsed` had passed
+  fn elapse(transitions: &Transitions, device_id: i32, elapsed: Duration) {
+    let mut fades = transitions.fades.lock().unwrap();
+    let fade = fades.get_mut(&device_id).unwrap();
+    fade.start -= elapsed;
+  }
+
+  #[test]
+  fn levels_are_interpolated_over_the_duration() {
+    let start = Instant::now();
+    let fade = fade(vec![0, 1000, 500], vec![1000, 0, 500], start);
+    assert_eq!(fade.levels(start), vec![0, 1000, 500]);
+    assert_eq!(fade.levels(start + Duration::from_millis(250)), vec![250, 750, 500]);
+    assert_eq!(fade.levels(start + Duration::from_millis(500)), vec![500, 500, 500]);
+    assert_eq!(fade.levels(start + Duration::from_millis(1000)), vec![1000, 0, 500]);
+    assert_eq!(fade.levels(start + Duration::from_millis(5000)), vec![1000, 0, 500]);
+    assert!(!fade.finished(start + Duration::from_millis(999)));
+    assert!(fade.finished(start + Duration::from_millis(1000)));
+  }
+
+  #[test]
+  fn missing_start_levels_jump_to_the_target() {
+    let start = Instant::now();
+    let fade = fade(vec![0], vec![1000, 800], start);
+    assert_eq!(fade.levels(start), vec![0, 800]);
+  }
+
+  #[test]
+  fn finished_fades_are_stepped_once_more() {
+    let transitions = Transitions::new();
+    transitions.start(1, 8, 1, vec![0, 0], vec![1000, 200], Duration::from_millis(1000));
+    assert!(transitions.active());
+    let frames = transitions.step();
+    assert_eq!(frames.len(), 1);
+    assert_eq!((frames[0].0, frames[0].1, frames[0].2), (1, 8, 1));
+
+    elapse(&transitions, 1, Duration::from_millis(1000));
+    assert_eq!(transitions.step(), vec![(1, 8, 1, vec![1000, 200])]);
+    assert!(!transitions.active());
+    assert!(transitions.step().is_empty());
+  }
+
+  #[test]
+  fn retargeted_fades_start_where_they_are() {
+    let transitions = Transitions::new();
+    transitions.start(1, 8, 1, vec![0], vec![1000], Duration::from_millis(1000));
+    elapse(&transitions, 1, Duration::from_millis(500));
+    let current = transitions.current(1).unwrap();
+    assert!((490..=510).contains(&current[0]));
+
+    transitions.start(1, 8, 1, current.clone(), vec![0], Duration::from_millis(1000));
+    assert_eq!(transitions.target(1), Some(vec![0]));
+    let restarted = transitions.current(1).unwrap();
+    assert!((current[0] - 10..=current[0]).contains(&restarted[0]));
+    transitions.cancel(1);
+    assert!(!transitions.fading(1));
+  }
+
+  #[test]
+  fn durations_only_go_to_devices_of_requested_points() {
+    let transitions = Transitions::new();
+    transitions.request(&[1], Some(500));
+    transitions.request(&[2], None);
+    transitions.request(&[3, 4], Some(800));
+    transitions.request(&[4], Some(200));
+    transitions.request(&[5], Some(300));
+    transitions.request(&[5], None);
+    transitions.request(&[6], Some(0));
+
+    let durations = transitions.take(&[(1, 10), (2, 20), (3, 30), (4, 30), (5, 50), (6, 60)]);
+    assert_eq!(durations.get(&10), Some(&Duration::from_millis(500)));
+    assert_eq!(durations.get(&30), Some(&Duration::from_millis(800)));
+    assert_eq!(durations.len(), 2);
+    assert!(transitions.take(&[(1, 10)]).is_empty());
+  }
+}
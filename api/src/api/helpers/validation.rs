@@ -0,0 +1,180 @@
+use crate::api::ApiError;
+use crate::calls::FieldError;
+use crate::models::TransitionQuery;
+use super::props::TRANSITION_MAX_MS;
+
+/// Number a rule is checked against, reported as the shortest `f64` printing the same
+pub trait Limit: Copy {
+    fn limit(self) -> f64;
+}
+
+impl Limit for i32 {
+    fn limit(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Limit for f64 {
+    fn limit(self) -> f64 {
+        self
+    }
+}
+
+impl Limit for f32 {
+    fn limit(self) -> f64 {
+        // widening 0.1f32 directly gives 0.10000000149011612
+        self.to_string().parse().unwrap_or(self as f64)
+    }
+}
+
+/// Collects every rule a request fails, so all of them are reported at once
+///
+/// Fields are named by their path in the request, see `FieldError`.
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `value` within `min..=max`, NaN never is
+    pub fn range<T: Limit>(&mut self, field: &str, value: T, min: T, max: T) -> &mut Self {
+        let (value, min, max) = (value.limit(), min.limit(), max.limit());
+        if !(min..=max).contains(&value) {
+            self.push(field, "range", Some(min), Some(max), None);
+        }
+        self
+    }
+
+    /// finite and at least `min`
+    pub fn min<T: Limit>(&mut self, field: &str, value: T, min: T) -> &mut Self {
+        let (value, min) = (value.limit(), min.limit());
+        if !value.is_finite() || value < min {
+            self.push(field, "min", Some(min), None, None);
+        }
+        self
+    }
+
+    /// Length of a text in characters or of a list within `min..=max`
+    pub fn length(&mut self, field: &str, length: usize, min: usize, max: Option<usize>) -> &mut Self {
+        if length < min || max.is_some_and(|v| length > v) {
+            self.push(field, "length", Some(min as f64), max.map(|v| v as f64), None);
+        }
+        self
+    }
+
+    pub fn one_of(&mut self, field: &str, value: &str, allowed: &'static [&'static str]) -> &mut Self {
+        if !allowed.contains(&value) {
+            self.push(field, "one_of", None, None, Some(allowed));
+        }
+        self
+    }
+
+    /// Any other rule without limits, e.g. `unique` or `exists`
+    pub fn check(&mut self, field: &str, valid: bool, rule: &'static str) -> &mut Self {
+        if !valid {
+            self.push(field, rule, None, None, None);
+        }
+        self
+    }
+
+    pub fn transition(&mut self, query: &TransitionQuery) -> &mut Self {
+        if query.transition_ms.is_some_and(|v| v > TRANSITION_MAX_MS) {
+            self.push("transition_ms", "range", Some(0.0), Some(TRANSITION_MAX_MS as f64), None);
+        }
+        self
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn finish(&mut self) -> Result<(), ApiError> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(ApiError::Invalid(std::mem::take(&mut self.errors))),
+        }
+    }
+
+    fn push(&mut self, field: &str, rule: &'static str, min: Option<f64>, max: Option<f64>, allowed: Option<&'static [&'static str]>) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            rule,
+            min,
+            max,
+            allowed,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(validator: &mut Validator) -> Vec<FieldError> {
+        match validator.finish() {
+            Ok(_) => vec![],
+            Err(ApiError::Invalid(v)) => v,
+            Err(_) => panic!("field errors expected"),
+        }
+    }
+
+    #[test]
+    fn every_failed_rule_is_reported() {
+        let mut validator = Validator::new();
+        validator
+        .range("[0].val", 70000, 0, 65535)
+        .min("[1].watts", -1.0f32, 0.0)
+        .length("name", 0, 1, Some(40))
+        .one_of("curve", "log", &["linear", "gamma"])
+        .check("[0].id", false, "exists")
+        .range("[2].val", 10, 0, 65535);
+        assert!(!validator.is_valid());
+        let errors = errors(&mut validator);
+        let rules = errors.iter().map(|v| (v.field.as_str(), v.rule)).collect::<Vec<_>>();
+        assert_eq!(rules, vec![
+            ("[0].val", "range"),
+            ("[1].watts", "min"),
+            ("name", "length"),
+            ("curve", "one_of"),
+            ("[0].id", "exists"),
+        ]);
+        assert_eq!((errors[0].min, errors[0].max), (Some(0.0), Some(65535.0)));
+        assert_eq!((errors[1].min, errors[1].max), (Some(0.0), None));
+        assert_eq!((errors[2].min, errors[2].max), (Some(1.0), Some(40.0)));
+        assert_eq!(errors[3].allowed, Some(&["linear", "gamma"][..]));
+        assert!(validator.is_valid());
+    }
+
+    #[test]
+    fn limits_serialize_like_requests() {
+        let mut validator = Validator::new();
+        validator.range("curve_gamma", 6.0f32, 0.1, 5.0);
+        let json = serde_json::to_value(errors(&mut validator)).unwrap();
+        assert_eq!(json, serde_json::json!([{"field": "curve_gamma", "rule": "range", "min": 0.1, "max": 5.0}]));
+    }
+
+    #[test]
+    fn nan_and_infinity_fail_numeric_rules() {
+        let mut validator = Validator::new();
+        validator
+        .range("x", f32::NAN, 0.0, 1.0)
+        .min("y", f32::INFINITY, 0.0)
+        .min("z", f32::NAN, 0.0);
+        assert_eq!(errors(&mut validator).len(), 3);
+    }
+
+    #[test]
+    fn transition_is_limited() {
+        let mut validator = Validator::new();
+        validator.transition(&TransitionQuery { transition_ms: Some(TRANSITION_MAX_MS) });
+        assert!(validator.is_valid());
+        validator.transition(&TransitionQuery { transition_ms: Some(TRANSITION_MAX_MS + 1) });
+        validator.transition(&TransitionQuery { transition_ms: None });
+        let errors = errors(&mut validator);
+        assert_eq!((errors.len(), errors[0].field.as_str(), errors[0].max), (1, "transition_ms", Some(TRANSITION_MAX_MS as f64)));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    api::{
+        ApiError,
+        helpers::{
+            spatial,
+            validation::Validator,
+        },
+    },
+    types::{
+        DbCon,
+        DbPool,
+    },
+    models::{
+        Layouts,
+        NewLayouts,
+        Points,
+    },
+};
+use actix_web::web;
+use diesel::{
+    prelude::*,
+    insert_into,
+    update,
+    delete,
+};
+
+pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<Layouts>>, ApiError> {
+    let response = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        all_layouts(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Layout fetching block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(response))
+}
+
+pub async fn post(
+    pool: web::Data<DbPool>,
+    data: web::Json<NewLayouts>,
+) -> Result<web::Json<Vec<Layouts>>, ApiError> {
+    let data = valid_request(data.into_inner())?;
+    let response = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::layouts::dsl::*;
+        insert_into(layouts).values(&data)
+        .execute(&mut con)
+        .map_err(|err| {
+            log::error!("Failed to insert new layout: {}", err);
+            ApiError::InternalErr
+        })?;
+        all_layouts(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Layout inserting block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(response))
+}
+
+// resizing below the points on it is refused, they have to be moved first
+pub async fn put(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+    data: web::Json<NewLayouts>,
+) -> Result<web::Json<Vec<Layouts>>, ApiError> {
+    let target_id = path.into_inner();
+    let data = valid_request(data.into_inner())?;
+    let response = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        let placed = crate::schema::points::dsl::points
+        .filter(crate::schema::points::dsl::layout_id.eq(target_id))
+        .load::<Points>(&mut con)
+        .map_err(|err| {
+            log::error!("Fetching layout [{}] points failed: {}", target_id, err);
+            ApiError::InternalErr
+        })?;
+        let resized = Layouts {
+            id: target_id,
+            layout_name: data.layout_name.clone(),
+            floor: data.floor,
+            width: data.width,
+            height: data.height,
+            background: data.background.clone(),
+        };
+        if !placed.iter().all(|point| fits(&resized, point)) {
+            return Err(ApiError::Conflict);
+        }
+
+        use crate::schema::layouts::dsl::*;
+        let update_count = update(layouts.find(target_id))
+        .set(&data)
+        .execute(&mut con)
+        .map_err(|err| {
+            log::error!("Failed to update layout id [{}]: {}", target_id, err);
+            ApiError::InternalErr
+        })?;
+        if update_count < 1 {
+            return Err(ApiError::NotFound);
+        }
+        all_layouts(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Layout update block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(response))
+}
+
+// points on it are kept without a layout
+pub async fn del(
+    path: web::Path<i32>,
+    pool: web::Data<DbPool>,
+) -> Result<web::Json<Vec<Layouts>>, ApiError> {
+    let target_id = path.into_inner();
+    let response = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Failed to get pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::layouts::dsl::*;
+        let delete_count = delete(layouts.find(target_id))
+        .execute(&mut con)
+        .map_err(|err| {
+            log::error!("Failed to delete layout: {}", err);
+            ApiError::InternalErr
+        })?;
+        if delete_count < 1 {
+            return Err(ApiError::NotFound);
+        }
+        all_layouts(&mut con)
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Layout deleting block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(response))
+}
+
+// rounding of rotated corners, a box flush with the edge still fits
+static FIT_TOLERANCE: f32 = 1e-3;
+
+/// Whether the rotated box of `point` lies within `layout`
+pub fn fits(layout: &Layouts, point: &Points) -> bool {
+    let values_valid = [point.x, point.y, point.width, point.height, point.rotation].iter().all(|v| v.is_finite())
+        && point.width >= 0.0 && point.height >= 0.0;
+    values_valid && spatial::corners(point).iter().all(|(x, y)| {
+        (-FIT_TOLERANCE..=layout.width + FIT_TOLERANCE).contains(x)
+        && (-FIT_TOLERANCE..=layout.height + FIT_TOLERANCE).contains(y)
+    })
+}
+
+fn all_layouts(con: &mut DbCon) -> Result<Vec<Layouts>, ApiError> {
+    use crate::schema::layouts::dsl::*;
+    layouts.order((floor.asc(), id.asc()))
+    .load::<Layouts>(con)
+    .map_err(|err| {
+        log::error!("Fetching layouts failed: {}", err);
+        ApiError::InternalErr
+    })
+}
+
+fn valid_request(mut data: NewLayouts) -> Result<NewLayouts, ApiError> {
+    data.layout_name = data.layout_name.trim().to_string();
+    Validator::new()
+    .length("layout_name", data.layout_name.chars().count(), 1, None)
+    .check("width", data.width.is_finite() && data.width > 0.0, "positive")
+    .check("height", data.height.is_finite() && data.height > 0.0, "positive")
+    .finish()?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(width: f32, height: f32) -> Layouts {
+        Layouts {
+            id: 1,
+            layout_name: "ground floor".to_string(),
+            floor: 0,
+            width,
+            height,
+            background: None,
+        }
+    }
+
+    fn point(x: f32, y: f32, width: f32, height: f32, rotation: f32) -> Points {
+        Points {
+            id: 1,
+            device_id: 1,
+            device_position: 0,
+            val: 0,
+            width,
+            height,
+            x,
+            y,
+            rotation,
+            watts: 0.0,
+            active: true,
+            tag: None,
+            curve: "linear".to_string(),
+            curve_gamma: 2.2,
+            curve_table: None,
+            layout_id: Some(1),
+        }
+    }
+
+    #[test]
+    fn boxes_inside_the_layout_fit() {
+        let layout = layout(10.0, 5.0);
+        assert!(fits(&layout, &point(0.0, 0.0, 10.0, 5.0, 0.0)));
+        assert!(fits(&layout, &point(2.0, 1.0, 3.0, 2.0, 0.0)));
+        assert!(fits(&layout, &point(10.0, 5.0, 0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn boxes_crossing_the_edge_do_not_fit() {
+        let layout = layout(10.0, 5.0);
+        assert!(!fits(&layout, &point(8.0, 0.0, 2.5, 1.0, 0.0)));
+        assert!(!fits(&layout, &point(0.0, 4.5, 1.0, 1.0, 0.0)));
+        assert!(!fits(&layout, &point(-1.0, 0.0, 1.0, 1.0, 0.0)));
+        assert!(!fits(&layout, &point(0.0, 0.0, -1.0, 1.0, 0.0)));
+        assert!(!fits(&layout, &point(f32::NAN, 0.0, 1.0, 1.0, 0.0)));
+        assert!(!fits(&layout, &point(0.0, 0.0, 1.0, 1.0, f32::INFINITY)));
+    }
+
+    #[test]
+    fn rotated_corners_have_to_fit() {
+        let layout = layout(10.0, 5.0);
+        // 4 x 1 box turned upright around its center at (5, 2.5) spans y 0.5..4.5
+        assert!(fits(&layout, &point(3.0, 2.0, 4.0, 1.0, 90.0)));
+        // the same box at the top edge fits flat but not turned
+        assert!(fits(&layout, &point(3.0, 0.0, 4.0, 1.0, 0.0)));
+        assert!(!fits(&layout, &point(3.0, 0.0, 4.0, 1.0, 90.0)));
+        // a square touching every edge only fits unrotated
+        assert!(fits(&layout, &point(0.0, 0.0, 5.0, 5.0, 180.0)));
+        assert!(!fits(&layout, &point(0.0, 0.0, 5.0, 5.0, 45.0)));
+    }
+
+    #[test]
+    fn rounding_of_rotated_corners_is_tolerated() {
+        let layout = layout(10.0, 5.0);
+        // corners of a full-size box turned half around land a hair off the edges
+        assert!(fits(&layout, &point(0.0, 0.0, 10.0, 5.0, 180.0)));
+        assert!(fits(&layout, &point(0.0, 0.0, 10.0, 5.0, 360.0)));
+        assert!(!fits(&layout, &point(0.0, 0.0, 10.0 + FIT_TOLERANCE * 4.0, 5.0, 0.0)));
+    }
+
+    #[test]
+    fn layout_requests_are_trimmed_and_checked() {
+        let request = |name: &str, width: f32, height: f32| valid_request(NewLayouts {
+            layout_name: name.to_string(),
+            floor: 0,
+            width,
+            height,
+            background: None,
+        });
+        assert_eq!(request(" hall ", 1.0, 1.0).unwrap().layout_name, "hall");
+        assert!(request("  ", 1.0, 1.0).is_err());
+        assert!(request("hall", 0.0, 1.0).is_err());
+        assert!(request("hall", 1.0, f32::NAN).is_err());
+    }
+}
@@ -1,15 +1,29 @@
 pub mod identify;
 pub mod single;
+pub mod spatial;
 
 use crate::{
     api::ApiError,
-    types::DbPool,
+    types::{
+        DbCon,
+        DbPool,
+    },
     models::{
+        Layouts,
+        Limited,
         Points,
+        PointsPatch,
+        PointsQuery,
         PointsUpdate,
         PointsRequest,
+        TransitionQuery,
     },
     api::helpers::props::{
+        CURVE_GAMMA_MAX,
+        CURVE_GAMMA_MIN,
+        CURVE_KINDS,
+        CURVE_TABLE_MAX_LENGTH,
+        CURVE_TABLE_MIN_LENGTH,
         LIGHT_LEVEL_MAX,
         LIGHT_LEVEL_MIN,
         ROTATION_MIN,
@@ -17,14 +31,24 @@ use crate::{
     },
 };
 use actix_web::web;
+use std::collections::HashSet;
 use diesel::{
     prelude::*,
     update,
 };
 
-use super::helpers::batcher::Batcher;
+use super::helpers::{
+    batcher::Batcher,
+    db,
+    power::PowerBudget,
+    transitions::Transitions,
+    validation::Validator,
+};
 
-pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<Points>>, ApiError> {
+pub async fn get(
+    query: web::Query<PointsQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<web::Json<Vec<Points>>, ApiError> {
     let pool_points = pool.clone();
     let response = web::block(move || {
         let mut con = pool_points.get()
@@ -33,8 +57,11 @@ pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<Points>>, ApiE
             ApiError::InternalErr
         })?;
         use crate::schema::points::dsl::*;
-        points.order(id.asc())
-        .load::<Points>(&mut con)
+        let mut selected = points.order(id.asc()).into_boxed();
+        if let Some(v) = query.layout_id {
+            selected = selected.filter(layout_id.eq(v));
+        }
+        selected.load::<Points>(&mut con)
         .map_err(|err| {
             log::error!("Fetching points failed: {}", err);
             ApiError::InternalErr
@@ -50,74 +77,352 @@ pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<Points>>, ApiE
 
 pub async fn put(
     pts: web::Json<Vec<PointsRequest>>,
+    query: web::Query<TransitionQuery>,
     pool: web::Data<DbPool>,
     batcher: web::Data<Batcher>,
-) -> Result<web::Json<Vec<Points>>, ApiError> {
+    transitions: web::Data<Transitions>,
+    power: web::Data<PowerBudget>,
+) -> Result<web::Json<Vec<Limited<Points>>>, ApiError> {
+    Validator::new().transition(&query).finish()?;
+
+    // every field is given, curve and layout are kept when left out
+    let patches = pts.iter()
+    .map(|item| PointsPatch {
+        id: item.id,
+        changes: PointsUpdate {
+            val: Some(item.val),
+            width: Some(item.width),
+            height: Some(item.height),
+            x: Some(item.x),
+            y: Some(item.y),
+            rotation: Some(item.rotation),
+            watts: Some(item.watts),
+            active: Some(item.active),
+            tag: Some(item.tag.clone()),
+            curve: item.curve.clone(),
+            curve_gamma: item.curve_gamma,
+            curve_table: item.curve_table.clone().map(Some),
+            layout_id: item.layout_id,
+        },
+    })
+    .collect::<Vec<_>>();
+    let point_ids = patches.iter().map(|item| item.id).collect::<Vec<_>>();
+
     let mut con = pool.get()
     .map_err(|err| {
         log::error!("Failed to get pool_points: {}", err);
         ApiError::InternalErr
     })?;
 
-    let result: Vec<Points> = web::block(move || {
+    let result = web::block(move || {
+        db::transaction(&mut con, |con| {
+            write_patches(con, &patches)?;
+            deactivate_presets(con)
+        })?;
         use crate::schema::points::dsl::*;
-        for item in pts.iter() {
-            diesel::update(points)
-            .filter(id.eq(item.id))
-            .set(&PointsUpdate {
-                active: Some(item.active),
-                tag: Some(item.tag.clone()),
-                width: Some(if item.width >= 0.0 { item.width } else { 0.0 }),
-                height: Some(if item.height >= 0.0 { item.height } else { 0.0 }),
-                x: Some(if item.x >= 0.0 { item.x } else { 0.0 }),
-                y: Some(if item.y >= 0.0 { item.y } else { 0.0 }),
-                watts: Some(if item.watts >= 0.0 { item.watts } else { 0.0 }),
-                val: Some(if item.val > LIGHT_LEVEL_MAX {
-                        LIGHT_LEVEL_MAX
-                    } else if item.val < LIGHT_LEVEL_MIN {
-                        LIGHT_LEVEL_MIN
-                    } else {
-                        item.val
-                    }),
-                rotation: Some(if item.rotation < ROTATION_MIN {
-                        ROTATION_MIN
-                    } else if item.rotation >= ROTATION_MAX {
-                        ROTATION_MAX
-                    } else {
-                        item.rotation
-                    }),
-            })
-            .execute(&mut con)
-            .map_err(|err| {
-                log::error!("updating [{}] point failed: {}", item.id , err);
-                ApiError::InternalErr
-            })?;
-        }
-        use crate::schema::presets::dsl::{
-            presets,
-            active,
-        };
-        update(presets).filter(active.eq(true))
-        .set(active.eq(false))
-        .execute(&mut con)
+        let point_list = points.order(id.asc())
+        .load::<Points>(&mut con)
         .map_err(|err| {
-            log::error!("failed to update presets: {}", err);
+            log::error!("Fetching points failed: {}", err);
             ApiError::InternalErr
         })?;
-        points.order(id.asc())
+        let limited = db::points::limited(&mut con, &power)?;
+        Ok::<_, ApiError>(with_limits(point_list, &limited))
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Point update block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    transitions.request(&point_ids, query.transition_ms);
+    batcher.request();
+
+    Ok(web::Json(result))
+}
+
+// only given fields are written
+pub async fn patch(
+    pts: web::Json<Vec<PointsPatch>>,
+    query: web::Query<TransitionQuery>,
+    pool: web::Data<DbPool>,
+    batcher: web::Data<Batcher>,
+    transitions: web::Data<Transitions>,
+    power: web::Data<PowerBudget>,
+) -> Result<web::Json<Vec<Limited<Points>>>, ApiError> {
+    Validator::new().transition(&query).finish()?;
+    // geometry and tags do not change what goes out
+    let dispatch = pts.iter().any(|item| {
+        let changes = &item.changes;
+        changes.val.is_some() || changes.active.is_some() || changes.watts.is_some()
+        || changes.curve.is_some() || changes.curve_gamma.is_some() || changes.curve_table.is_some()
+    });
+    let point_ids = pts.iter().map(|item| item.id).collect::<Vec<_>>();
+
+    let mut con = pool.get()
+    .map_err(|err| {
+        log::error!("Failed to get pool_points: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    let result = web::block(move || {
+        db::transaction(&mut con, |con| {
+            write_patches(con, &pts)?;
+            if pts.iter().any(|item| item.changes.val.is_some()) {
+                deactivate_presets(con)?;
+            }
+            Ok(())
+        })?;
+        use crate::schema::points::dsl::*;
+        let point_list = points.filter(id.eq_any(pts.iter().map(|item| item.id)))
+        .order(id.asc())
         .load::<Points>(&mut con)
         .map_err(|err| {
             log::error!("Fetching points failed: {}", err);
             ApiError::InternalErr
-        })
+        })?;
+        let limited = db::points::limited(&mut con, &power)?;
+        Ok::<_, ApiError>(with_limits(point_list, &limited))
     })
     .await
     .map_err(|err| {
-        log::error!("Point update block failed: {}", err);
+        log::error!("Point patch block failed: {}", err);
         ApiError::InternalErr
     })??;
 
-    batcher.request();
+    if dispatch {
+        transitions.request(&point_ids, query.transition_ms);
+        batcher.request();
+    }
 
     Ok(web::Json(result))
 }
+
+/// Marks points the power budget scales down, levels in the database stay as requested
+pub fn with_limits(point_list: Vec<Points>, limited: &HashSet<i32>) -> Vec<Limited<Points>> {
+    point_list.into_iter()
+    .map(|point| Limited {
+        limited: limited.contains(&point.id),
+        item: point,
+    })
+    .collect()
+}
+
+/// Validates every patch against the point as it would be after patching, writes them only if all pass
+///
+/// Has to run in a transaction, checked points are locked until it ends.
+fn write_patches(con: &mut DbCon, patches: &[PointsPatch]) -> Result<(), ApiError> {
+    use crate::schema::points::dsl::*;
+    let current = points.filter(id.eq_any(patches.iter().map(|item| item.id)))
+    .for_update()
+    .load::<Points>(con)
+    .map_err(|err| {
+        log::error!("Fetching points failed: {}", err);
+        ApiError::InternalErr
+    })?;
+    let layout_list = crate::schema::layouts::dsl::layouts
+    .load::<Layouts>(con)
+    .map_err(|err| {
+        log::error!("Fetching layouts failed: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    let mut validator = Validator::new();
+    for (index, item) in patches.iter().enumerate() {
+        let field = format!("[{}].id", index);
+        let unique = !patches[..index].iter().any(|v| v.id == item.id);
+        validator.check(&field, unique, "unique");
+        match current.iter().find(|point| point.id == item.id) {
+            Some(point) => validate_patch(&mut validator, index, point, &item.changes, &layout_list),
+            None => {
+                validator.check(&field, false, "exists");
+            },
+        }
+    }
+    validator.finish()?;
+
+    for item in patches {
+        // diesel refuses an update without columns
+        if patch_is_empty(&item.changes) {
+            continue;
+        }
+        diesel::update(points)
+        .filter(id.eq(item.id))
+        .set(&item.changes)
+        .execute(con)
+        .map_err(|err| {
+            log::error!("updating [{}] point failed: {}", item.id , err);
+            ApiError::InternalErr
+        })?;
+    }
+    Ok(())
+}
+
+fn validate_patch(validator: &mut Validator, index: usize, current: &Points, changes: &PointsUpdate, layout_list: &[Layouts]) {
+    let field = |name: &str| format!("[{}].{}", index, name);
+    if let Some(v) = changes.val {
+        validator.range(&field("val"), v, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+    }
+    if let Some(v) = changes.rotation {
+        validator.range(&field("rotation"), v, ROTATION_MIN, ROTATION_MAX);
+    }
+    let sizes = [
+        ("width", changes.width),
+        ("height", changes.height),
+        ("x", changes.x),
+        ("y", changes.y),
+        ("watts", changes.watts),
+    ];
+    for (name, value) in sizes {
+        if let Some(v) = value {
+            validator.min(&field(name), v, 0.0);
+        }
+    }
+
+    if let Some(v) = &changes.curve {
+        validator.one_of(&field("curve"), v, CURVE_KINDS);
+    }
+    if let Some(v) = changes.curve_gamma {
+        validator.range(&field("curve_gamma"), v, CURVE_GAMMA_MIN, CURVE_GAMMA_MAX);
+    }
+    if let Some(Some(table)) = &changes.curve_table {
+        validator.length(&field("curve_table"), table.len(), CURVE_TABLE_MIN_LENGTH, Some(CURVE_TABLE_MAX_LENGTH));
+        if let Some(v) = table.iter().find(|v| !(LIGHT_LEVEL_MIN..=LIGHT_LEVEL_MAX).contains(*v)) {
+            validator.range(&field("curve_table"), *v, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+        }
+        validator.check(&field("curve_table"), table.windows(2).all(|w| w[0] <= w[1]), "non_decreasing");
+    }
+    let curve = changes.curve.as_deref().unwrap_or(&current.curve);
+    let has_table = match &changes.curve_table {
+        Some(v) => v.is_some(),
+        None => current.curve_table.is_some(),
+    };
+    validator.check(&field("curve_table"), curve != "table" || has_table, "required");
+
+    let target = match changes.layout_id.unwrap_or(current.layout_id) {
+        Some(v) => v,
+        None => return,
+    };
+    match layout_list.iter().find(|layout| layout.id == target) {
+        Some(layout) => {
+            let patched = Points {
+                x: changes.x.unwrap_or(current.x),
+                y: changes.y.unwrap_or(current.y),
+                width: changes.width.unwrap_or(current.width),
+                height: changes.height.unwrap_or(current.height),
+                rotation: changes.rotation.unwrap_or(current.rotation),
+                ..current.clone()
+            };
+            let fits = super::layouts::fits(layout, &patched);
+            validator.check(&field("layout_id"), fits, "within_layout");
+        },
+        None => {
+            validator.check(&field("layout_id"), false, "exists");
+        },
+    }
+}
+
+fn patch_is_empty(changes: &PointsUpdate) -> bool {
+    changes.val.is_none() && changes.width.is_none() && changes.height.is_none()
+    && changes.x.is_none() && changes.y.is_none() && changes.rotation.is_none()
+    && changes.watts.is_none() && changes.active.is_none() && changes.tag.is_none()
+    && changes.curve.is_none() && changes.curve_gamma.is_none() && changes.curve_table.is_none()
+    && changes.layout_id.is_none()
+}
+
+fn deactivate_presets(con: &mut DbCon) -> Result<(), ApiError> {
+    use crate::schema::presets::dsl::*;
+    update(presets).filter(active.eq(true))
+    .set(active.eq(false))
+    .execute(con)
+    .map_err(|err| {
+        log::error!("failed to update presets: {}", err);
+        ApiError::InternalErr
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point() -> Points {
+        Points {
+            id: 1,
+            device_id: 1,
+            device_position: 0,
+            val: 0,
+            width: 1.0,
+            height: 1.0,
+            x: 1.0,
+            y: 1.0,
+            rotation: 0.0,
+            watts: 5.0,
+            active: true,
+            tag: None,
+            curve: "linear".to_string(),
+            curve_gamma: 2.2,
+            curve_table: None,
+            layout_id: Some(1),
+        }
+    }
+
+    fn layouts() -> Vec<Layouts> {
+        vec![Layouts {
+            id: 1,
+            layout_name: "hall".to_string(),
+            floor: 0,
+            width: 4.0,
+            height: 4.0,
+            background: None,
+        }]
+    }
+
+    fn changes(json: &str) -> PointsUpdate {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn left_out_and_null_fields_differ() {
+        let kept = changes(r#"{"val": 5}"#);
+        assert_eq!((kept.val, kept.tag, kept.layout_id), (Some(5), None, None));
+        let cleared = changes(r#"{"tag": null, "layout_id": null}"#);
+        assert!(!patch_is_empty(&cleared));
+        assert_eq!((cleared.tag, cleared.layout_id), (Some(None), Some(None)));
+        assert!(patch_is_empty(&changes("{}")));
+    }
+
+    /// `(field, rule)` of every failed check
+    fn errors(current: &Points, json: &str) -> Vec<(String, &'static str)> {
+        let mut validator = Validator::new();
+        validate_patch(&mut validator, 0, current, &changes(json), &layouts());
+        match validator.finish() {
+            Ok(_) => vec![],
+            Err(ApiError::Invalid(v)) => v.into_iter().map(|v| (v.field, v.rule)).collect(),
+            Err(_) => panic!("field errors expected"),
+        }
+    }
+
+    #[test]
+    fn patched_fields_are_checked() {
+        let current = point();
+        assert!(errors(&current, r#"{"val": 65535, "rotation": 90}"#).is_empty());
+        assert_eq!(errors(&current, r#"{"val": 65536, "watts": -1}"#), vec![
+            ("[0].val".to_string(), "range"),
+            ("[0].watts".to_string(), "min"),
+        ]);
+        assert_eq!(errors(&current, r#"{"curve": "table"}"#), vec![("[0].curve_table".to_string(), "required")]);
+        assert_eq!(errors(&current, r#"{"curve": "log"}"#), vec![("[0].curve".to_string(), "one_of")]);
+        assert!(errors(&current, r#"{"curve": "table", "curve_table": [0, 10]}"#).is_empty());
+        assert_eq!(errors(&current, r#"{"curve_table": [10, 0]}"#), vec![("[0].curve_table".to_string(), "non_decreasing")]);
+    }
+
+    #[test]
+    fn patched_geometry_has_to_fit_the_layout() {
+        let current = point();
+        assert!(errors(&current, r#"{"x": 3}"#).is_empty());
+        assert_eq!(errors(&current, r#"{"x": 3.5}"#), vec![("[0].layout_id".to_string(), "within_layout")]);
+        assert_eq!(errors(&current, r#"{"layout_id": 2}"#), vec![("[0].layout_id".to_string(), "exists")]);
+        assert!(errors(&current, r#"{"x": 30, "layout_id": null}"#).is_empty());
+    }
+}
@@ -44,7 +44,7 @@ pub async fn post(
         })?;
 
         if !point_list.iter().any(|v| &v.id == &point_id) {
-            return Err(ApiError::BadRequest);
+            return Err(ApiError::invalid("id", "exists"));
         }
 
         diesel::update(points).set(val.eq(0)).execute(& mut con)
@@ -1,24 +1,26 @@
-use actix_web::{
-  web,
-  HttpResponse,
-  Responder,
-};
+use actix_web::web;
 use diesel::prelude::*;
 
 use crate::{
   types::DbPool,
   models::{
+    Limited,
     SinglePoint,
     Points,
+    TransitionQuery,
   },
   api::{
     ApiError,
     helpers::{
+    db,
     props::{
       LIGHT_LEVEL_MAX,
       LIGHT_LEVEL_MIN,
     },
     batcher::Batcher,
+    power::PowerBudget,
+    transitions::Transitions,
+    validation::Validator,
   },
 },
 };
@@ -62,19 +64,20 @@ pub async fn get(
 pub async fn put(
   path: web::Path<i32>,
   data: web::Json<SinglePoint>,
+  query: web::Query<TransitionQuery>,
   pool: web::Data<DbPool>,
   batcher: web::Data<Batcher>,
-) -> Result<impl Responder, ApiError> {
+  transitions: web::Data<Transitions>,
+  power: web::Data<PowerBudget>,
+) -> Result<web::Json<Limited<SinglePoint>>, ApiError> {
+  Validator::new()
+  .transition(&query)
+  .range("value", data.value, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX)
+  .finish()?;
 
   let point_id = path.into_inner();
 
-  let new_value = if data.value > LIGHT_LEVEL_MAX {
-    LIGHT_LEVEL_MAX
-  } else if data.value < LIGHT_LEVEL_MIN {
-      LIGHT_LEVEL_MIN
-  } else {
-      data.value
-  };
+  let new_value = data.value;
 
   let mut con = pool.get()
   .map_err(|err| {
@@ -82,32 +85,20 @@ pub async fn put(
     ApiError::InternalErr
   })?;
 
-  web::block(move || {
-    use crate::schema::points::dsl::*;
-    diesel::update(points).filter(id.eq(point_id)).set(val.eq(new_value)).execute(&mut con).map_err(|err| {
-        log::error!("updating single [{}] point failed: {}", point_id, err);
-        ApiError::InternalErr
-    })?;
-
-    use crate::schema::presets::dsl::{
-        presets,
-        active,
-    };
-    diesel::update(presets).filter(active.eq(true))
-    .set(active.eq(false))
-    .execute(&mut con)
-    .map_err(|err| {
-        log::error!("failed to update presets: {}", err);
-        ApiError::InternalErr
-    })?;
-    Ok(())
+  let limited = web::block(move || {
+    db::points::set_value(&mut con, point_id, new_value)?;
+    db::points::limited(&mut con, &power)
   })
   .await
   .map_err(|err| {
     log::error!("Point update block failed: {}", err);
     ApiError::InternalErr
   })??;
-    
+
+  transitions.request(&[point_id], query.transition_ms);
   batcher.request();
-  Ok(HttpResponse::Ok())
+  Ok(web::Json(Limited {
+    item: SinglePoint { value: new_value },
+    limited: limited.contains(&point_id),
+  }))
 }
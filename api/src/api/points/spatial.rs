@@ -0,0 +1,210 @@
+use actix_web::web;
+use diesel::prelude::*;
+
+use crate::{
+    types::DbPool,
+    models::{
+        Limited,
+        Points,
+        SpatialCommand,
+        TransitionQuery,
+    },
+    api::{
+        ApiError,
+        helpers::{
+            db,
+            props::{
+                LIGHT_LEVEL_MAX,
+                LIGHT_LEVEL_MIN,
+            },
+            batcher::Batcher,
+            power::PowerBudget,
+            spatial,
+            transitions::Transitions,
+            validation::Validator,
+        },
+    },
+};
+
+static SHAPES: &[&str] = &["rect", "polygon", "radial"];
+
+/// Area a command acts on
+enum Area {
+    Polygon(Vec<(f32, f32)>),
+    Radial(f32, f32, f32),
+}
+
+// points are matched by their rotated boxes lying inside the area, set like any other point change and dispatched as one batch
+pub async fn put(
+    data: web::Json<SpatialCommand>,
+    query: web::Query<TransitionQuery>,
+    pool: web::Data<DbPool>,
+    batcher: web::Data<Batcher>,
+    transitions: web::Data<Transitions>,
+    power: web::Data<PowerBudget>,
+) -> Result<web::Json<Vec<Limited<Points>>>, ApiError> {
+    let area = valid_area(&data, &query)?;
+
+    let mut con = pool.get()
+    .map_err(|err| {
+        log::error!("Failed to get pool_points: {}", err);
+        ApiError::InternalErr
+    })?;
+
+    let result = web::block(move || {
+        if let Some(target) = data.layout_id {
+            use crate::schema::layouts::dsl::*;
+            let found = layouts.find(target)
+            .count()
+            .get_result::<i64>(&mut con)
+            .map_err(|err| {
+                log::error!("Fetching layout [{}] failed: {}", target, err);
+                ApiError::InternalErr
+            })?;
+            Validator::new().check("layout_id", found > 0, "exists").finish()?;
+        }
+
+        use crate::schema::points::dsl::*;
+        let mut selected = points.order(id.asc()).into_boxed();
+        if let Some(v) = data.layout_id {
+            selected = selected.filter(layout_id.eq(v));
+        }
+        let candidates = selected.load::<Points>(&mut con)
+        .map_err(|err| {
+            log::error!("Fetching points failed: {}", err);
+            ApiError::InternalErr
+        })?;
+
+        let changed = db::transaction(&mut con, |con| {
+            let mut changed: Vec<i32> = vec![];
+            for point in &candidates {
+                let new_value = match &area {
+                    Area::Polygon(corners) if spatial::inside(point, corners) => data.value,
+                    Area::Radial(center_x, center_y, radius) => {
+                        match falloff(data.value, spatial::distance(point, *center_x, *center_y), *radius) {
+                            Some(v) => v,
+                            None => continue,
+                        }
+                    },
+                    _ => continue,
+                };
+                db::points::set_value(con, point.id, new_value)?;
+                changed.push(point.id);
+            }
+            Ok(changed)
+        })?;
+
+        let point_list = points.filter(id.eq_any(&changed))
+        .order(id.asc())
+        .load::<Points>(&mut con)
+        .map_err(|err| {
+            log::error!("Fetching points failed: {}", err);
+            ApiError::InternalErr
+        })?;
+        let limited = db::points::limited(&mut con, &power)?;
+        Ok::<_, ApiError>(super::with_limits(point_list, &limited))
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Spatial command block failed: {}", err);
+        ApiError::InternalErr
+    })??;
+
+    if !result.is_empty() {
+        transitions.request(&result.iter().map(|v| v.item.id).collect::<Vec<_>>(), query.transition_ms);
+        batcher.request();
+    }
+    Ok(web::Json(result))
+}
+
+/// Checks fields the shape needs, rectangles become polygons
+fn valid_area(data: &SpatialCommand, query: &TransitionQuery) -> Result<Area, ApiError> {
+    let mut validator = Validator::new();
+    validator
+    .transition(query)
+    .range("value", data.value, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX)
+    .one_of("shape", &data.shape, SHAPES);
+
+    let needed: &[(&str, Option<f32>)] = match data.shape.as_str() {
+        "rect" => &[("x", data.x), ("y", data.y), ("width", data.width), ("height", data.height)],
+        "radial" => &[("x", data.x), ("y", data.y), ("radius", data.radius)],
+        _ => &[],
+    };
+    for (field, value) in needed {
+        match value {
+            Some(v) => validator.check(field, v.is_finite(), "finite"),
+            None => validator.check(field, false, "required"),
+        };
+    }
+    if let (Some(width), Some(height)) = (data.width, data.height) {
+        validator.min("width", width, 0.0).min("height", height, 0.0);
+    }
+    if let Some(v) = data.radius {
+        validator.check("radius", v > 0.0, "positive");
+    }
+    if data.shape == "polygon" {
+        match &data.points {
+            Some(corners) => {
+                validator.length("points", corners.len(), 3, None)
+                .check("points", corners.iter().flatten().all(|v| v.is_finite()), "finite");
+            },
+            None => {
+                validator.check("points", false, "required");
+            },
+        }
+    }
+    validator.finish()?;
+
+    let value = |v: Option<f32>| v.unwrap_or_default();
+    Ok(match data.shape.as_str() {
+        "rect" => {
+            let (x, y) = (value(data.x), value(data.y));
+            let (width, height) = (value(data.width), value(data.height));
+            Area::Polygon(vec![(x, y), (x + width, y), (x + width, y + height), (x, y + height)])
+        },
+        "radial" => Area::Radial(value(data.x), value(data.y), value(data.radius)),
+        _ => Area::Polygon(data.points.iter().flatten().map(|v| (v[0], v[1])).collect()),
+    })
+}
+
+/// Level of a point `distance` away from the center of a radial area, `None` outside of it
+fn falloff(value: i32, distance: f32, radius: f32) -> Option<i32> {
+    if distance >= radius {
+        return None;
+    }
+    Some((value as f32 * (1.0 - distance / radius)).round() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radial_levels_fall_off_to_the_radius() {
+        assert_eq!(falloff(1000, 0.0, 4.0), Some(1000));
+        assert_eq!(falloff(1000, 1.0, 4.0), Some(750));
+        assert_eq!(falloff(1000, 3.999, 4.0), Some(0));
+        assert_eq!(falloff(1000, 4.0, 4.0), None);
+        assert_eq!(falloff(1000, 5.0, 4.0), None);
+    }
+
+    #[test]
+    fn rectangles_become_polygons() {
+        let data = SpatialCommand {
+            layout_id: None,
+            shape: "rect".to_string(),
+            value: 1000,
+            x: Some(1.0),
+            y: Some(2.0),
+            width: Some(3.0),
+            height: Some(4.0),
+            radius: None,
+            points: None,
+        };
+        let query = TransitionQuery { transition_ms: None };
+        match valid_area(&data, &query) {
+            Ok(Area::Polygon(corners)) => assert_eq!(corners, vec![(1.0, 2.0), (4.0, 2.0), (4.0, 6.0), (1.0, 6.0)]),
+            _ => panic!("rect was not turned into a polygon"),
+        }
+    }
+}
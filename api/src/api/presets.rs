@@ -15,6 +15,7 @@ use crate::{
         QueryById,
     },
     middleware::auth::TokenData,
+    api::helpers::validation::Validator,
 };
 use actix_web::web;
 use diesel::{
@@ -62,6 +63,7 @@ pub async fn post(
     token: web::ReqData<TokenData>,
     data: web::Json<PubNewPresets>,
 ) -> Result<web::Json<Vec<PubPresets>>, ApiError> {
+    valid_name(&data.preset_name)?;
 
     let uid = token.claims.uid.clone();
 
@@ -205,6 +207,7 @@ pub async fn upd(
     token: web::ReqData<TokenData>,
     data: web::Json<PubPresets>,
 ) -> Result<web::Json<Vec<PubPresets>>, ApiError> {
+    valid_name(&data.preset_name)?;
     let uid = token.claims.uid.clone();
 
     let response = web::block(move || {
@@ -325,3 +328,10 @@ pub async fn del(
     })??;
     Ok(web::Json(presets))
 }
+
+/// Names may not be blank, they are stored as given
+fn valid_name(name: &str) -> Result<(), ApiError> {
+    Validator::new()
+    .length("preset_name", name.trim().chars().count(), 1, None)
+    .finish()
+}
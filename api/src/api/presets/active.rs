@@ -2,18 +2,19 @@ use actix_web::web;
 
 use diesel::prelude::*;
 
-use diesel::update;
-
 use crate::api::helpers::batcher::Batcher;
-use crate::models::Points;
+use crate::api::helpers::db;
+use crate::api::helpers::power::PowerBudget;
+use crate::api::helpers::transitions::Transitions;
+use crate::api::helpers::validation::Validator;
 use crate::{
     types::DbPool,
     middleware::auth::TokenData,
     api::ApiError,
     models::{
+        Limited,
         QueryById,
-        Presets,
-        PresetItems,
+        TransitionQuery,
     },
 };
 
@@ -62,8 +63,12 @@ pub async fn put(
     pool: web::Data<DbPool>,
     token: web::ReqData<TokenData>,
     data: web::Json<QueryById>,
+    query: web::Query<TransitionQuery>,
     batcher: web::Data<Batcher>,
-) -> Result<web::Json<QueryById>, ApiError> {
+    transitions: web::Data<Transitions>,
+    power: web::Data<PowerBudget>,
+) -> Result<web::Json<Limited<QueryById>>, ApiError> {
+    Validator::new().transition(&query).finish()?;
     let mut con = pool.get()
     .map_err(|err| {
         log::error!("Failed to get pool: {}", err);
@@ -71,69 +76,9 @@ pub async fn put(
     })?;
     let uid = token.claims.uid.clone();
     let active_id = data.id.clone();
-    web::block(move || {
-        use crate::schema::presets::dsl::*;
-        let selected_presets = presets.filter(id.eq(active_id.clone()).and(user_id.eq(uid.clone())))
-        .load::<Presets>(&mut con)
-        .map_err(|err| {
-            log::error!("Failed to fetch user related preset: {}", err);
-            ApiError::InternalErr
-        })?;
-
-        if selected_presets.len() > 1 {
-            return Err(ApiError::InternalErr);
-        }
-        if selected_presets.len() < 1 {
-            return Err(ApiError::Conflict);
-        }
-
-        update(presets).filter(active.eq(true))
-        .set(active.eq(false))
-        .execute(&mut con)
-        .map_err(|err| {
-            log::error!("Failed to fetch set presets as inactive: {}", err);
-            ApiError::InternalErr
-        })?;
-
-        update(presets).filter(id.eq(active_id.clone()))
-        .set(active.eq(true))
-        .execute(&mut con)
-        .map_err(|err| {
-            log::error!("Failed to set active preset: {}", err);
-            ApiError::InternalErr
-        })?;
-
-        use crate::schema::preset_items::dsl::{
-            preset_items,
-            preset_id,
-        };
-        let selected_preset_items = preset_items.filter(preset_id.eq(active_id.clone()))
-        .load::<PresetItems>(&mut con)
-        .map_err(|err| {
-            log::error!("Failed to fetch selected preset items: {}", err);
-            ApiError::InternalErr
-        })?;
-
-        use crate::schema::points::dsl::{
-            points,
-            id as point_table_id,
-            val as point_table_val,
-        };
-        for p_item in &selected_preset_items {
-            update(points).filter(point_table_id.eq(p_item.point_id))
-            .set(point_table_val.eq(p_item.val))
-            .execute(&mut con)
-            .map_err(|err| {
-                log::error!("Failed to update point [{}]: {}", p_item.point_id, err);
-                ApiError::InternalErr
-            })?;
-        }
-        points.order(point_table_id.asc())
-        .load::<Points>(&mut con)
-        .map_err(|err| {
-            log::error!("Fetching points failed: {}", err);
-            ApiError::InternalErr
-        })
+    let (point_ids, limited) = web::block(move || {
+        let point_ids = db::presets::activate(&mut con, active_id, Some(uid))?;
+        Ok::<_, ApiError>((point_ids, db::points::limited(&mut con, &power)?))
     })
     .await
     .map_err(|err| {
@@ -141,8 +86,12 @@ pub async fn put(
         ApiError::InternalErr
     })??;
 
+    transitions.request(&point_ids, query.transition_ms);
     batcher.request();
 
-    let result = QueryById { id: active_id };
+    let result = Limited {
+        item: QueryById { id: active_id },
+        limited: !limited.is_empty(),
+    };
     Ok(web::Json(result))
 }
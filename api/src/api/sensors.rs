@@ -0,0 +1,28 @@
+use crate::api::ApiError;
+use crate::types::DbPool;
+use crate::models::Sensors;
+use actix_web::web;
+use diesel::prelude::*;
+
+pub async fn get(pool: web::Data<DbPool>) -> Result<web::Json<Vec<Sensors>>, ApiError> {
+    let response = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+            log::error!("Could not fetch connection from pool: {}", err);
+            ApiError::InternalErr
+        })?;
+        use crate::schema::sensors::dsl::*;
+        sensors.order(id.asc())
+        .load::<Sensors>(&mut con)
+        .map_err(|err| {
+            log::error!("Fetching sensors failed: {}", err);
+            ApiError::InternalErr
+        })
+    })
+    .await
+    .map_err(|err| {
+        log::error!("Web block failed with: {}", err);
+        ApiError::InternalErr
+    })??;
+    Ok(web::Json(response))
+}
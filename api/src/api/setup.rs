@@ -21,6 +21,10 @@ use crate::{
 };
 
 use super::ApiError;
+use super::helpers::{
+    props::CREDENTIALS_MIN_LENGTH,
+    validation::Validator,
+};
 
 use diesel::{
     prelude::*,
@@ -82,12 +86,11 @@ pub async fn post(request: web::Json<SetupAuth>, pool: web::Data<DbPool>, shared
         }
 
         let mut identity = request.user.clone();
-        identity.user_name = identity.user_name.trim().to_string();
-        identity.password = identity.password.trim().to_string();
-
-        if identity.password.len() < 8 || identity.user_name.len() < 8 {
-            return Err(ApiError::BadRequest);
-        }
+        Validator::new()
+        .check("user.user_name", identity.user_name.trim() == identity.user_name, "trimmed")
+        .length("user.user_name", identity.user_name.chars().count(), CREDENTIALS_MIN_LENGTH, None)
+        .length("user.password", identity.password.chars().count(), CREDENTIALS_MIN_LENGTH, None)
+        .finish()?;
 
         identity.password = hash(identity.password, DEFAULT_COST)
         .map_err(|err| {
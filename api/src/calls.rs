@@ -42,6 +42,24 @@ pub struct AuthRes {
 
 #[derive(Serialize)]
 pub struct ErrMessage {
-    pub code: Option<u8>,
+    /// machine readable reason, only set for errors that carry details
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
     pub message: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// One rule a request field failed
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    /// path within the request, `[0].val` is `val` of the first item of a list
+    pub field: String,
+    pub rule: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<&'static [&'static str]>,
 }
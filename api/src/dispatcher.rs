@@ -1,9 +1,46 @@
 use crate::api::helpers::i2c::LightDevices;
+use crate::api::helpers::i2c::bus::Buses;
+use crate::api::helpers::db::devices::{
+  mark_failed,
+  mark_seen,
+};
+use crate::api::helpers::dmx::{
+  DmxOutput,
+  DmxPatch,
+};
+use crate::api::helpers::energy::EnergyMeter;
+use crate::api::helpers::frames::FrameCache;
+use crate::api::helpers::power::{device_watts, PowerBudget};
+use crate::api::helpers::transitions::Transitions;
+use crate::api::helpers::props::{
+  DMX_BUS,
+  I2C_BYTES_PER_LIGHT,
+  I2C_LIGHT_CONTROLLER,
+};
 use crate::types::DbPool;
-use crate::models::Points;
+use crate::models::{
+  NewEnergy,
+  Points,
+};
 use diesel::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
 
-pub async fn dispatch(db_pool: DbPool, i2c_device_id: u8) {
+/// Writes point levels to every light controller whose frame changed since last dispatch
+///
+/// With a transition requested, controllers start fading from their current output instead,
+/// a fade already heading to other levels is retargeted from where it is.
+/// DMX fixtures are sent through `dmx` instead of a bus.
+/// Levels are scaled down by `power` first when they would go over budget.
+pub async fn dispatch(
+  db_pool: DbPool,
+  buses: &Buses,
+  frames: &FrameCache,
+  transitions: &Transitions,
+  dmx: &DmxOutput,
+  power: &PowerBudget,
+  energy: &EnergyMeter,
+) {
   let mut con = match db_pool.get() {
       Ok(r) => r,
       Err(e) => {
@@ -24,39 +61,370 @@ pub async fn dispatch(db_pool: DbPool, i2c_device_id: u8) {
   use crate::schema::devices::dsl::{
     devices,
     adr,
+    bus,
+    device_type,
+    max_watts,
     id as device_id,
   };
 
-  let db_devices: Vec<(i32, i32)> = match devices.select((device_id, adr)).load::<(i32, i32)>(&mut con) {
+  let db_devices: Vec<(i32, i32, i32, Option<f32>)> = match devices.select((device_id, adr, bus, max_watts))
+    .filter(device_type.eq(I2C_LIGHT_CONTROLLER as i32))
+    .load::<(i32, i32, i32, Option<f32>)>(&mut con) {
     Ok(v) => v,
     Err(e) => {
       log::error!("Dispatcher fetching db devices failed: {}", e);
       return;
     },
   };
-  
-  let converted = LightDevices::convert_points(point_list.clone(), false);
 
-  let mut controller = match LightDevices::new(i2c_device_id) {
+  use crate::schema::devices::dsl::{
+    transport,
+    universe,
+    dmx_channel,
+    endpoint_count,
+  };
+  let patch = match devices.select((device_id, transport, universe, dmx_channel, endpoint_count))
+    .filter(bus.eq(DMX_BUS))
+    .load::<(i32, String, Option<i32>, Option<i32>, i32)>(&mut con) {
     Ok(v) => v,
     Err(e) => {
-      log::error!("Dispatcher failed to get i2c driver: {}", e);
+      log::error!("Dispatcher fetching dmx patch failed: {}", e);
       return;
     },
   };
-  
-  for (dev_id, dev_adr) in db_devices {
-    match converted.iter().position(|(k, _)| k.clone() == dev_id) {
-      Some(index) => {
-        match controller.set_light_levels(dev_adr as u16, converted[index].1.clone()) {
-          Ok(v) => v,
-          Err(e) => {
-            log::error!("Dispatcher point update failed at set_light_levels for {} : {}", dev_adr, e);
-            continue;
-          },
-        };
+  dmx.set_patch(patch.into_iter()
+    .filter_map(|(dev_id, dev_transport, dev_universe, dev_channel, dev_endpoints)| {
+      let patch = DmxPatch::new(&dev_transport, dev_universe?, dev_channel?, dev_endpoints)?;
+      Some((dev_id, patch))
+    })
+    .collect::<HashMap<_, _>>());
+
+  let mut device_points: HashMap<i32, Vec<&Points>> = HashMap::new();
+  for point in &point_list {
+    device_points.entry(point.device_id).or_default().push(point);
+  }
+  device_points.values_mut().for_each(|v| v.sort_by_key(|point| point.device_position));
+  energy.set_points(device_points.iter()
+    .map(|(dev_id, list)| (*dev_id, list.iter().map(|point| (point.id, point.watts)).collect()))
+    .collect());
+  let point_watts = device_watts(&point_list);
+  let limits = db_devices.iter()
+    .filter_map(|(dev_id, _, _, dev_max_watts)| Some((*dev_id, (*dev_max_watts)?)))
+    .collect::<HashMap<_, _>>();
+  let durations = transitions.take(&point_list.iter().map(|point| (point.id, point.device_id)).collect::<Vec<_>>());
+  let converted = power.limit(LightDevices::convert_points(point_list, false), &point_watts, &limits);
+  let outputs = db_devices.iter()
+    .map(|(dev_id, dev_adr, dev_bus, _)| (*dev_id, *dev_adr, *dev_bus))
+    .collect::<Vec<_>>();
+  let changed = changed_frames(frames, transitions, &durations, &outputs, &converted);
+  if changed.is_empty() {
+    log::debug!("Dispatcher found no changed frames, {:?}", frames.stats());
+    return;
+  }
+
+  let (seen, failed) = write(buses, frames, transitions, dmx, changed);
+  if let Err(e) = mark_seen(&mut con, seen) {
+    log::error!("Dispatcher failed to update device health: {}", e);
+  }
+  record_failed(&mut con, failed);
+  log::debug!("Dispatcher finished, {:?}", frames.stats());
+}
+
+/// Writes next frame of every running fade
+///
+/// Only failures are recorded so frames do not update the database many times a second,
+/// readback records contact again once the fade is done.
+pub async fn fade(db_pool: DbPool, buses: &Buses, frames: &FrameCache, transitions: &Transitions, dmx: &DmxOutput) {
+  let changed: Vec<(i32, i32, i32, Vec<i32>)> = transitions.step()
+    .into_iter()
+    .filter(|(dev_id, _, _, levels)| {
+      if frames.changed(*dev_id, levels) {
+        return true;
+      }
+      frames.skip(levels.len() * I2C_BYTES_PER_LIGHT as usize);
+      false
+    })
+    .collect();
+  if changed.is_empty() {
+    return;
+  }
+
+  let (_, failed) = write(buses, frames, transitions, dmx, changed);
+  if failed.is_empty() {
+    return;
+  }
+  let mut con = match db_pool.get() {
+      Ok(r) => r,
+      Err(e) => {
+        log::error!("Dispatcher failed to fetch db_pool for fade: {}", e);
+        return;
+      }
+  };
+  record_failed(&mut con, failed);
+}
+
+/// `(device id, address, bus, levels)` to write now for every `(device id, address, bus)` whose converted levels changed
+///
+/// Changed devices with a duration in `durations` and a known output start fading there instead.
+fn changed_frames(
+  frames: &FrameCache,
+  transitions: &Transitions,
+  durations: &HashMap<i32, Duration>,
+  db_devices: &[(i32, i32, i32)],
+  converted: &[(i32, Vec<i32>)],
+) -> Vec<(i32, i32, i32, Vec<i32>)> {
+  db_devices.iter()
+    .filter_map(|(dev_id, dev_adr, dev_bus)| {
+      let (_, levels) = converted.iter().find(|(k, _)| k == dev_id)?;
+      if transitions.target(*dev_id).as_ref() == Some(levels) {
+        return None;
+      }
+      if !frames.changed(*dev_id, levels) {
+        transitions.cancel(*dev_id);
+        frames.skip(levels.len() * I2C_BYTES_PER_LIGHT as usize);
+        return None;
+      }
+      let from = transitions.current(*dev_id).or_else(|| frames.last(*dev_id));
+      match (durations.get(dev_id), from) {
+        (Some(duration), Some(from)) if from.len() == levels.len() => {
+          transitions.start(*dev_id, *dev_adr, *dev_bus, from, levels.clone(), *duration);
+          None
+        },
+        _ => {
+          transitions.cancel(*dev_id);
+          Some((*dev_id, *dev_adr, *dev_bus, levels.clone()))
+        },
+      }
+    })
+    .collect()
+}
+
+/// Writes `(device id, address, bus, levels)` frames, returns devices that took them and those that failed
+fn write(
+  buses: &Buses,
+  frames: &FrameCache,
+  transitions: &Transitions,
+  dmx: &DmxOutput,
+  changed: Vec<(i32, i32, i32, Vec<i32>)>,
+) -> (Vec<i32>, Vec<(i32, &'static str)>) {
+  let mut seen: Vec<i32> = vec![];
+  let mut failed: Vec<(i32, &'static str)> = vec![];
+  let dmx_frames = changed.iter()
+    .filter(|v| v.2 == DMX_BUS)
+    .map(|(dev_id, _, _, levels)| (*dev_id, levels.clone()))
+    .collect::<Vec<_>>();
+  for (dev_id, result) in dmx.send(&dmx_frames) {
+    match result {
+      Ok(_) => {
+        if let Some((_, levels)) = dmx_frames.iter().find(|v| v.0 == dev_id) {
+          frames.store(dev_id, levels.clone());
+        }
+        seen.push(dev_id);
+      },
+      Err(e) => {
+        frames.forget(dev_id);
+        transitions.cancel(dev_id);
+        failed.push((dev_id, e.kind()));
+        log::error!("Dispatcher failed to send dmx fixture [{}]: {}", dev_id, e);
+      },
+    }
+  }
+
+  for (dev_id, dev_adr, dev_bus, _) in changed.iter().filter(|v| v.2 != DMX_BUS && buses.get(v.2).is_none()) {
+    frames.forget(*dev_id);
+    transitions.cancel(*dev_id);
+    log::error!("Dispatcher skipped {} on bus {} that is not configured", dev_adr, dev_bus);
+  }
+
+  for bus_config in buses.iter() {
+    let bus_changes = changed.iter().filter(|v| v.2 == bus_config.id).collect::<Vec<_>>();
+    if bus_changes.is_empty() {
+      continue;
+    }
+
+    let mut controller = match LightDevices::new(bus_config) {
+      Ok(v) => v,
+      Err(e) => {
+        log::error!("Dispatcher failed to get i2c driver for bus {}: {}", bus_config.id, e);
+        bus_changes.iter().for_each(|v| {
+          frames.forget(v.0);
+          transitions.cancel(v.0);
+          failed.push((v.0, e.kind()));
+        });
+        continue;
       },
-      None => (),
     };
+
+    for (dev_id, dev_adr, _, levels) in bus_changes {
+      match controller.set_light_levels(*dev_adr as u16, levels.clone()) {
+        Ok(_) => {
+          frames.store(*dev_id, levels.clone());
+          seen.push(*dev_id);
+        },
+        Err(e) => {
+          // unknown state on hardware, next dispatch has to write it again
+          frames.forget(*dev_id);
+          transitions.cancel(*dev_id);
+          failed.push((*dev_id, e.kind()));
+          log::error!("Dispatcher point update failed at set_light_levels for {} on bus {} : {}", dev_adr, bus_config.id, e);
+        },
+      };
+    }
+  }
+
+  (seen, failed)
+}
+
+fn record_failed(con: &mut crate::types::DbCon, failed: Vec<(i32, &'static str)>) {
+  for (dev_id, kind) in failed {
+    if let Err(e) = mark_failed(con, dev_id, kind) {
+      log::error!("Dispatcher failed to update device [{}] health: {}", dev_id, e);
+    }
+  }
+}
+
+/// Stores energy used since the last sample, one row per lit point
+///
+/// Points removed since the last dispatch are stored without a point, so their energy still adds up.
+pub async fn sample_energy(db_pool: DbPool, energy: &EnergyMeter) {
+  let (started_at, sampled_at, totals) = energy.take();
+  let totals = totals.into_iter()
+    .filter(|(_, wh)| *wh > 0.0)
+    .collect::<Vec<_>>();
+  if totals.is_empty() {
+    return;
+  }
+
+  let mut con = match db_pool.get() {
+      Ok(r) => r,
+      Err(e) => {
+        log::error!("Energy sample failed to fetch db_pool: {}", e);
+        return;
+      }
+  };
+  let point_ids = match crate::schema::points::dsl::points
+    .select(crate::schema::points::dsl::id)
+    .load::<i32>(&mut con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Energy sample fetching points failed: {}", e);
+      return;
+    },
+  };
+  let samples = totals.into_iter()
+    .map(|(point_id, wh)| NewEnergy {
+      point_id: Some(point_id).filter(|v| point_ids.contains(v)),
+      started_at,
+      sampled_at,
+      wh,
+    })
+    .collect::<Vec<_>>();
+  use crate::schema::energy::dsl::energy as energy_table;
+  if let Err(e) = diesel::insert_into(energy_table).values(&samples).execute(&mut con) {
+    log::error!("Energy sample failed to store {} points: {}", samples.len(), e);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::api::helpers::i2c::bus::Backend;
+
+  fn simulators(connections: &str, specs: &str) -> Buses {
+    Buses::new("simulator", connections, specs, "").unwrap()
+  }
+
+  fn levels(buses: &Buses, bus_id: i32, address: u16) -> Option<Vec<u16>> {
+    match &buses.get(bus_id)?.backend {
+      Backend::Simulator(v) => v.levels(address),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn unchanged_frames_are_not_written() {
+    let buses = simulators("1", "0x08:2,0x09:2");
+    let frames = FrameCache::new();
+    let transitions = Transitions::new();
+    let db_devices = [(1, 0x08, 1), (2, 0x09, 1)];
+    let dispatches = [
+      vec![(1, vec![10, 20]), (2, vec![30, 40])],
+      vec![(1, vec![10, 20]), (2, vec![30, 50])],
+      vec![(1, vec![10, 20]), (2, vec![30, 50])],
+    ];
+    let written = dispatches.iter()
+      .map(|converted| {
+        let changed = changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, converted);
+        let ids = changed.iter().map(|v| v.0).collect::<Vec<_>>();
+        let (seen, _) = write(&buses, &frames, &transitions, &DmxOutput::new(None), changed);
+        assert_eq!(seen, ids);
+        ids
+      })
+      .collect::<Vec<_>>();
+
+    assert_eq!(written, vec![vec![1, 2], vec![2], vec![]]);
+    let stats = frames.stats();
+    assert_eq!((stats.frames_sent, stats.frames_skipped, stats.bytes_saved, stats.transactions_saved), (3, 3, 12, 3));
+    assert_eq!(levels(&buses, 1, 0x08), Some(vec![10, 20]));
+    assert_eq!(levels(&buses, 1, 0x09), Some(vec![30, 50]));
+  }
+
+  #[test]
+  fn failed_frames_are_written_again() {
+    let buses = simulators("1", "0x08:2");
+    let frames = FrameCache::new();
+    let transitions = Transitions::new();
+    let db_devices = [(1, 0x08, 1), (2, 0x30, 1)];
+    let converted = [(1, vec![1, 2, 3]), (2, vec![4])];
+    let (seen, failed) = write(&buses, &frames, &transitions, &DmxOutput::new(None), changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, &converted));
+    assert!(seen.is_empty());
+    assert_eq!(failed, vec![(1, "invalid_input"), (2, "nack")]);
+    assert_eq!(changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, &converted).len(), 2);
+    assert_eq!(frames.stats().frames_sent, 0);
+    assert_eq!(levels(&buses, 1, 0x08), Some(vec![0, 0]));
+  }
+
+  #[test]
+  fn frames_go_to_the_bus_of_their_device() {
+    let buses = simulators("1,3", "0x08:1;0x08:1");
+    let frames = FrameCache::new();
+    let transitions = Transitions::new();
+    let db_devices = [(1, 0x08, 1), (2, 0x08, 3), (3, 0x08, 4)];
+    let converted = [(1, vec![10]), (2, vec![20]), (3, vec![30])];
+    write(&buses, &frames, &transitions, &DmxOutput::new(None), changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, &converted));
+    assert_eq!(levels(&buses, 1, 0x08), Some(vec![10]));
+    assert_eq!(levels(&buses, 3, 0x08), Some(vec![20]));
+    assert!(!frames.changed(2, &[20]));
+    assert!(frames.changed(3, &[30]));
+  }
+
+  #[test]
+  fn faulted_devices_are_reported_until_they_answer() {
+    let buses = Buses::new("simulator", "1", "0x08:1,0x09:1", "0x09:timeout:1").unwrap();
+    let frames = FrameCache::new();
+    let transitions = Transitions::new();
+    let db_devices = [(1, 0x08, 1), (2, 0x09, 1)];
+    let converted = [(1, vec![10]), (2, vec![20])];
+    let (seen, failed) = write(&buses, &frames, &transitions, &DmxOutput::new(None), changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, &converted));
+    assert_eq!((seen, failed), (vec![1], vec![(2, "timeout")]));
+    let (seen, failed) = write(&buses, &frames, &transitions, &DmxOutput::new(None), changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, &converted));
+    assert_eq!((seen, failed), (vec![2], vec![]));
+    assert_eq!(levels(&buses, 1, 0x09), Some(vec![20]));
+  }
+
+  #[test]
+  fn only_devices_with_a_duration_fade() {
+    let buses = simulators("1", "0x08:1,0x09:1");
+    let frames = FrameCache::new();
+    let transitions = Transitions::new();
+    let db_devices = [(1, 0x08, 1), (2, 0x09, 1)];
+    write(&buses, &frames, &transitions, &DmxOutput::new(None), changed_frames(&frames, &transitions, &HashMap::new(), &db_devices, &[(1, vec![0]), (2, vec![0])]));
+
+    let durations = HashMap::from([(1, Duration::from_millis(1000))]);
+    let changed = changed_frames(&frames, &transitions, &durations, &db_devices, &[(1, vec![1000]), (2, vec![1000])]);
+    assert_eq!(changed, vec![(2, 0x09, 1, vec![1000])]);
+    assert!(transitions.fading(1));
+    assert_eq!(transitions.target(1), Some(vec![1000]));
+    assert!(!transitions.fading(2));
   }
 }
@@ -6,11 +6,26 @@ pub mod api;
 pub mod middleware;
 pub mod types;
 pub mod dispatcher;
+pub mod presence;
+pub mod readback;
+pub mod startup;
+pub mod mqtt;
 
 use api::expose_api;
 use api::helpers::batcher::Batcher;
+use api::helpers::dmx::DmxOutput;
+use api::helpers::energy::EnergyMeter;
+use api::helpers::frames::FrameCache;
+use api::helpers::power::PowerBudget;
 use api::helpers::i2c::LightDevices;
+use api::helpers::transitions::Transitions;
+use api::helpers::i2c::bus::Buses;
 use dotenvy::dotenv;
+use mqtt::{
+    MqttBridge,
+    MqttSettings,
+};
+use startup::StartupPolicy;
 use types::{
     Tokens,
     SharedStorage,
@@ -58,15 +73,44 @@ async fn main() -> std::io::Result<()> {
 
     let setup_secret = env::var("SETUP_SECRET").expect("SETUP_SECRET must be set");
 
-    let i2c_device = env::var("I2C").expect("I2C must be set").parse::<u8>().expect("I2C must be a number (u8)");
+    let i2c_devices = env::var("I2C").expect("I2C must be set");
+    let i2c_backend = env::var("I2C_BACKEND").unwrap_or_default();
+    let i2c_simulator = env::var("I2C_SIMULATOR").unwrap_or_default();
+    let i2c_simulator_faults = env::var("I2C_SIMULATOR_FAULTS").unwrap_or_default();
+    let serial_ports = env::var("SERIAL").unwrap_or_default();
+    let serial_simulator = env::var("SERIAL_SIMULATOR").unwrap_or_default();
+    let i2c_scan = env::var("I2C_SCAN").unwrap_or_default();
+    let i2c_scan_exclude = env::var("I2C_SCAN_EXCLUDE").unwrap_or_default();
+    let i2c_scan_fixed = env::var("I2C_SCAN_FIXED").unwrap_or_default();
+    let buses = Buses::new(&i2c_backend, &i2c_devices, &i2c_simulator, &i2c_simulator_faults)
+        .expect("I2C must be a list of numbers (u8), I2C_BACKEND one of (linux, simulator) with valid I2C_SIMULATOR and I2C_SIMULATOR_FAULTS")
+        .with_serial(&serial_ports, &serial_simulator)
+        .expect("SERIAL must list terminal paths or pty with valid SERIAL_SIMULATOR")
+        .with_scan(&i2c_scan, &i2c_scan_exclude, &i2c_scan_fixed)
+        .expect("I2C_SCAN, I2C_SCAN_EXCLUDE and I2C_SCAN_FIXED must list addresses within 0x08-0x76");
 
-    match LightDevices::test(i2c_device).err() {
-        Some(err) => {
-            panic!("{}", err);
-        },
-        None => {}
+    for bus_config in buses.iter() {
+        if let Err(err) = LightDevices::test(bus_config) {
+            panic!("i2c bus {}: {}", bus_config.id, err);
+        }
     }
     
+    let default_presence_rate_ms = 1000;
+    let presence_rate_ms = match env::var("PRESENCE_RATE_MS") {
+        Ok(v) => v.parse::<u64>().unwrap_or(default_presence_rate_ms),
+        Err(_) => default_presence_rate_ms,
+    };
+
+    let startup_policy = StartupPolicy::new(&env::var("STARTUP_SYNC").unwrap_or_default())
+        .expect("STARTUP_SYNC must be one of (push, adopt, blackout, keep)");
+
+    // 0 turns readback off
+    let default_readback_rate_ms = 10000;
+    let readback_rate_ms = match env::var("READBACK_RATE_MS") {
+        Ok(v) => v.parse::<u64>().unwrap_or(default_readback_rate_ms),
+        Err(_) => default_readback_rate_ms,
+    };
+
     let default_rate_ms = 200;
     let dispatcher_rate_ms = match env::var("DISPATCHER_RATE_MS") {
         Ok(v) => match v.parse::<u64>() {
@@ -75,6 +119,52 @@ async fn main() -> std::io::Result<()> {
         },
         Err(_) => default_rate_ms.to_owned(),
     };
+
+    // unicast target for art-net and sacn, broadcast and multicast without it
+    let dmx_target = env::var("DMX_TARGET").ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| v.trim().parse::<std::net::IpAddr>().expect("DMX_TARGET must be an ip address"));
+    // 0 turns resending universes off
+    let default_dmx_refresh_ms = 1000;
+    let dmx_refresh_ms = match env::var("DMX_REFRESH_MS") {
+        Ok(v) => v.parse::<u64>().unwrap_or(default_dmx_refresh_ms),
+        Err(_) => default_dmx_refresh_ms,
+    };
+
+    // bridge stays off without a broker
+    let mqtt_settings = env::var("MQTT_HOST").ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(|host| MqttSettings {
+            host: host.trim().to_string(),
+            port: env::var("MQTT_PORT").ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| v.trim().parse::<u16>().expect("MQTT_PORT must be a number (u16)"))
+                .unwrap_or(1883),
+            user: env::var("MQTT_USER").ok().filter(|v| !v.is_empty()),
+            password: env::var("MQTT_PASSWORD").ok().filter(|v| !v.is_empty()),
+            client_id: env::var("MQTT_CLIENT_ID").ok().filter(|v| !v.is_empty()).unwrap_or_else(|| "l1ghts".to_string()),
+            prefix: env::var("MQTT_PREFIX").ok().filter(|v| !v.is_empty()).unwrap_or_else(|| "l1ghts".to_string()),
+            discovery_prefix: env::var("MQTT_DISCOVERY_PREFIX").ok().filter(|v| !v.is_empty()).unwrap_or_else(|| "homeassistant".to_string()),
+        });
+
+    // maximum load of the whole installation, unlimited without it
+    let power_budget = env::var("POWER_BUDGET_WATTS").ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| v.trim().parse::<f32>().ok().filter(|v| v.is_finite() && *v > 0.0).expect("POWER_BUDGET_WATTS must be a positive number"));
+
+    // interval of energy rows per point, 0 turns accounting off
+    let default_energy_sample_ms = 300000;
+    let energy_sample_ms = match env::var("ENERGY_SAMPLE_MS") {
+        Ok(v) => v.parse::<u64>().unwrap_or(default_energy_sample_ms),
+        Err(_) => default_energy_sample_ms,
+    };
+
+    // frame interval while fades are running
+    let default_transition_rate_ms = 40;
+    let transition_rate_ms = match env::var("TRANSITION_RATE_MS") {
+        Ok(v) => v.parse::<u64>().unwrap_or(default_transition_rate_ms).max(1),
+        Err(_) => default_transition_rate_ms,
+    };
     
     // Duration::from_millis();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -84,32 +174,132 @@ async fn main() -> std::io::Result<()> {
         .expect("Could not initialized database pool");
 
     let cache_lock = SharedStorage {
-        i2c_device: Arc::new(i2c_device),
+        buses: Arc::new(buses.clone()),
         setup_secret: Arc::new(setup_secret),
     };
 
     let batcher = Batcher::new();
+    let frames = FrameCache::new();
+    let transitions = Transitions::new();
+    let dmx = DmxOutput::new(dmx_target);
+    let mqtt = mqtt_settings.map(MqttBridge::new);
+    let power = PowerBudget::new(power_budget);
+    let energy = EnergyMeter::new();
 
     let background_batcher = batcher.clone();
     let db_pool_batcher = db_pool.clone();
-    let device_id_batcher = i2c_device.clone();
+    let buses_batcher = buses.clone();
+    let frames_batcher = frames.clone();
+    let transitions_batcher = transitions.clone();
+    let dmx_batcher = dmx.clone();
+    let power_batcher = power.clone();
+    let energy_batcher = energy.clone();
+    let mqtt_batcher = mqtt.as_ref().map(|(bridge, _)| bridge.clone());
     actix_web::rt::spawn(async move {
         loop {
-            actix_web::rt::time::sleep(Duration::from_millis(dispatcher_rate_ms)).await;
+            let rate_ms = match transitions_batcher.active() {
+                true => dispatcher_rate_ms.min(transition_rate_ms),
+                false => dispatcher_rate_ms,
+            };
+            actix_web::rt::time::sleep(Duration::from_millis(rate_ms)).await;
+            if energy_sample_ms > 0 {
+                energy_batcher.accumulate(&frames_batcher);
+            }
 
             if background_batcher.pull() {
-                dispatcher::dispatch(db_pool_batcher.clone(), device_id_batcher).await;
+                dispatcher::dispatch(
+                    db_pool_batcher.clone(),
+                    &buses_batcher,
+                    &frames_batcher,
+                    &transitions_batcher,
+                    &dmx_batcher,
+                    &power_batcher,
+                    &energy_batcher,
+                ).await;
+                if let Some(bridge) = &mqtt_batcher {
+                    bridge.publish(&db_pool_batcher);
+                }
+            }
+            if transitions_batcher.active() {
+                dispatcher::fade(
+                    db_pool_batcher.clone(),
+                    &buses_batcher,
+                    &frames_batcher,
+                    &transitions_batcher,
+                    &dmx_batcher,
+                ).await;
             }
         }
     });
 
+    let db_pool_presence = db_pool.clone();
+    let buses_presence = buses.clone();
+    actix_web::rt::spawn(async move {
+        loop {
+            actix_web::rt::time::sleep(Duration::from_millis(presence_rate_ms)).await;
+            presence::poll(db_pool_presence.clone(), &buses_presence).await;
+        }
+    });
+
+    if let Some((bridge, eventloop)) = mqtt {
+        actix_web::rt::spawn(mqtt::run(bridge, eventloop, db_pool.clone(), batcher.clone(), transitions.clone()));
+    }
+
+    if energy_sample_ms > 0 {
+        let db_pool_energy = db_pool.clone();
+        let energy_sample = energy.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                actix_web::rt::time::sleep(Duration::from_millis(energy_sample_ms)).await;
+                dispatcher::sample_energy(db_pool_energy.clone(), &energy_sample).await;
+            }
+        });
+    }
+
+    if dmx_refresh_ms > 0 {
+        let dmx_refresh = dmx.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                actix_web::rt::time::sleep(Duration::from_millis(dmx_refresh_ms)).await;
+                dmx_refresh.refresh();
+            }
+        });
+    }
+
+    if readback_rate_ms > 0 {
+        let db_pool_readback = db_pool.clone();
+        let buses_readback = buses.clone();
+        let frames_readback = frames.clone();
+        let batcher_readback = batcher.clone();
+        let transitions_readback = transitions.clone();
+        let power_readback = power.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                actix_web::rt::time::sleep(Duration::from_millis(readback_rate_ms)).await;
+                readback::verify(
+                    db_pool_readback.clone(),
+                    &buses_readback,
+                    &frames_readback,
+                    &transitions_readback,
+                    &batcher_readback,
+                    &power_readback,
+                ).await;
+            }
+        });
+    }
+
     env_logger::init_from_env(Env::default().default_filter_or("info"));
+    startup::sync(startup_policy, db_pool.clone(), &buses, &frames, &batcher).await;
     HttpServer::new(move || {
             App::new()
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(tokens.clone()))
             .app_data(web::Data::new(cache_lock.clone()))
             .app_data(web::Data::new(batcher.clone()))
+            .app_data(web::Data::new(frames.clone()))
+            .app_data(web::Data::new(transitions.clone()))
+            .app_data(web::Data::new(power.clone()))
+            .app_data(web::Data::new(dmx.clone()))
             .wrap(
                 if env::var("ENV").expect("ENV must be set") == "dev" {
                     Cors::permissive()
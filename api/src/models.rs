@@ -13,6 +13,24 @@ pub struct Credentials {
     pub recovery_expires: Option<NaiveDateTime>,
 }
 
+#[derive(Queryable, Debug, Serialize, Clone)]
+pub struct Energy {
+    pub id: i32,
+    pub point_id: Option<i32>,
+    pub started_at: NaiveDateTime,
+    pub sampled_at: NaiveDateTime,
+    pub wh: f64,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = energy)]
+pub struct NewEnergy {
+    pub point_id: Option<i32>,
+    pub started_at: NaiveDateTime,
+    pub sampled_at: NaiveDateTime,
+    pub wh: f64,
+}
+
 #[derive(Insertable, Debug)]
 #[diesel(table_name = credentials)]
 pub struct NewCredentials {
@@ -27,6 +45,21 @@ pub struct Devices {
     pub id: i32,
     pub adr: i32,
     pub endpoint_count: i32,
+    pub device_type: i32,
+    pub bus: i32,
+    pub online: bool,
+    pub last_seen: Option<NaiveDateTime>,
+    pub failure_count: i32,
+    pub last_error: Option<String>,
+    pub drift_count: i32,
+    pub last_drift: Option<NaiveDateTime>,
+    pub protocol_version: i32,
+    pub features: i32,
+    pub firmware_version: Option<String>,
+    pub transport: String,
+    pub universe: Option<i32>,
+    pub dmx_channel: Option<i32>,
+    pub max_watts: Option<f32>,
 }
 
 #[derive(Insertable, Debug, Serialize, Clone)]
@@ -34,6 +67,29 @@ pub struct Devices {
 pub struct NewDevices {
     pub adr: i32,
     pub endpoint_count: i32,
+    pub device_type: i32,
+    pub bus: i32,
+    pub protocol_version: i32,
+    pub features: i32,
+    pub firmware_version: Option<String>,
+    pub transport: String,
+}
+
+#[derive(Queryable, Debug, Serialize, Clone)]
+pub struct Sensors {
+    pub id: i32,
+    pub device_id: i32,
+    pub device_position: i32,
+    pub occupied: bool,
+    pub changed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = sensors)]
+pub struct NewSensors {
+    pub device_id: i32,
+    pub device_position: i32,
+    pub occupied: bool,
 }
 
 #[derive(Queryable, Debug, Deserialize, Serialize, Clone)]
@@ -50,6 +106,10 @@ pub struct Points {
     pub watts: f32,
     pub active: bool,
     pub tag: Option<String>,
+    pub curve: String,
+    pub curve_gamma: f32,
+    pub curve_table: Option<Vec<i32>>,
+    pub layout_id: Option<i32>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -64,9 +124,15 @@ pub struct PointsRequest {
     pub watts: f32,
     pub active: bool,
     pub tag: Option<String>,
+    pub curve: Option<String>,
+    pub curve_gamma: Option<f32>,
+    pub curve_table: Option<Vec<i32>>,
+    /// left out keeps the layout, null takes the point off its layout
+    #[serde(default, deserialize_with = "double_option")]
+    pub layout_id: Option<Option<i32>>,
 }
 
-#[derive(AsChangeset)]
+#[derive(AsChangeset, Deserialize)]
 #[diesel(table_name = points)]
 pub struct PointsUpdate {
     pub val: Option<i32>,
@@ -77,7 +143,50 @@ pub struct PointsUpdate {
     pub rotation: Option<f32>,
     pub watts: Option<f32>,
     pub active: Option<bool>,
+    #[serde(default, deserialize_with = "double_option")]
     pub tag: Option<Option<String>>,
+    pub curve: Option<String>,
+    pub curve_gamma: Option<f32>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub curve_table: Option<Option<Vec<i32>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub layout_id: Option<Option<i32>>,
+}
+
+/// Brightness for every point in an area of the floor plan
+#[derive(Debug, Deserialize)]
+pub struct SpatialCommand {
+    /// `rect`, `polygon` or `radial`
+    pub shape: String,
+    /// level inside the shape, the center level for `radial`
+    pub value: i32,
+    /// only points on this layout, all points when left out
+    pub layout_id: Option<i32>,
+    /// top left corner for `rect`, center for `radial`
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    /// corners for `polygon`, in order
+    pub points: Option<Vec<[f32; 2]>>,
+    /// distance from the center where `radial` level falls to zero
+    pub radius: Option<f32>,
+}
+
+/// Partial update of one point, fields left out are kept
+#[derive(Deserialize)]
+pub struct PointsPatch {
+    pub id: i32,
+    #[serde(flatten)]
+    pub changes: PointsUpdate,
+}
+
+/// Outcome of a change, `limited` is set when the power budget scales its levels down
+#[derive(Debug, Serialize)]
+pub struct Limited<T: Serialize> {
+    #[serde(flatten)]
+    pub item: T,
+    pub limited: bool,
 }
 
 #[derive(Insertable, Debug)]
@@ -96,6 +205,32 @@ pub struct NewPoints {
     pub tag: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PointsQuery {
+    pub layout_id: Option<i32>,
+}
+
+#[derive(Queryable, Debug, Serialize, Clone)]
+pub struct Layouts {
+    pub id: i32,
+    pub layout_name: String,
+    pub floor: i32,
+    pub width: f32,
+    pub height: f32,
+    pub background: Option<String>,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Deserialize, Clone)]
+#[diesel(table_name = layouts)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewLayouts {
+    pub layout_name: String,
+    pub floor: i32,
+    pub width: f32,
+    pub height: f32,
+    pub background: Option<String>,
+}
+
 #[derive(Queryable, Debug)]
 pub struct Presets {
     pub id: i32,
@@ -141,6 +276,71 @@ pub struct SinglePoint {
     pub value: i32,
 }
 
+#[derive(Insertable, Debug)]
+#[diesel(table_name = devices)]
+pub struct NewDmxDevices {
+    pub adr: i32,
+    pub endpoint_count: i32,
+    pub device_type: i32,
+    pub bus: i32,
+    pub transport: String,
+    pub universe: Option<i32>,
+    pub dmx_channel: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DmxRequest {
+    pub transport: String,
+    pub universe: i32,
+    pub channel: i32,
+    pub endpoint_count: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceAddress {
+    pub adr: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceSettings {
+    pub pwm_frequency: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DevicePower {
+    pub max_watts: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnergyQuery {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PointEnergy {
+    // null for points that were removed
+    pub point_id: Option<i32>,
+    pub wh: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceEnergy {
+    pub device_id: i32,
+    pub wh: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagEnergy {
+    pub tag: Option<String>,
+    pub wh: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransitionQuery {
+    pub transition_ms: Option<u64>,
+}
+
 #[derive(Queryable, Debug)]
 pub struct PresetItems {
     pub id: i32,
@@ -157,6 +357,99 @@ pub struct NewPresetItems {
     pub val: i32,
 }
 
+#[derive(Queryable, Debug, Serialize, Clone)]
+pub struct PointGroups {
+    pub id: i32,
+    pub group_name: String,
+    pub kind: String,
+    pub icon: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = point_groups)]
+pub struct NewPointGroups {
+    pub group_name: String,
+    pub kind: String,
+    pub icon: Option<String>,
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct PointGroupItems {
+    pub id: i32,
+    pub group_id: i32,
+    pub point_id: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = point_group_items)]
+pub struct NewPointGroupItems {
+    pub group_id: i32,
+    pub point_id: i32,
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = scan_reports)]
+pub struct ScanReports {
+    pub bus: i32,
+    pub scanned_at: NaiveDateTime,
+    pub addresses: i32,
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct ScanEntries {
+    pub id: i32,
+    pub bus: i32,
+    pub adr: i32,
+    pub identifier: i32,
+    pub known: bool,
+    pub endpoint_count: i32,
+    pub probed: bool,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = scan_entries)]
+pub struct NewScanEntries {
+    pub bus: i32,
+    pub adr: i32,
+    pub identifier: i32,
+    pub known: bool,
+    pub endpoint_count: i32,
+    pub probed: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GroupRequest {
+    pub group_name: String,
+    /// `room` or `group`
+    pub kind: String,
+    pub icon: Option<String>,
+    pub points: Vec<i32>,
+}
+
+/// Group with state derived from its member points
+#[derive(Debug, Serialize, Clone)]
+pub struct GroupState {
+    pub id: i32,
+    pub group_name: String,
+    pub kind: String,
+    pub icon: Option<String>,
+    pub points: Vec<i32>,
+    /// average level of the members, 0 without any
+    pub level: i32,
+    /// any active member is lit
+    pub on: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GroupCommand {
+    /// `set`, `scale` or `toggle`
+    pub action: String,
+    /// level for `set`, level lights are toggled on to (defaults to their own, full when dark)
+    pub value: Option<i32>,
+    /// multiplier of every member level for `scale`
+    pub factor: Option<f32>,
+}
+
 #[derive(Queryable, Debug, Clone)]
 pub struct CredentialRefresh {
     pub id: i32,
@@ -176,3 +469,12 @@ pub struct NewCredentialRefresh {
     pub created_at: NaiveDateTime,
     pub used_at: NaiveDateTime,
 }
+
+/// Tells a field that was left out (`None`) apart from an explicit null (`Some(None)`)
+fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,507 @@
+use crate::api::ApiError;
+use crate::api::helpers::batcher::Batcher;
+use crate::api::helpers::db;
+use crate::api::helpers::props::{
+  LIGHT_LEVEL_MAX,
+  LIGHT_LEVEL_MIN,
+  MQTT_RECONNECT_MS,
+  TRANSITION_MAX_MS,
+};
+use crate::api::helpers::transitions::Transitions;
+use crate::api::helpers::validation::Validator;
+use crate::types::DbPool;
+use crate::models::{
+  Points,
+  Presets,
+};
+use actix_web::web;
+use diesel::prelude::*;
+use rumqttc::{
+  AsyncClient,
+  Event,
+  EventLoop,
+  LastWill,
+  MqttOptions,
+  Packet,
+  QoS,
+};
+use serde::{
+  Deserialize,
+  Serialize,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{
+  Arc,
+  Mutex,
+};
+use std::time::Duration;
+
+/// Broker connection and topics, read from `MQTT_*` environment
+#[derive(Clone, Debug)]
+pub struct MqttSettings {
+  pub host: String,
+  pub port: u16,
+  pub user: Option<String>,
+  pub password: Option<String>,
+  /// client id, also node id of discovery topics
+  pub client_id: String,
+  /// base of state, command and availability topics
+  pub prefix: String,
+  pub discovery_prefix: String,
+}
+
+static LIGHT_STATES: &[&str] = &["ON", "OFF"];
+
+/// Command of a Home Assistant light with json schema, `transition` is in seconds
+#[derive(Deserialize, Debug)]
+struct LightCommand {
+  state: Option<String>,
+  brightness: Option<i32>,
+  transition: Option<f64>,
+}
+
+/// State of a Home Assistant light with json schema
+#[derive(Serialize, Debug, PartialEq, Clone)]
+struct LightState {
+  state: &'static str,
+  brightness: i32,
+}
+
+/// What the broker holds from us, so only changes get published
+#[derive(Default)]
+struct Published {
+  points: HashMap<i32, (Option<String>, LightState)>,
+  presets: HashMap<i32, String>,
+}
+
+/// Optional bridge exposing points as Home Assistant lights and presets as scenes
+///
+/// Commands go through the same helpers and `Batcher` as the REST handlers,
+/// state is published by the dispatcher loop after each dispatch.
+#[derive(Clone)]
+pub struct MqttBridge {
+  client: AsyncClient,
+  settings: MqttSettings,
+  published: Arc<Mutex<Published>>,
+}
+
+impl MqttBridge {
+  pub fn new(settings: MqttSettings) -> (Self, EventLoop) {
+    let mut options = MqttOptions::new(&settings.client_id, &settings.host, settings.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(availability_topic(&settings), "offline", QoS::AtLeastOnce, true));
+    if let Some(user) = &settings.user {
+      options.set_credentials(user, settings.password.clone().unwrap_or_default());
+    }
+    let (client, eventloop) = AsyncClient::new(options, 256);
+    let bridge = MqttBridge {
+      client,
+      settings,
+      published: Arc::new(Mutex::new(Published::default())),
+    };
+    (bridge, eventloop)
+  }
+
+  /// Publishes discovery of new, renamed and removed points and presets and every changed point state
+  ///
+  /// Whatever could not be queued is retried on the next call.
+  pub fn publish(&self, db_pool: &DbPool) {
+    let mut con = match db_pool.get() {
+        Ok(r) => r,
+        Err(e) => {
+          log::error!("Mqtt publish failed to fetch db_pool: {}", e);
+          return;
+        }
+    };
+    let point_list = match crate::schema::points::dsl::points
+      .order(crate::schema::points::dsl::id.asc())
+      .load::<Points>(&mut con) {
+      Ok(v) => v,
+      Err(e) => {
+        log::error!("Mqtt publish fetching points failed: {}", e);
+        return;
+      },
+    };
+    let preset_list = match crate::schema::presets::dsl::presets
+      .order(crate::schema::presets::dsl::id.asc())
+      .load::<Presets>(&mut con) {
+      Ok(v) => v,
+      Err(e) => {
+        log::error!("Mqtt publish fetching presets failed: {}", e);
+        return;
+      },
+    };
+
+    let mut published = self.published.lock().unwrap();
+    for point in &point_list {
+      let state = light_state(point);
+      let (config_done, state_done) = match published.points.get(&point.id) {
+        Some((tag, last)) => (*tag == point.tag, *last == state),
+        None => (false, false),
+      };
+      if !config_done && !self.send(self.point_config_topic(point.id), self.point_config(point).to_string()) {
+        continue;
+      }
+      if !state_done && !self.send(self.point_state_topic(point.id), json!(state).to_string()) {
+        // config is sent again as well, harmless and keeps the entry consistent
+        continue;
+      }
+      published.points.insert(point.id, (point.tag.clone(), state));
+    }
+    let removed_points = published.points.keys()
+      .filter(|v| !point_list.iter().any(|point| point.id == **v))
+      .copied()
+      .collect::<Vec<_>>();
+    for point_id in removed_points {
+      if self.send(self.point_config_topic(point_id), String::new()) {
+        published.points.remove(&point_id);
+      }
+    }
+
+    for preset in &preset_list {
+      if published.presets.get(&preset.id) == Some(&preset.preset_name) {
+        continue;
+      }
+      if self.send(self.preset_config_topic(preset.id), self.preset_config(preset).to_string()) {
+        published.presets.insert(preset.id, preset.preset_name.clone());
+      }
+    }
+    let removed_presets = published.presets.keys()
+      .filter(|v| !preset_list.iter().any(|preset| preset.id == **v))
+      .copied()
+      .collect::<Vec<_>>();
+    for preset_id in removed_presets {
+      if self.send(self.preset_config_topic(preset_id), String::new()) {
+        published.presets.remove(&preset_id);
+      }
+    }
+  }
+
+  /// Retained publish without waiting on the broker, false when the request queue is full
+  fn send(&self, topic: String, payload: String) -> bool {
+    match self.client.try_publish(topic.clone(), QoS::AtLeastOnce, true, payload) {
+      Ok(_) => true,
+      Err(e) => {
+        log::warn!("Mqtt publish to {} failed: {}", topic, e);
+        false
+      },
+    }
+  }
+
+  fn point_config(&self, point: &Points) -> serde_json::Value {
+    json!({
+      "name": point.tag.clone().unwrap_or_else(|| format!("Point {}", point.id)),
+      "unique_id": format!("{}_point_{}", self.settings.client_id, point.id),
+      "schema": "json",
+      "brightness": true,
+      "brightness_scale": LIGHT_LEVEL_MAX,
+      "command_topic": self.point_command_topic(point.id),
+      "state_topic": self.point_state_topic(point.id),
+      "availability_topic": availability_topic(&self.settings),
+      "device": self.device(),
+    })
+  }
+
+  fn preset_config(&self, preset: &Presets) -> serde_json::Value {
+    json!({
+      "name": preset.preset_name,
+      "unique_id": format!("{}_preset_{}", self.settings.client_id, preset.id),
+      "command_topic": self.preset_command_topic(preset.id),
+      "payload_on": "ON",
+      "availability_topic": availability_topic(&self.settings),
+      "device": self.device(),
+    })
+  }
+
+  fn device(&self) -> serde_json::Value {
+    json!({
+      "identifiers": [self.settings.client_id],
+      "name": "L1ghts",
+      "manufacturer": "L1ghts",
+    })
+  }
+
+  fn point_config_topic(&self, point_id: i32) -> String {
+    format!("{}/light/{}/point_{}/config", self.settings.discovery_prefix, self.settings.client_id, point_id)
+  }
+
+  fn preset_config_topic(&self, preset_id: i32) -> String {
+    format!("{}/scene/{}/preset_{}/config", self.settings.discovery_prefix, self.settings.client_id, preset_id)
+  }
+
+  fn point_state_topic(&self, point_id: i32) -> String {
+    format!("{}/point/{}/state", self.settings.prefix, point_id)
+  }
+
+  fn point_command_topic(&self, point_id: i32) -> String {
+    format!("{}/point/{}/set", self.settings.prefix, point_id)
+  }
+
+  fn preset_command_topic(&self, preset_id: i32) -> String {
+    format!("{}/preset/{}/set", self.settings.prefix, preset_id)
+  }
+}
+
+/// Lights are ON while active and lit, brightness is kept while OFF
+fn light_state(point: &Points) -> LightState {
+  LightState {
+    state: if point.active && point.val > LIGHT_LEVEL_MIN { "ON" } else { "OFF" },
+    brightness: point.val,
+  }
+}
+
+/// Parses a light command, checked like the REST handlers check theirs
+fn valid_command(payload: &[u8]) -> Result<LightCommand, ApiError> {
+  let request = serde_json::from_slice::<LightCommand>(payload).map_err(|_| ApiError::invalid("payload", "format"))?;
+  let mut validator = Validator::new();
+  match request.state.as_deref() {
+    Some(v) => validator.one_of("state", v, LIGHT_STATES),
+    None => validator.check("state", request.brightness.is_some(), "required"),
+  };
+  if let Some(v) = request.brightness {
+    validator.range("brightness", v, LIGHT_LEVEL_MIN, LIGHT_LEVEL_MAX);
+  }
+  if let Some(v) = request.transition {
+    validator.range("transition", v, 0.0, (TRANSITION_MAX_MS / 1000) as f64);
+  }
+  validator.finish()?;
+  Ok(request)
+}
+
+fn availability_topic(settings: &MqttSettings) -> String {
+  format!("{}/status", settings.prefix)
+}
+
+/// Drives the broker connection and handles incoming commands, reconnects on its own
+pub async fn run(bridge: MqttBridge, mut eventloop: EventLoop, db_pool: DbPool, batcher: Batcher, transitions: Transitions) {
+  let settings = bridge.settings.clone();
+  let status_topic = format!("{}/status", settings.discovery_prefix);
+  loop {
+    let event = match eventloop.poll().await {
+      Ok(v) => v,
+      Err(e) => {
+        log::warn!("Mqtt connection to {}:{} failed: {}", settings.host, settings.port, e);
+        actix_web::rt::time::sleep(Duration::from_millis(MQTT_RECONNECT_MS)).await;
+        continue;
+      },
+    };
+    match event {
+      Event::Incoming(Packet::ConnAck(_)) => {
+        log::info!("Mqtt connected to {}:{}", settings.host, settings.port);
+        let subscribed = [
+          format!("{}/point/+/set", settings.prefix),
+          format!("{}/preset/+/set", settings.prefix),
+          status_topic.clone(),
+        ];
+        for topic in subscribed {
+          if let Err(e) = bridge.client.try_subscribe(topic, QoS::AtLeastOnce) {
+            log::error!("Mqtt subscribe failed: {}", e);
+          }
+        }
+        // broker may have lost retained messages, everything goes out again
+        *bridge.published.lock().unwrap() = Published::default();
+        bridge.send(availability_topic(&settings), "online".to_string());
+        bridge.publish(&db_pool);
+      },
+      Event::Incoming(Packet::Publish(message)) => {
+        if message.topic == status_topic {
+          // home assistant restarted and asks for discovery again
+          if message.payload.as_ref() == b"online" {
+            *bridge.published.lock().unwrap() = Published::default();
+            bridge.publish(&db_pool);
+          }
+          continue;
+        }
+        let result = command(&settings, &message.topic, &message.payload, &db_pool, &transitions).await;
+        match result {
+          Ok(()) => batcher.request(),
+          Err(e) => log::warn!("Mqtt command on {} rejected: {:?}", message.topic, e),
+        }
+      },
+      _ => {},
+    }
+  }
+}
+
+/// Applies point or preset command, validated like the REST handlers
+async fn command(
+  settings: &MqttSettings,
+  topic: &str,
+  payload: &[u8],
+  db_pool: &DbPool,
+  transitions: &Transitions,
+) -> Result<(), ApiError> {
+  let parts = topic.strip_prefix(&format!("{}/", settings.prefix))
+    .ok_or(ApiError::NotFound)?
+    .split('/')
+    .collect::<Vec<_>>();
+  let (kind, target_id) = match parts.as_slice() {
+    [kind, target_id, "set"] => (*kind, target_id.parse::<i32>().map_err(|_| ApiError::NotFound)?),
+    _ => return Err(ApiError::NotFound),
+  };
+
+  let pool = db_pool.clone();
+  match kind {
+    "point" => {
+      let request = valid_command(payload)?;
+      let transition_ms = request.transition.map(|v| (v * 1000.0).round() as u64);
+      web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+          log::error!("Failed to get pool_points: {}", err);
+          ApiError::InternalErr
+        })?;
+        db::transaction(&mut con, |con| {
+          use crate::schema::points::dsl::*;
+          let point = points.find(target_id)
+            .first::<Points>(con)
+            .optional()
+            .map_err(|err| {
+              log::error!("Fetching point [{}] failed: {}", target_id, err);
+              ApiError::InternalErr
+            })?
+            .ok_or(ApiError::NotFound)?;
+          // OFF keeps the level, so ON brings the light back to it
+          if request.state.as_deref() == Some("OFF") {
+            return db::points::set_active(con, target_id, false);
+          }
+          match request.brightness {
+            Some(v) => db::points::set_value(con, target_id, v)?,
+            None if point.val == LIGHT_LEVEL_MIN => db::points::set_value(con, target_id, LIGHT_LEVEL_MAX)?,
+            None => {},
+          }
+          // published state is only ON for active points
+          db::points::set_active(con, target_id, true)
+        })
+      })
+      .await
+      .map_err(|err| {
+        log::error!("Mqtt point command block failed: {}", err);
+        ApiError::InternalErr
+      })??;
+      transitions.request(&[target_id], transition_ms);
+      Ok(())
+    },
+    "preset" => {
+      let point_ids = web::block(move || {
+        let mut con = pool.get()
+        .map_err(|err| {
+          log::error!("Failed to get pool: {}", err);
+          ApiError::InternalErr
+        })?;
+        db::presets::activate(&mut con, target_id, None)
+      })
+      .await
+      .map_err(|err| {
+        log::error!("Mqtt preset command block failed: {}", err);
+        ApiError::InternalErr
+      })??;
+      transitions.request(&point_ids, None);
+      Ok(())
+    },
+    _ => Err(ApiError::NotFound),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn settings() -> MqttSettings {
+    MqttSettings {
+      host: "localhost".to_string(),
+      port: 1883,
+      user: None,
+      password: None,
+      client_id: "l1ghts".to_string(),
+      prefix: "l1ghts".to_string(),
+      discovery_prefix: "homeassistant".to_string(),
+    }
+  }
+
+  fn point(val: i32, active: bool, tag: Option<&str>) -> Points {
+    Points {
+      id: 3,
+      device_id: 1,
+      device_position: 0,
+      val,
+      width: 1.0,
+      height: 1.0,
+      x: 0.0,
+      y: 0.0,
+      rotation: 0.0,
+      watts: 0.0,
+      active,
+      tag: tag.map(|v| v.to_string()),
+      curve: "linear".to_string(),
+      curve_gamma: 2.2,
+      curve_table: None,
+      layout_id: None,
+    }
+  }
+
+  /// `(field, rule)` of every failed check
+  fn errors(payload: &str) -> Vec<(String, &'static str)> {
+    match valid_command(payload.as_bytes()) {
+      Ok(_) => vec![],
+      Err(ApiError::Invalid(v)) => v.into_iter().map(|e| (e.field, e.rule)).collect(),
+      Err(e) => panic!("unexpected error {:?}", e),
+    }
+  }
+
+  #[test]
+  fn light_commands_are_checked() {
+    assert!(errors(r#"{"state": "ON"}"#).is_empty());
+    assert!(errors(r#"{"state": "OFF", "transition": 2.5}"#).is_empty());
+    assert!(errors(r#"{"brightness": 100}"#).is_empty());
+    assert_eq!(errors(r#"{}"#), vec![("state".to_string(), "required")]);
+    assert_eq!(errors(r#"{"state": "on"}"#), vec![("state".to_string(), "one_of")]);
+    assert_eq!(errors(r#"{"state": "ON", "brightness": -1}"#), vec![("brightness".to_string(), "range")]);
+    assert_eq!(
+      errors(&format!(r#"{{"brightness": {}}}"#, LIGHT_LEVEL_MAX + 1)),
+      vec![("brightness".to_string(), "range")],
+    );
+    assert_eq!(
+      errors(&format!(r#"{{"state": "ON", "transition": {}}}"#, TRANSITION_MAX_MS / 1000 + 1)),
+      vec![("transition".to_string(), "range")],
+    );
+    assert_eq!(errors("ON"), vec![("payload".to_string(), "format")]);
+  }
+
+  #[test]
+  fn lights_are_on_while_active_and_lit() {
+    assert_eq!(light_state(&point(500, true, None)), LightState { state: "ON", brightness: 500 });
+    assert_eq!(light_state(&point(500, false, None)), LightState { state: "OFF", brightness: 500 });
+    assert_eq!(light_state(&point(LIGHT_LEVEL_MIN, true, None)), LightState { state: "OFF", brightness: LIGHT_LEVEL_MIN });
+  }
+
+  #[test]
+  fn discovery_points_at_the_bridge_topics() {
+    let (bridge, _eventloop) = MqttBridge::new(settings());
+    let config = bridge.point_config(&point(0, true, Some("desk")));
+    assert_eq!(config["name"], "desk");
+    assert_eq!(config["unique_id"], "l1ghts_point_3");
+    assert_eq!(config["brightness_scale"], LIGHT_LEVEL_MAX);
+    assert_eq!(config["command_topic"], "l1ghts/point/3/set");
+    assert_eq!(config["state_topic"], "l1ghts/point/3/state");
+    assert_eq!(config["availability_topic"], "l1ghts/status");
+    assert_eq!(config["device"]["identifiers"][0], "l1ghts");
+    assert_eq!(bridge.point_config(&point(0, true, None))["name"], "Point 3");
+    assert_eq!(bridge.point_config_topic(3), "homeassistant/light/l1ghts/point_3/config");
+
+    let preset = Presets {
+      id: 4,
+      user_id: 1,
+      preset_name: "evening".to_string(),
+      favorite: false,
+      active: false,
+      icon: None,
+    };
+    let config = bridge.preset_config(&preset);
+    assert_eq!(config["name"], "evening");
+    assert_eq!(config["unique_id"], "l1ghts_preset_4");
+    assert_eq!(config["command_topic"], "l1ghts/preset/4/set");
+    assert_eq!(bridge.preset_config_topic(4), "homeassistant/scene/l1ghts/preset_4/config");
+  }
+}
@@ -0,0 +1,106 @@
+use crate::api::helpers::db::devices::{
+  mark_failed,
+  mark_seen,
+};
+use crate::api::helpers::i2c::LightDevices;
+use crate::api::helpers::i2c::bus::Buses;
+use crate::api::helpers::props::I2C_PRESENCE_DETECTOR;
+use crate::types::DbPool;
+use crate::models::Sensors;
+use chrono::Utc;
+use diesel::prelude::*;
+
+/// Reads occupancy from every presence detector and stores the changes
+pub async fn poll(db_pool: DbPool, buses: &Buses) {
+  let mut con = match db_pool.get() {
+      Ok(r) => r,
+      Err(e) => {
+        log::error!("Presence poll failed to fetch db_pool: {}", e);
+        return;
+      }
+  };
+
+  use crate::schema::devices::dsl::{
+    devices,
+    adr,
+    bus,
+    device_type,
+    id as device_id,
+  };
+
+  let detectors: Vec<(i32, i32, i32)> = match devices.select((device_id, adr, bus))
+    .filter(device_type.eq(I2C_PRESENCE_DETECTOR as i32))
+    .load::<(i32, i32, i32)>(&mut con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Presence poll fetching db devices failed: {}", e);
+      return;
+    },
+  };
+  if detectors.is_empty() {
+    return;
+  }
+
+  use crate::schema::sensors::dsl::*;
+  let sensor_list = match sensors.order(id.asc()).load::<Sensors>(&mut con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Presence poll fetching sensors failed: {}", e);
+      return;
+    },
+  };
+
+  let now = Utc::now().naive_utc();
+  let mut seen: Vec<i32> = vec![];
+  let mut failed: Vec<(i32, &'static str)> = vec![];
+  for bus_config in buses.iter() {
+    let bus_detectors = detectors.iter().filter(|v| v.2 == bus_config.id).collect::<Vec<_>>();
+    if bus_detectors.is_empty() {
+      continue;
+    }
+
+    let mut controller = match LightDevices::new(bus_config) {
+      Ok(v) => v,
+      Err(e) => {
+        log::error!("Presence poll failed to get i2c driver for bus {}: {}", bus_config.id, e);
+        bus_detectors.iter().for_each(|v| failed.push((v.0, e.kind())));
+        continue;
+      },
+    };
+
+    for (dev_id, dev_adr, _) in bus_detectors {
+      let states = match controller.get_presence_states(*dev_adr as u16) {
+        Ok(v) => v,
+        Err(e) => {
+          log::error!("Presence poll failed at get_presence_states for {} on bus {} : {}", dev_adr, bus_config.id, e);
+          failed.push((*dev_id, e.kind()));
+          continue;
+        },
+      };
+      seen.push(*dev_id);
+      for sensor in sensor_list.iter().filter(|v| v.device_id == *dev_id) {
+        let state = match states.get(sensor.device_position as usize) {
+          Some(v) => *v,
+          None => continue,
+        };
+        if state == sensor.occupied {
+          continue;
+        }
+        if let Err(e) = diesel::update(sensors.filter(id.eq(sensor.id)))
+          .set((occupied.eq(state), changed_at.eq(now)))
+          .execute(&mut con) {
+          log::error!("Presence poll failed to update sensor [{}]: {}", sensor.id, e);
+        }
+      }
+    }
+  }
+
+  if let Err(e) = mark_seen(&mut con, seen) {
+    log::error!("Presence poll failed to update device health: {}", e);
+  }
+  for (dev_id, kind) in failed {
+    if let Err(e) = mark_failed(&mut con, dev_id, kind) {
+      log::error!("Presence poll failed to update device [{}] health: {}", dev_id, e);
+    }
+  }
+}
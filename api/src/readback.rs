@@ -0,0 +1,190 @@
+use crate::api::helpers::batcher::Batcher;
+use crate::api::helpers::db::devices::{
+  mark_drift,
+  mark_failed,
+  mark_seen,
+};
+use crate::api::helpers::frames::FrameCache;
+use crate::api::helpers::power::{device_watts, PowerBudget};
+use crate::api::helpers::transitions::Transitions;
+use crate::api::helpers::i2c::LightDevices;
+use crate::api::helpers::i2c::bus::Buses;
+use crate::api::helpers::props::I2C_LIGHT_CONTROLLER;
+use crate::types::DbPool;
+use crate::models::Points;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+/// Reads levels back from every light controller and re-sends frames that drifted
+///
+/// Levels are compared after the power limiter, as the dispatcher sends them.
+/// Controllers with a dispatch still pending or a fade running are skipped.
+pub async fn verify(
+  db_pool: DbPool,
+  buses: &Buses,
+  frames: &FrameCache,
+  transitions: &Transitions,
+  batcher: &Batcher,
+  power: &PowerBudget,
+) {
+  let mut con = match db_pool.get() {
+      Ok(r) => r,
+      Err(e) => {
+        log::error!("Readback failed to fetch db_pool: {}", e);
+        return;
+      }
+  };
+
+  use crate::schema::points::dsl::*;
+  let point_list = match points.order(id.asc()).load::<Points>(&mut con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Readback fetching points failed: {}", e);
+      return;
+    },
+  };
+
+  use crate::schema::devices::dsl::{
+    devices,
+    adr,
+    bus,
+    device_type,
+    max_watts,
+    id as device_id,
+  };
+
+  let db_devices: Vec<(i32, i32, i32, Option<f32>)> = match devices.select((device_id, adr, bus, max_watts))
+    .filter(device_type.eq(I2C_LIGHT_CONTROLLER as i32))
+    .load::<(i32, i32, i32, Option<f32>)>(&mut con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Readback fetching db devices failed: {}", e);
+      return;
+    },
+  };
+
+  let point_watts = device_watts(&point_list);
+  let limits = db_devices.iter()
+    .filter_map(|(dev_id, _, _, dev_max_watts)| Some((*dev_id, (*dev_max_watts)?)))
+    .collect::<HashMap<_, _>>();
+  let (converted, _) = power.scale(LightDevices::convert_points(point_list, false), &point_watts, &limits);
+  let outputs = db_devices.iter()
+    .map(|(dev_id, dev_adr, dev_bus, _)| (*dev_id, *dev_adr, *dev_bus))
+    .collect::<Vec<_>>();
+  let (seen, failed, drifted) = compare(buses, frames, transitions, &outputs, &converted);
+  if !drifted.is_empty() {
+    batcher.request();
+  }
+  if let Err(e) = mark_seen(&mut con, seen) {
+    log::error!("Readback failed to update device health: {}", e);
+  }
+  for (dev_id, kind) in failed {
+    if let Err(e) = mark_failed(&mut con, dev_id, kind) {
+      log::error!("Readback failed to update device [{}] health: {}", dev_id, e);
+    }
+  }
+  for dev_id in drifted {
+    if let Err(e) = mark_drift(&mut con, dev_id) {
+      log::error!("Readback failed to record device [{}] drift: {}", dev_id, e);
+    }
+  }
+}
+
+/// Reads back every `(device id, address, bus)`, returns devices that answered, failed and drifted from `converted`
+///
+/// Drifted devices are forgotten so the next dispatch writes them again.
+fn compare(
+  buses: &Buses,
+  frames: &FrameCache,
+  transitions: &Transitions,
+  db_devices: &[(i32, i32, i32)],
+  converted: &[(i32, Vec<i32>)],
+) -> (Vec<i32>, Vec<(i32, &'static str)>, Vec<i32>) {
+  let mut seen: Vec<i32> = vec![];
+  let mut failed: Vec<(i32, &'static str)> = vec![];
+  let mut drifted: Vec<i32> = vec![];
+  for bus_config in buses.iter() {
+    let bus_devices = db_devices.iter().filter(|v| v.2 == bus_config.id).collect::<Vec<_>>();
+    if bus_devices.is_empty() {
+      continue;
+    }
+
+    let mut controller = match LightDevices::new(bus_config) {
+      Ok(v) => v,
+      Err(e) => {
+        log::error!("Readback failed to get i2c driver for bus {}: {}", bus_config.id, e);
+        continue;
+      },
+    };
+
+    for (dev_id, dev_adr, _) in bus_devices {
+      let expected = match converted.iter().find(|(k, _)| k == dev_id) {
+        Some((_, v)) => v,
+        None => continue,
+      };
+      if transitions.fading(*dev_id) || frames.last(*dev_id).is_some_and(|sent| &sent != expected) {
+        continue;
+      }
+      let levels = match controller.get_light_levels(*dev_adr as u16) {
+        Ok(v) => v,
+        Err(e) => {
+          log::error!("Readback failed at get_light_levels for {} on bus {} : {}", dev_adr, bus_config.id, e);
+          failed.push((*dev_id, e.kind()));
+          continue;
+        },
+      };
+      seen.push(*dev_id);
+      if &levels != expected {
+        log::warn!("Readback found drifted levels on {} bus {}, re-sending", dev_adr, bus_config.id);
+        frames.forget(*dev_id);
+        drifted.push(*dev_id);
+      }
+    }
+  }
+
+  (seen, failed, drifted)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::api::helpers::i2c::bus::Backend;
+  use crate::api::helpers::i2c::simulator::Simulator;
+
+  fn simulator(buses: &Buses) -> Simulator {
+    match &buses.get(1).unwrap().backend {
+      Backend::Simulator(v) => v.clone(),
+      _ => panic!("simulator expected"),
+    }
+  }
+
+  #[test]
+  fn drifted_levels_are_forgotten() {
+    let buses = Buses::new("simulator", "1", "0x08:2,0x09:1", "").unwrap();
+    let frames = FrameCache::new();
+    let mut controller = LightDevices::new(buses.get(1).unwrap()).unwrap();
+    controller.set_light_levels(0x08, vec![10, 20]).unwrap();
+    controller.set_light_levels(0x09, vec![30]).unwrap();
+    frames.store(1, vec![10, 20]);
+    frames.store(2, vec![30]);
+    simulator(&buses).add_device(0x09, 1);
+
+    let db_devices = [(1, 0x08, 1), (2, 0x09, 1)];
+    let converted = [(1, vec![10, 20]), (2, vec![30])];
+    let (seen, failed, drifted) = compare(&buses, &frames, &Transitions::new(), &db_devices, &converted);
+    assert_eq!((seen, failed, drifted), (vec![1, 2], vec![], vec![2]));
+    assert!(!frames.changed(1, &[10, 20]));
+    assert!(frames.changed(2, &[30]));
+  }
+
+  #[test]
+  fn pending_frames_are_not_read() {
+    let buses = Buses::new("simulator", "1", "0x08:1", "0x08:nack").unwrap();
+    let frames = FrameCache::new();
+    frames.store(1, vec![10]);
+    let db_devices = [(1, 0x08, 1), (2, 0x30, 1), (3, 0x08, 2)];
+    let converted = [(1, vec![20]), (2, vec![30]), (3, vec![40])];
+    let (seen, failed, drifted) = compare(&buses, &frames, &Transitions::new(), &db_devices, &converted);
+    assert_eq!((seen, failed, drifted), (vec![], vec![(2, "nack")], vec![]));
+  }
+}
@@ -26,6 +26,59 @@ diesel::table! {
         id -> Int4,
         adr -> Int4,
         endpoint_count -> Int4,
+        device_type -> Int4,
+        bus -> Int4,
+        online -> Bool,
+        last_seen -> Nullable<Timestamp>,
+        failure_count -> Int4,
+        last_error -> Nullable<Text>,
+        drift_count -> Int4,
+        last_drift -> Nullable<Timestamp>,
+        protocol_version -> Int4,
+        features -> Int4,
+        firmware_version -> Nullable<Text>,
+        transport -> Text,
+        universe -> Nullable<Int4>,
+        dmx_channel -> Nullable<Int4>,
+        max_watts -> Nullable<Float4>,
+    }
+}
+
+diesel::table! {
+    energy (id) {
+        id -> Int4,
+        point_id -> Nullable<Int4>,
+        started_at -> Timestamp,
+        sampled_at -> Timestamp,
+        wh -> Float8,
+    }
+}
+
+diesel::table! {
+    layouts (id) {
+        id -> Int4,
+        layout_name -> Text,
+        floor -> Int4,
+        width -> Float4,
+        height -> Float4,
+        background -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    point_group_items (id) {
+        id -> Int4,
+        group_id -> Int4,
+        point_id -> Int4,
+    }
+}
+
+diesel::table! {
+    point_groups (id) {
+        id -> Int4,
+        group_name -> Text,
+        kind -> Text,
+        icon -> Nullable<Text>,
     }
 }
 
@@ -43,6 +96,10 @@ diesel::table! {
         watts -> Float4,
         active -> Bool,
         tag -> Nullable<Text>,
+        curve -> Text,
+        curve_gamma -> Float4,
+        curve_table -> Nullable<Array<Int4>>,
+        layout_id -> Nullable<Int4>,
     }
 }
 
@@ -66,17 +123,60 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    scan_entries (id) {
+        id -> Int4,
+        bus -> Int4,
+        adr -> Int4,
+        identifier -> Int4,
+        known -> Bool,
+        endpoint_count -> Int4,
+        probed -> Bool,
+    }
+}
+
+diesel::table! {
+    scan_reports (bus) {
+        bus -> Int4,
+        scanned_at -> Timestamp,
+        addresses -> Int4,
+    }
+}
+
+diesel::table! {
+    sensors (id) {
+        id -> Int4,
+        device_id -> Int4,
+        device_position -> Int4,
+        occupied -> Bool,
+        changed_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::joinable!(credential_refresh -> credentials (credential_id));
+diesel::joinable!(energy -> points (point_id));
+diesel::joinable!(point_group_items -> point_groups (group_id));
+diesel::joinable!(point_group_items -> points (point_id));
 diesel::joinable!(points -> devices (device_id));
+diesel::joinable!(points -> layouts (layout_id));
 diesel::joinable!(preset_items -> points (point_id));
 diesel::joinable!(preset_items -> presets (preset_id));
 diesel::joinable!(presets -> credentials (user_id));
+diesel::joinable!(scan_entries -> scan_reports (bus));
+diesel::joinable!(sensors -> devices (device_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     credential_refresh,
     credentials,
     devices,
+    energy,
+    layouts,
+    point_group_items,
+    point_groups,
     points,
     preset_items,
     presets,
+    scan_entries,
+    scan_reports,
+    sensors,
 );
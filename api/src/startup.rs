@@ -0,0 +1,190 @@
+use crate::api::helpers::batcher::Batcher;
+use crate::api::helpers::curves::Curve;
+use crate::api::helpers::db;
+use crate::api::helpers::db::devices::{
+  mark_failed,
+  mark_seen,
+};
+use crate::api::helpers::frames::FrameCache;
+use crate::api::helpers::i2c::LightDevices;
+use crate::api::helpers::i2c::bus::Buses;
+use crate::api::helpers::props::I2C_LIGHT_CONTROLLER;
+use crate::types::DbPool;
+use crate::models::Points;
+use diesel::prelude::*;
+
+/// What to do with hardware and stored levels disagreeing after a restart
+///
+/// Picked with `STARTUP_SYNC` (`push` by default).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartupPolicy {
+  /// writes stored levels to every controller
+  Push,
+  /// stores levels read from controllers into `points.val`
+  Adopt,
+  /// sets every point to 0 and writes it out
+  Blackout,
+  /// leaves hardware alone until first change
+  Keep,
+}
+
+impl StartupPolicy {
+  pub fn new(policy: &str) -> Option<Self> {
+    match policy.trim().to_lowercase().as_str() {
+      "" | "push" => Some(StartupPolicy::Push),
+      "adopt" => Some(StartupPolicy::Adopt),
+      "blackout" => Some(StartupPolicy::Blackout),
+      "keep" => Some(StartupPolicy::Keep),
+      _ => None,
+    }
+  }
+}
+
+pub async fn sync(policy: StartupPolicy, db_pool: DbPool, buses: &Buses, frames: &FrameCache, batcher: &Batcher) {
+  let mut con = match db_pool.get() {
+      Ok(r) => r,
+      Err(e) => {
+        log::error!("Startup sync failed to fetch db_pool: {}", e);
+        return;
+      }
+  };
+
+  restore_scans(&mut con, buses);
+  match policy {
+    StartupPolicy::Keep => (),
+    StartupPolicy::Push => {
+      frames.clear();
+      batcher.request();
+    },
+    StartupPolicy::Blackout => {
+      use crate::schema::points::dsl::*;
+      if let Err(e) = diesel::update(points).set(val.eq(0)).execute(&mut con) {
+        log::error!("Startup blackout failed to update points: {}", e);
+        return;
+      }
+      deactivate_presets(&mut con);
+      frames.clear();
+      batcher.request();
+    },
+    StartupPolicy::Adopt => adopt(&mut con, buses, frames),
+  }
+  log::info!("Startup sync finished with {:?} policy", policy);
+}
+
+/// Loads scan reports of the last discovery, so it does not probe what it left alone before
+fn restore_scans(con: &mut crate::types::DbCon, buses: &Buses) {
+  let reports = match db::scan::load(con) {
+    Ok(v) => v,
+    Err(_) => return,
+  };
+  for report in reports {
+    if let Some(bus_config) = buses.get(report.bus) {
+      *bus_config.scan_report.lock().unwrap() = report;
+    }
+  }
+}
+
+fn adopt(con: &mut crate::types::DbCon, buses: &Buses, frames: &FrameCache) {
+  use crate::schema::points::dsl::*;
+  let point_list = match points.order(id.asc()).load::<Points>(con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Startup adopt fetching points failed: {}", e);
+      return;
+    },
+  };
+
+  use crate::schema::devices::dsl::{
+    devices,
+    adr,
+    bus,
+    device_type,
+    id as device_id,
+  };
+  let db_devices: Vec<(i32, i32, i32)> = match devices.select((device_id, adr, bus))
+    .filter(device_type.eq(I2C_LIGHT_CONTROLLER as i32))
+    .load::<(i32, i32, i32)>(con) {
+    Ok(v) => v,
+    Err(e) => {
+      log::error!("Startup adopt fetching db devices failed: {}", e);
+      return;
+    },
+  };
+
+  let mut seen: Vec<i32> = vec![];
+  let mut failed: Vec<(i32, &'static str)> = vec![];
+  for bus_config in buses.iter() {
+    let bus_devices = db_devices.iter().filter(|v| v.2 == bus_config.id).collect::<Vec<_>>();
+    if bus_devices.is_empty() {
+      continue;
+    }
+    let mut controller = match LightDevices::new(bus_config) {
+      Ok(v) => v,
+      Err(e) => {
+        log::error!("Startup adopt failed to get i2c driver for bus {}: {}", bus_config.id, e);
+        continue;
+      },
+    };
+
+    for (dev_id, dev_adr, _) in bus_devices {
+      let levels = match controller.get_light_levels(*dev_adr as u16) {
+        Ok(v) => v,
+        Err(e) => {
+          log::error!("Startup adopt failed at get_light_levels for {} on bus {} : {}", dev_adr, bus_config.id, e);
+          failed.push((*dev_id, e.kind()));
+          continue;
+        },
+      };
+      seen.push(*dev_id);
+      for point in point_list.iter().filter(|v| v.device_id == *dev_id) {
+        let level = match levels.get(point.device_position as usize) {
+          Some(v) => *v,
+          None => continue,
+        };
+        // hardware holds curve output, points keep perceptual level
+        let perceptual = Curve::from_point(point).invert(level);
+        // lit output only makes sense on an active point, dark ones keep their flag
+        let adopted = diesel::update(points.find(point.id))
+          .set((val.eq(perceptual), active.eq(point.active || level > 0)))
+          .execute(con);
+        if let Err(e) = adopted {
+          log::error!("Startup adopt failed to update point [{}]: {}", point.id, e);
+        }
+      }
+      frames.store(*dev_id, levels);
+    }
+  }
+  deactivate_presets(con);
+
+  if let Err(e) = mark_seen(con, seen) {
+    log::error!("Startup adopt failed to update device health: {}", e);
+  }
+  for (dev_id, kind) in failed {
+    if let Err(e) = mark_failed(con, dev_id, kind) {
+      log::error!("Startup adopt failed to update device [{}] health: {}", dev_id, e);
+    }
+  }
+}
+
+/// Levels no longer come from a preset
+fn deactivate_presets(con: &mut crate::types::DbCon) {
+  use crate::schema::presets::dsl::*;
+  if let Err(e) = diesel::update(presets).filter(active.eq(true)).set(active.eq(false)).execute(con) {
+    log::error!("Startup sync failed to update presets: {}", e);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn policy_is_parsed_from_env_value() {
+    assert_eq!(StartupPolicy::new(""), Some(StartupPolicy::Push));
+    assert_eq!(StartupPolicy::new("push"), Some(StartupPolicy::Push));
+    assert_eq!(StartupPolicy::new(" Adopt "), Some(StartupPolicy::Adopt));
+    assert_eq!(StartupPolicy::new("BLACKOUT"), Some(StartupPolicy::Blackout));
+    assert_eq!(StartupPolicy::new("keep"), Some(StartupPolicy::Keep));
+    assert_eq!(StartupPolicy::new("restore"), None);
+  }
+}
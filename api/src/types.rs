@@ -1,5 +1,6 @@
 use std::sync::Arc;
 use crate::calls::AuthToken;
+use crate::api::helpers::i2c::bus::Buses;
 use jsonwebtoken::TokenData;
 
 use diesel::{
@@ -19,7 +20,7 @@ pub struct Tokens {
 
 #[derive(Debug, Clone)]
 pub struct SharedStorage {
-    pub i2c_device: Arc<u8>,
+    pub buses: Arc<Buses>,
     pub setup_secret: Arc<String>,
 }
 
@@ -2,6 +2,9 @@ import ax from "axios";
 import { AxiosInstance, CancelTokenSource } from "axios";
 import auth from "./providers/auth";
 import devices from "./providers/devices";
+import energy from "./providers/energy";
+import groups from "./providers/groups";
+import layouts from "./providers/layouts";
 import points from "./providers/points";
 import presets from "./providers/presets";
 import step from "./providers/step";
@@ -10,6 +13,9 @@ function methods(axios: AxiosInstance) {
   return {
     auth: auth(axios),
     devices: devices(axios),
+    energy: energy(axios),
+    groups: groups(axios),
+    layouts: layouts(axios),
     points: points(axios),
     presets: presets(axios),
     step: step(axios),
@@ -1,5 +1,5 @@
 import { AxiosInstance, AxiosPromise, CancelToken } from "axios";
-import { Devices } from "../types.api";
+import { DevicePower, DeviceSettings, Devices, DmxDevice, PowerReport, ScanReport } from "../types.api";
 
 function devices(axios: AxiosInstance) {
   return {
@@ -8,7 +8,42 @@ function devices(axios: AxiosInstance) {
     },
     refresh: function (cancelToken?: CancelToken): AxiosPromise<Devices[]> {
       return axios.post("/devices", { cancelToken });
-    }
+    },
+    scan: {
+      get: function (cancelToken?: CancelToken): AxiosPromise<ScanReport[]> {
+        return axios.get("/devices/scan", { cancelToken });
+      },
+      reset: function (cancelToken?: CancelToken): AxiosPromise<void> {
+        return axios.delete("/devices/scan", { cancelToken });
+      },
+    },
+    dmx: {
+      create: function (device: DmxDevice, cancelToken?: CancelToken): AxiosPromise<Devices> {
+        return axios.post("/devices/dmx", device, { cancelToken });
+      },
+      update: function (id: number, device: DmxDevice, cancelToken?: CancelToken): AxiosPromise<Devices> {
+        return axios.put(`/devices/${id}/dmx`, device, { cancelToken });
+      },
+      delete: function (id: number, cancelToken?: CancelToken): AxiosPromise<void> {
+        return axios.delete(`/devices/${id}/dmx`, { cancelToken });
+      },
+    },
+    power: {
+      get: function (cancelToken?: CancelToken): AxiosPromise<PowerReport> {
+        return axios.get("/devices/power", { cancelToken });
+      },
+      update: function (id: number, power: DevicePower, cancelToken?: CancelToken): AxiosPromise<Devices> {
+        return axios.put(`/devices/${id}/power`, power, { cancelToken });
+      },
+    },
+    settings: {
+      get: function (id: number, cancelToken?: CancelToken): AxiosPromise<DeviceSettings> {
+        return axios.get(`/devices/${id}/settings`, { cancelToken });
+      },
+      update: function (id: number, settings: DeviceSettings, cancelToken?: CancelToken): AxiosPromise<DeviceSettings> {
+        return axios.put(`/devices/${id}/settings`, settings, { cancelToken });
+      },
+    },
   };
 }
 
@@ -0,0 +1,18 @@
+import { AxiosInstance, AxiosPromise, CancelToken } from "axios";
+import { DeviceEnergy, EnergyQuery, PointEnergy, TagEnergy } from "../types.api";
+
+function energy(axios: AxiosInstance) {
+  return {
+    points: function (params: EnergyQuery, cancelToken?: CancelToken): AxiosPromise<PointEnergy[]> {
+      return axios.get("/energy/points", { params, cancelToken });
+    },
+    devices: function (params: EnergyQuery, cancelToken?: CancelToken): AxiosPromise<DeviceEnergy[]> {
+      return axios.get("/energy/devices", { params, cancelToken });
+    },
+    tags: function (params: EnergyQuery, cancelToken?: CancelToken): AxiosPromise<TagEnergy[]> {
+      return axios.get("/energy/tags", { params, cancelToken });
+    },
+  };
+}
+
+export default energy;
@@ -0,0 +1,24 @@
+import { AxiosInstance, AxiosPromise, CancelToken } from "axios";
+import { GroupCommand, GroupRequest, GroupState, Limited } from "../types.api";
+
+function groups(axios: AxiosInstance) {
+  return {
+    get: function (cancelToken?: CancelToken): AxiosPromise<GroupState[]> {
+      return axios.get("/groups", { cancelToken });
+    },
+    create: function (group: GroupRequest, cancelToken?: CancelToken): AxiosPromise<GroupState[]> {
+      return axios.post("/groups", group, { cancelToken });
+    },
+    update: function (id: number, group: GroupRequest, cancelToken?: CancelToken): AxiosPromise<GroupState[]> {
+      return axios.put(`/groups/${id}`, group, { cancelToken });
+    },
+    delete: function (id: number, cancelToken?: CancelToken): AxiosPromise<GroupState[]> {
+      return axios.delete(`/groups/${id}`, { cancelToken });
+    },
+    command: function (id: number, command: GroupCommand, cancelToken?: CancelToken, transitionMs?: number): AxiosPromise<Limited<GroupState>> {
+      return axios.put(`/groups/${id}/command`, command, { cancelToken, params: { transition_ms: transitionMs } });
+    },
+  };
+}
+
+export default groups;
@@ -0,0 +1,21 @@
+import { AxiosInstance, AxiosPromise, CancelToken } from "axios";
+import { Layout, NewLayout } from "../types.api";
+
+function layouts(axios: AxiosInstance) {
+  return {
+    get: function (cancelToken?: CancelToken): AxiosPromise<Layout[]> {
+      return axios.get("/layouts", { cancelToken });
+    },
+    create: function (layout: NewLayout, cancelToken?: CancelToken): AxiosPromise<Layout[]> {
+      return axios.post("/layouts", layout, { cancelToken });
+    },
+    update: function (id: number, layout: NewLayout, cancelToken?: CancelToken): AxiosPromise<Layout[]> {
+      return axios.put(`/layouts/${id}`, layout, { cancelToken });
+    },
+    delete: function (id: number, cancelToken?: CancelToken): AxiosPromise<Layout[]> {
+      return axios.delete(`/layouts/${id}`, { cancelToken });
+    },
+  };
+}
+
+export default layouts;
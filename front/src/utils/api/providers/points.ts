@@ -1,13 +1,19 @@
 import { AxiosInstance, AxiosPromise, CancelToken } from "axios";
-import { Points, QueryById, UpdatePoints } from "../types.api";
+import { Limited, PatchPoints, Points, QueryById, SpatialCommand, UpdatePoints } from "../types.api";
 
 function points(axios: AxiosInstance) {
   return {
-    get: function(cancelToken?: CancelToken): AxiosPromise<Points[]> {
-      return axios.get("/points", { cancelToken });
+    get: function(cancelToken?: CancelToken, layoutId?: number): AxiosPromise<Points[]> {
+      return axios.get("/points", { cancelToken, params: { layout_id: layoutId } });
     },
-    update: function(points: UpdatePoints[], cancelToken?: CancelToken): AxiosPromise<Points[]> {
-      return axios.put("/points", points, { cancelToken });
+    update: function(points: UpdatePoints[], cancelToken?: CancelToken, transitionMs?: number): AxiosPromise<Limited<Points>[]> {
+      return axios.put("/points", points, { cancelToken, params: { transition_ms: transitionMs } });
+    },
+    patch: function(points: PatchPoints[], cancelToken?: CancelToken, transitionMs?: number): AxiosPromise<Limited<Points>[]> {
+      return axios.patch("/points", points, { cancelToken, params: { transition_ms: transitionMs } });
+    },
+    spatial: function(command: SpatialCommand, cancelToken?: CancelToken, transitionMs?: number): AxiosPromise<Limited<Points>[]> {
+      return axios.put("/points/spatial", command, { cancelToken, params: { transition_ms: transitionMs } });
     },
     identify: function(id: number, cancelToken?: CancelToken): AxiosPromise<QueryById> {
       return axios.post("/points/identify", { id }, { cancelToken });
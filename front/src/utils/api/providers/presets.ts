@@ -1,5 +1,5 @@
 import { AxiosInstance, AxiosPromise, CancelToken } from "axios";
-import { NewPresets, Presets, QueryById } from "../types.api";
+import { Limited, NewPresets, Presets, QueryById } from "../types.api";
 
 function presets(axios: AxiosInstance) {
   return {
@@ -19,8 +19,8 @@ function presets(axios: AxiosInstance) {
       get: function (cancelToken?: CancelToken): AxiosPromise<QueryById> {
         return axios.get("/presets/active", { cancelToken });
       },
-      update: function (id: number, cancelToken?: CancelToken): AxiosPromise<QueryById> {
-        return axios.put("/presets/active", { id }, { cancelToken });
+      update: function (id: number, cancelToken?: CancelToken, transitionMs?: number): AxiosPromise<Limited<QueryById>> {
+        return axios.put("/presets/active", { id }, { cancelToken, params: { transition_ms: transitionMs } });
       }
     },
   };
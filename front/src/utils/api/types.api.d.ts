@@ -16,6 +16,29 @@ export interface Devices {
     id: number,
     adr: number,
     endpoint_count: number,
+    device_type: number,
+    bus: number,
+    online: boolean,
+    last_seen: string | null,
+    failure_count: number,
+    last_error: string | null,
+    drift_count: number,
+    last_drift: string | null,
+    protocol_version: number,
+    features: number,
+    firmware_version: string | null,
+    transport: 'i2c' | 'serial' | 'artnet' | 'sacn',
+    universe: number | null,
+    dmx_channel: number | null,
+    max_watts: number | null,
+}
+
+export interface Sensors {
+    id: number,
+    device_id: number,
+    device_position: number,
+    occupied: boolean,
+    changed_at: string | null,
 }
 export interface UpdatePoints {
     id: number,
@@ -28,17 +51,143 @@ export interface UpdatePoints {
     watts: number,
     active: boolean,
     tag: string | null,
+    curve?: PointCurve,
+    curve_gamma?: number,
+    curve_table?: number[] | null,
+    layout_id?: number | null,
+}
+
+export type PatchPoints = Partial<Omit<UpdatePoints, 'id'>> & { id: number };
+
+export interface SpatialCommand {
+    shape: 'rect' | 'polygon' | 'radial',
+    value: number,
+    layout_id?: number,
+    x?: number,
+    y?: number,
+    width?: number,
+    height?: number,
+    points?: [number, number][],
+    radius?: number,
 }
 
+export type PointCurve = 'linear' | 'gamma' | 'cie1931' | 'table';
+
 export interface Points extends UpdatePoints {
     device_id: number,
     device_position: number,
+    layout_id: number | null,
+}
+
+export interface ScanEntry {
+    adr: number,
+    identifier: number,
+    known: boolean,
+    endpoint_count: number,
+    probed: boolean,
+}
+
+export interface ScanReport {
+    bus: number,
+    scanned_at: string | null,
+    addresses: number,
+    entries: ScanEntry[],
+}
+
+export interface DeviceSettings {
+    pwm_frequency: number | null,
+}
+
+export interface DmxDevice {
+    transport: 'artnet' | 'sacn',
+    universe: number,
+    channel: number,
+    endpoint_count: number,
+}
+
+export interface DevicePower {
+    max_watts: number | null,
+}
+
+export interface DeviceDraw {
+    device_id: number,
+    max_watts: number | null,
+    requested_watts: number,
+    draw_watts: number,
+    scale: number,
+}
+
+export interface PowerReport {
+    budget_watts: number | null,
+    requested_watts: number,
+    draw_watts: number,
+    limited: boolean,
+    limited_at: string | null,
+    devices: DeviceDraw[],
+}
+
+export interface EnergyQuery {
+    from?: string,
+    to?: string,
+}
+
+export interface PointEnergy {
+    point_id: number | null,
+    wh: number,
+}
+
+export interface DeviceEnergy {
+    device_id: number,
+    wh: number,
+}
+
+export interface TagEnergy {
+    tag: string | null,
+    wh: number,
+}
+
+export type GroupKind = 'room' | 'group';
+
+export interface GroupRequest {
+    group_name: string,
+    kind: GroupKind,
+    icon: string | null,
+    points: number[],
+}
+
+export interface GroupState extends GroupRequest {
+    id: number,
+    level: number,
+    on: boolean,
+}
+
+export interface GroupCommand {
+    action: 'set' | 'scale' | 'toggle',
+    value?: number,
+    factor?: number,
+}
+
+export interface NewLayout {
+    layout_name: string,
+    floor: number,
+    width: number,
+    height: number,
+    background: string | null,
+}
+
+export interface Layout extends NewLayout {
+    id: number,
 }
 
 export interface QueryById {
     id: number,
 }
 
+// set when the power budget scales the changed levels down
+export type Limited<T> = T & {
+    limited: boolean,
+}
+
 export interface Presets extends NewPresets, QueryById {
 }
 
@@ -47,3 +196,17 @@ export interface NewPresets {
     favorite: boolean,
     icon: string | null,
 }
+
+export interface FieldError {
+    field: string,
+    rule: 'range' | 'min' | 'length' | 'one_of' | 'unique' | 'exists' | 'required' | 'non_decreasing' | 'within_layout' | 'trimmed' | 'format' | 'finite' | 'positive' | 'order',
+    min?: number,
+    max?: number,
+    allowed?: string[],
+}
+
+export interface ErrMessage {
+    code?: 'validation',
+    message: string,
+    errors?: FieldError[],
+}
@@ -2,6 +2,9 @@ from machine import Pin, PWM, mem32
 import utime
 from time import sleep
 from i2cSlave import i2c_slave
+import json
+import sys
+import select
 
 address = 0x08
 
@@ -10,6 +13,50 @@ address = 0x08
 #  * 0x20 - presence detector
 peripheral_identifier = 0x10
 
+# capability block served from register 0xf0:
+#  magic, protocol version, feature flags, firmware version length, firmware version
+#  feature flags:
+#  * 0x01 - pwm frequency register
+#  * 0x02 - address change command
+protocol_version = 1
+features = 0x03
+firmware_version = "1.3.0"
+capability_offset = 0xf0
+capability_block = [0xca, protocol_version, features, len(firmware_version)]
+capability_block.extend([ord(c) for c in firmware_version])
+
+# pwm frequency in Hz, read as 2 bytes big endian from register 0xe0,
+# written as [0xe0, high, low], kept in settings file across restarts
+pwm_frequency_register = 0xe0
+pwm_frequency_min = 100
+pwm_frequency_max = 20000
+settings_file = "settings.json"
+pwm_frequency = 1000
+
+# address change, written as [0xe2, address, ~address], kept in settings file as well
+address_register = 0xe2
+address_min = 0x08
+address_max = 0x77
+
+settings = {}
+try:
+    with open(settings_file) as f:
+        settings = json.load(f)
+except (OSError, ValueError):
+    pass
+if pwm_frequency_min <= settings.get("pwm_frequency", pwm_frequency) <= pwm_frequency_max:
+    pwm_frequency = settings.get("pwm_frequency", pwm_frequency)
+if address_min <= settings.get("address", address) <= address_max:
+    address = settings.get("address", address)
+
+
+def save_settings():
+    try:
+        with open(settings_file, "w") as f:
+            json.dump({"pwm_frequency": pwm_frequency, "address": address}, f)
+    except OSError:
+        pass
+
 responder = i2c_slave(0, sda=0, scl=1, slaveAddress=address)
 counter = 0
 indicator = Pin(25, Pin.OUT)
@@ -36,7 +83,7 @@ led_bit_value = []
  
  # initializing pwm
 for i in leds:
-    i.freq(1000)
+    i.freq(pwm_frequency)
     i.duty_u16(0)
     for _ in [0, 1]:
         # setting 2 bits per signal
@@ -49,6 +96,115 @@ for e in range(10):
 content = []
 content_action = []
 
+# usb serial frames, same register model as i2c:
+#  request  0xa5, op (0x01 read, 0x02 write), address, length, payload, crc
+#  response 0x5a, status (0x00 ok, 0x01 nack, 0x02 invalid), length, data, crc
+# read payload is register and count, crc is CRC-8 (polynomial 0x07) between start byte and crc
+serial_request = 0xa5
+serial_response = 0x5a
+serial_buffer = []
+serial_poll = select.poll()
+serial_poll.register(sys.stdin, select.POLLIN)
+
+
+def handle_write(content_action):
+    global counter, led_bit_value, pwm_frequency, address, responder
+    if (len(content_action) == 1):
+        counter = content_action[0]
+    if (len(content_action) == 3 and content_action[0] == pwm_frequency_register):
+        requested = (content_action[1] << 8) + content_action[2]
+        if pwm_frequency_min <= requested <= pwm_frequency_max:
+            pwm_frequency = requested
+            for i in leds:
+                i.freq(pwm_frequency)
+            save_settings()
+    if (len(content_action) == 3 and content_action[0] == address_register
+            and content_action[1] == (~content_action[2] & 0xff)
+            and address_min <= content_action[1] <= address_max):
+        address = content_action[1]
+        save_settings()
+        responder = i2c_slave(0, sda=0, scl=1, slaveAddress=address)
+    if (len(content_action) == len(led_bit_value)):
+        # on the right amount of bytes we update pwm
+        led_bit_value = content_action.copy()
+        local_counter = 0
+        for i in leds:
+            intensity = 0
+            for o in [0, 1]:
+                led_bit_value[(local_counter * 2) + o] &= 0xff
+                if o == 0:
+                    intensity = led_bit_value[local_counter * 2] * 256
+                    continue
+                intensity = intensity + led_bit_value[(local_counter * 2) + 1]
+            local_counter += 1
+            i.duty_u16(intensity & 0xffff)
+
+
+def read_register():
+    global counter
+    data_map = [peripheral_identifier, len(leds)]
+    data_map.extend(led_bit_value)
+    transmitted = 0x00
+    if len(data_map) > counter and type(data_map[counter]) != None:
+        transmitted = data_map[counter]
+    elif counter == pwm_frequency_register:
+        transmitted = pwm_frequency >> 8
+    elif counter == pwm_frequency_register + 1:
+        transmitted = pwm_frequency & 0xff
+    elif counter >= capability_offset and len(capability_block) > counter - capability_offset:
+        transmitted = capability_block[counter - capability_offset]
+    counter = counter + 1
+    if (counter > 255):
+        counter = 0
+    return transmitted & 0xff
+
+
+def crc8(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x07) if crc & 0x80 else (crc << 1)
+            crc &= 0xff
+    return crc
+
+
+def handle_frame(frame):
+    global counter
+    op, target, payload = frame[0], frame[1], frame[3:]
+    status = 0x00
+    data = []
+    if target != address:
+        status = 0x01
+    elif op == 0x01 and len(payload) == 2:
+        counter = payload[0]
+        data = [read_register() for _ in range(payload[1])]
+    elif op == 0x02:
+        handle_write(list(payload))
+    else:
+        status = 0x02
+    response = [status, len(data)] + data
+    response.append(crc8(response))
+    sys.stdout.buffer.write(bytes([serial_response] + response))
+
+
+def poll_serial():
+    global serial_buffer
+    while serial_poll.poll(0):
+        serial_buffer.append(sys.stdin.buffer.read(1)[0])
+    # dropping noise until start of a frame
+    while len(serial_buffer) != 0 and serial_buffer[0] != serial_request:
+        serial_buffer.pop(0)
+    if len(serial_buffer) < 4 or len(serial_buffer) < serial_buffer[3] + 5:
+        return
+    length = serial_buffer[3] + 5
+    frame = serial_buffer[1:length - 1]
+    crc = serial_buffer[length - 1]
+    serial_buffer = serial_buffer[length:]
+    if crc8(frame) == crc:
+        handle_frame(frame)
+
+
 try:
     while True:
         while responder.any():
@@ -57,33 +213,12 @@ try:
             # end of transmition
             content_action = content.copy()
             content = []
-            if (len(content_action) == 1):
-                counter = content_action[0]
-            if (len(content_action) == len(led_bit_value)):
-                # on the right amount of bytes we update pwm
-                led_bit_value = content_action.copy()
-                local_counter = 0
-                for i in leds:
-                    intensity = 0
-                    for o in [0, 1]:
-                        led_bit_value[(local_counter * 2) + o] &= 0xff
-                        if o == 0:
-                            intensity = led_bit_value[local_counter * 2] * 256
-                            continue
-                        intensity = intensity + led_bit_value[(local_counter * 2) + 1]
-                    local_counter += 1
-                    i.duty_u16(intensity & 0xffff)
+            handle_write(content_action)
 
         if responder.anyRead():
-            data_map = [peripheral_identifier, len(leds)]
-            data_map.extend(led_bit_value)
-            transmitted = 0x00
-            if len(data_map) > counter and type(data_map[counter]) != None:
-                transmitted = data_map[counter]
-            responder.put(transmitted & 0xff)
-            counter = counter + 1
-            if (counter > 255):
-                counter = 0
+            responder.put(read_register())
+
+        poll_serial()
 except KeyboardInterrupt:
     pass
 